@@ -0,0 +1,8 @@
+#!/bin/sh
+# real traces for the tests, needs an x86_64 linux host
+set -e
+
+cc -O2 -o /tmp/rebg-record ../tools/ptrace/record.c
+
+gcc -o memory-amd64 memory.c
+/tmp/rebg-record memory-amd64.trace ./memory-amd64
@@ -0,0 +1,225 @@
+//! Compares the `HistMem` implementations on made up traces shaped like what
+//! the programs in `bins/` do: a stack that is written and read over and
+//! over, a big memset, and a copy done 8 bytes at a time. Then on the stores
+//! of a real one, `bins/memory-amd64.trace`. Prints the time to build and
+//! query each, and the peak heap usage.
+//!
+//! cargo bench --bench histmem
+
+use rebg::mem::{byte, paged, qword};
+use rebg::tick::Tick;
+use rebg::tracer::parser::{get_next_message, Message};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(now, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// What all three have in common
+trait Mem {
+    const NAME: &'static str;
+    fn new() -> Self;
+    fn store64(&mut self, tick: Tick, adr: u64, val: u64);
+    fn load64(&self, tick: Tick, adr: u64) -> Option<u64>;
+    fn fill(&mut self, tick: Tick, adr: u64, bytes: &[u8]);
+}
+
+impl Mem for byte::HistMem {
+    const NAME: &'static str = "byte";
+
+    fn new() -> Self {
+        byte::HistMem::new()
+    }
+
+    fn store64(&mut self, tick: Tick, adr: u64, val: u64) {
+        byte::HistMem::store64(self, tick, adr, val).unwrap()
+    }
+
+    fn load64(&self, tick: Tick, adr: u64) -> Option<u64> {
+        byte::HistMem::load64(self, tick, adr)
+    }
+
+    fn fill(&mut self, tick: Tick, adr: u64, bytes: &[u8]) {
+        self.store_bytes(tick, adr, bytes).unwrap()
+    }
+}
+
+impl Mem for qword::HistMem {
+    const NAME: &'static str = "qword";
+
+    fn new() -> Self {
+        qword::HistMem::new()
+    }
+
+    fn store64(&mut self, tick: Tick, adr: u64, val: u64) {
+        qword::HistMem::store64(self, tick, adr, val).unwrap()
+    }
+
+    fn load64(&self, tick: Tick, adr: u64) -> Option<u64> {
+        qword::HistMem::load64(self, tick, adr)
+    }
+
+    fn fill(&mut self, tick: Tick, adr: u64, bytes: &[u8]) {
+        for (offset, chunk) in bytes.chunks_exact(8).enumerate() {
+            let val = u64::from_le_bytes(chunk.try_into().unwrap());
+            self.store64(tick, adr + offset as u64 * 8, val).unwrap();
+        }
+    }
+}
+
+impl Mem for paged::HistMem {
+    const NAME: &'static str = "paged";
+
+    fn new() -> Self {
+        paged::HistMem::new()
+    }
+
+    fn store64(&mut self, tick: Tick, adr: u64, val: u64) {
+        paged::HistMem::store64(self, tick, adr, val).unwrap()
+    }
+
+    fn load64(&self, tick: Tick, adr: u64) -> Option<u64> {
+        paged::HistMem::load64(self, tick, adr)
+    }
+
+    fn fill(&mut self, tick: Tick, adr: u64, bytes: &[u8]) {
+        self.store_bytes(tick, adr, bytes).unwrap()
+    }
+}
+
+const STACK: u64 = 0x7ffc_0000;
+const HEAP: u64 = 0x5555_0000;
+
+/// Pushes and pops in a 1KiB window, like a loop calling a small function
+fn stack<M: Mem>(mem: &mut M, ticks: u64) -> Vec<(Tick, u64)> {
+    let mut probes = Vec::new();
+    for step in 0..ticks {
+        let adr = STACK - 8 * (step * 7 % 128);
+        mem.store64(Tick(step).next(), adr, step);
+        if step % 3 == 0 {
+            probes.push((Tick(step), adr));
+        }
+    }
+    probes
+}
+
+/// One memset of 4MiB
+fn memset<M: Mem>(mem: &mut M, _ticks: u64) -> Vec<(Tick, u64)> {
+    mem.fill(Tick(1), HEAP, &vec![0x41; 4 << 20]);
+    (0..1 << 16).map(|i| (Tick(2), HEAP + i * 64)).collect()
+}
+
+/// `rep movsq` of 1MiB, one qword per step
+fn memcpy<M: Mem>(mem: &mut M, _ticks: u64) -> Vec<(Tick, u64)> {
+    for idx in 0..(1u64 << 17) {
+        mem.store64(Tick(idx + 1), HEAP + idx * 8, idx);
+    }
+    (0..1 << 16)
+        .map(|i| (Tick(i * 2 + 1), HEAP + i * 16))
+        .collect()
+}
+
+/// The stores of `bins/memory-amd64.trace`, over and over as if it was a
+/// loop in a longer program
+fn recorded<M: Mem>(mem: &mut M, ticks: u64) -> Vec<(Tick, u64)> {
+    let raw = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../bins/memory-amd64.trace"
+    ))
+    .unwrap();
+
+    // (step, adr, value), stores show up at the tick after their step
+    let mut stores = Vec::new();
+    let mut reader = &raw[..];
+    let mut step = 0;
+    while let Some(msg) = get_next_message(&mut reader) {
+        match msg.unwrap() {
+            Message::Separator => step += 1,
+            Message::Store(adr, val, 8) => stores.push((step, adr, val)),
+            _ => {}
+        }
+    }
+
+    let mut probes = Vec::new();
+    for round in 0..ticks / step {
+        for &(at, adr, val) in &stores {
+            let tick = Tick(round * step + at).next();
+            mem.store64(tick, adr, val);
+            probes.push((tick, adr));
+        }
+    }
+    probes
+}
+
+type Workload<M> = fn(&mut M, u64) -> Vec<(Tick, u64)>;
+
+fn run<M: Mem>(name: &str, workload: Workload<M>) {
+    CURRENT.store(0, Ordering::Relaxed);
+    PEAK.store(0, Ordering::Relaxed);
+
+    let start = Instant::now();
+    let mut mem = M::new();
+    let probes = workload(&mut mem, 1_000_000);
+    let build = start.elapsed();
+    let peak = PEAK.load(Ordering::Relaxed);
+
+    let start = Instant::now();
+    let mut found = 0;
+    for &(tick, adr) in &probes {
+        found += mem.load64(tick, adr).is_some() as usize;
+    }
+    let query = start.elapsed();
+
+    println!(
+        "{:8} {:6} build {:>9} query {:>9} ({} found) peak {:>8} KiB",
+        name,
+        M::NAME,
+        ms(build),
+        ms(query),
+        found,
+        peak >> 10
+    );
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn main() {
+    run::<byte::HistMem>("stack", stack);
+    run::<qword::HistMem>("stack", stack);
+    run::<paged::HistMem>("stack", stack);
+
+    run::<byte::HistMem>("memset", memset);
+    run::<qword::HistMem>("memset", memset);
+    run::<paged::HistMem>("memset", memset);
+
+    run::<byte::HistMem>("memcpy", memcpy);
+    run::<qword::HistMem>("memcpy", memcpy);
+    run::<paged::HistMem>("memcpy", memcpy);
+
+    run::<byte::HistMem>("recorded", recorded);
+    run::<qword::HistMem>("recorded", recorded);
+    run::<paged::HistMem>("recorded", recorded);
+}
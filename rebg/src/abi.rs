@@ -0,0 +1,92 @@
+use crate::{
+    arch::Arch,
+    dis::regs::{Aarch64Reg, Reg, X64Reg},
+};
+
+/// Where a function finds its integer arguments, and where it leaves the
+/// return value. Anything passed on the stack is not covered.
+#[derive(Clone, Copy, Debug)]
+pub struct CallingConvention {
+    pub name: &'static str,
+    pub args: &'static [Reg],
+    pub ret: Reg,
+}
+
+const SYSV_X64: CallingConvention = CallingConvention {
+    name: "sysv",
+    args: &[
+        Reg::X64Reg(X64Reg::Rdi),
+        Reg::X64Reg(X64Reg::Rsi),
+        Reg::X64Reg(X64Reg::Rdx),
+        Reg::X64Reg(X64Reg::Rcx),
+        Reg::X64Reg(X64Reg::R8),
+        Reg::X64Reg(X64Reg::R9),
+    ],
+    ret: Reg::X64Reg(X64Reg::Rax),
+};
+
+const AAPCS64: CallingConvention = CallingConvention {
+    name: "aapcs64",
+    args: &[
+        Reg::Aarch64Reg(Aarch64Reg::X0),
+        Reg::Aarch64Reg(Aarch64Reg::X1),
+        Reg::Aarch64Reg(Aarch64Reg::X2),
+        Reg::Aarch64Reg(Aarch64Reg::X3),
+        Reg::Aarch64Reg(Aarch64Reg::X4),
+        Reg::Aarch64Reg(Aarch64Reg::X5),
+        Reg::Aarch64Reg(Aarch64Reg::X6),
+        Reg::Aarch64Reg(Aarch64Reg::X7),
+    ],
+    ret: Reg::Aarch64Reg(Aarch64Reg::X0),
+};
+
+impl CallingConvention {
+    pub fn of(arch: Arch) -> Self {
+        match arch {
+            Arch::ARM64 => AAPCS64,
+            Arch::X86_64 => SYSV_X64,
+        }
+    }
+
+    /// Argument values, given the registers at the first instruction of the
+    /// callee
+    pub fn args(&self, regs: &[u64]) -> Vec<u64> {
+        self.args
+            .iter()
+            .map(|r| regs[r.idx().expect("argument register not traced")])
+            .collect()
+    }
+
+    /// Return value, given the registers right after returning
+    pub fn ret(&self, regs: &[u64]) -> u64 {
+        regs[self.ret.idx().expect("return register not traced")]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallingConvention;
+    use crate::arch::Arch;
+
+    #[test]
+    fn registers_are_traced() {
+        for (arch, n) in [(Arch::X86_64, 16), (Arch::ARM64, 32)] {
+            let cc = CallingConvention::of(arch);
+            let regs: Vec<u64> = (0..n).collect();
+
+            // would panic if any of them has no index
+            assert_eq!(cc.args(&regs).len(), cc.args.len());
+            assert!(cc.ret(&regs) < n);
+        }
+    }
+
+    #[test]
+    fn sysv_order() {
+        let cc = CallingConvention::of(Arch::X86_64);
+        let regs: Vec<u64> = (0..16).collect();
+
+        // rdi, rsi, rdx, rcx, r8, r9
+        assert_eq!(cc.args(&regs), vec![7, 6, 2, 1, 8, 9]);
+        assert_eq!(cc.ret(&regs), 0);
+    }
+}
@@ -0,0 +1,573 @@
+//! ELF core files of the traced process at a tick, for gdb and friends. We
+//! have registers for every thread that ran, memory as far as `HistMem` knows
+//! it, and the loaded modules from the symbol tables. Bytes we never saw are
+//! written as zeroes, pages we never saw are left out of the file.
+
+use super::{Analysis, Process};
+use crate::{
+    arch::Arch,
+    state::{State, Step},
+    tick::Tick,
+};
+use bitflags::Flags;
+use std::{fmt, ops::Range};
+
+const PAGE: u64 = 0x1000;
+
+const EHDR_SIZE: u64 = 64;
+const PHDR_SIZE: u64 = 56;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_FILE: u32 = 0x4649_4c45;
+const NT_ARM_TLS: u32 = 0x401;
+
+/// What a core looks like when it didn't crash, like stopping at a breakpoint
+const SIGTRAP: u64 = 5;
+
+struct Note {
+    name: &'static str,
+    kind: u32,
+    desc: Vec<u8>,
+}
+
+/// A PT_LOAD, `data` is empty for mappings we know nothing about
+struct Load {
+    range: Range<u64>,
+    flags: u32,
+    data: Vec<u8>,
+}
+
+/// Little endian writer, both targets are
+#[derive(Default)]
+struct Buf(Vec<u8>);
+
+impl Buf {
+    fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// `s` in a NUL padded field of `len` bytes, cut short if needed
+    fn str(&mut self, s: &str, len: usize) {
+        let bytes = &s.as_bytes()[..s.len().min(len - 1)];
+        self.0.extend_from_slice(bytes);
+        self.pad_to(self.0.len() + len - bytes.len());
+    }
+
+    fn pad_to(&mut self, len: usize) {
+        self.0.resize(len, 0);
+    }
+
+    fn align(&mut self, to: usize) {
+        self.pad_to(self.0.len().next_multiple_of(to));
+    }
+}
+
+/// Core file of the process running at `tick`, as it was right before the
+/// step at `tick`
+pub fn coredump<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    arch: Arch,
+    tick: Tick,
+) -> Vec<u8>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let process = &analysis.processes[analysis.process_of[tick.index()]];
+    let current = &analysis.trace[tick.index()];
+
+    let signal = analysis
+        .crash
+        .as_ref()
+        .filter(|c| c.tick == Some(tick))
+        .and_then(|c| c.signal)
+        .unwrap_or(SIGTRAP);
+
+    // the last step of every thread of this process up to here, the one
+    // that's running first so gdb picks it
+    let mut threads: Vec<(u64, &STEP)> = analysis
+        .threads
+        .iter()
+        .filter_map(|(&thread, ticks)| {
+            let ran = &ticks[..ticks.partition_point(|&t| t <= tick)];
+            let &last = ran.last()?;
+            let step = &analysis.trace[last.index()];
+            (step.pid() == current.pid()).then_some((thread, step))
+        })
+        .collect();
+    threads.sort_by_key(|&(thread, _)| thread != current.thread());
+
+    let mut notes = Vec::new();
+    for &(thread, step) in &threads {
+        let signal = if thread == current.thread() {
+            signal
+        } else {
+            0
+        };
+        notes.push(Note {
+            name: "CORE",
+            kind: NT_PRSTATUS,
+            desc: prstatus(arch, step.state(), signal, thread, process),
+        });
+    }
+    notes.push(Note {
+        name: "CORE",
+        kind: NT_PRPSINFO,
+        desc: prpsinfo(process),
+    });
+    notes.push(Note {
+        name: "CORE",
+        kind: NT_FILE,
+        desc: files(process, tick),
+    });
+    if let Arch::ARM64 = arch {
+        if let Some(&(_, tls)) = current
+            .state()
+            .bases()
+            .iter()
+            .find(|(n, _)| *n == "tpidr_el0")
+        {
+            notes.push(Note {
+                name: "LINUX",
+                kind: NT_ARM_TLS,
+                desc: tls.to_le_bytes().to_vec(),
+            });
+        }
+    }
+
+    let loads = loads(process, tick);
+    write(arch, &notes, &loads)
+}
+
+fn prstatus<const N: usize>(
+    arch: Arch,
+    state: &impl State<N>,
+    signal: u64,
+    thread: u64,
+    process: &Process,
+) -> Vec<u8> {
+    let mut buf = Buf::default();
+    // pr_info.si_signo, si_code, si_errno
+    buf.u32(signal as u32);
+    buf.pad_to(12);
+    // pr_cursig
+    buf.u16(signal as u16);
+    // pr_sigpend, pr_sighold
+    buf.pad_to(32);
+    buf.u32(thread as u32);
+    buf.u32(process.parent.unwrap_or(0) as u32);
+    // pr_pgrp, pr_sid
+    buf.u32(process.pid as u32);
+    buf.u32(process.pid as u32);
+    // the times
+    buf.pad_to(112);
+
+    let regs = state.regs();
+    let flags = state.flags().bits() as u64;
+    match arch {
+        Arch::X86_64 => {
+            let base = |name| {
+                state
+                    .bases()
+                    .into_iter()
+                    .find(|&(n, _)| n == name)
+                    .map_or(0, |(_, v)| v)
+            };
+            // struct user_regs_struct
+            for idx in [15, 14, 13, 12, 5, 3, 11, 10, 9, 8, 0, 1, 2, 6, 7] {
+                buf.u64(regs[idx]);
+            }
+            // orig_rax, not in a syscall
+            buf.u64(u64::MAX);
+            buf.u64(state.pc());
+            // cs
+            buf.u64(0x33);
+            buf.u64(flags);
+            buf.u64(regs[4]);
+            // ss
+            buf.u64(0x2b);
+            buf.u64(base("fs_base"));
+            buf.u64(base("gs_base"));
+            // ds, es, fs, gs
+            buf.pad_to(112 + 27 * 8);
+        }
+        Arch::ARM64 => {
+            // struct user_pt_regs, x0 to x30 and sp are in order
+            for &reg in regs {
+                buf.u64(reg);
+            }
+            buf.u64(state.pc());
+            buf.u64(flags);
+        }
+    }
+    // pr_fpvalid
+    buf.u32(0);
+    buf.align(8);
+    buf.0
+}
+
+fn prpsinfo(process: &Process) -> Vec<u8> {
+    let path = process.path.as_deref().unwrap_or_default();
+
+    let mut buf = Buf::default();
+    // pr_state, pr_sname
+    buf.0.extend_from_slice(&[0, b'R']);
+    // pr_zomb, pr_nice, pr_flag, pr_uid, pr_gid
+    buf.pad_to(24);
+    buf.u32(process.pid as u32);
+    buf.u32(process.parent.unwrap_or(0) as u32);
+    buf.u32(process.pid as u32);
+    buf.u32(process.pid as u32);
+    buf.str(path.rsplit('/').next().unwrap_or(path), 16);
+    // we don't know the arguments
+    buf.str(path, 80);
+    buf.0
+}
+
+/// Which file every mapping of a loaded module came from
+fn files(process: &Process, tick: Tick) -> Vec<u8> {
+    let mut entries = Vec::new();
+    for table in process.table.tables_at(tick) {
+        let Some((start, end)) = table.range else {
+            continue;
+        };
+        for region in process.maps.at(tick) {
+            let (from, to) = (region.start.max(start), region.end.min(end));
+            if from >= to {
+                continue;
+            }
+            // .bss has no file behind it. gdb wants whole pages, a segment
+            // that starts mid page has the file's page before it mapped too
+            if let Some(offset) = table.file_offset(from) {
+                let from = from - from % PAGE;
+                entries.push((from, to, offset / PAGE, table.binary_path.as_str()));
+            }
+        }
+    }
+
+    let mut buf = Buf::default();
+    buf.u64(entries.len() as u64);
+    buf.u64(PAGE);
+    for &(start, end, offset, _) in &entries {
+        buf.u64(start);
+        buf.u64(end);
+        buf.u64(offset);
+    }
+    for &(_, _, _, path) in &entries {
+        buf.0.extend_from_slice(path.as_bytes());
+        buf.0.push(0);
+    }
+    buf.0
+}
+
+/// Every mapping, split into what we know and what we don't
+fn loads(process: &Process, tick: Tick) -> Vec<Load> {
+    let mut loads = Vec::new();
+    for region in process.maps.at(tick) {
+        let perms = region.perms;
+        let flags = (perms.read as u32) << 2 | (perms.write as u32) << 1 | perms.exec as u32;
+
+        let mut at = region.start;
+        for run in process.mem.touched(region.start..region.end) {
+            let run = run.start.max(region.start)..run.end.min(region.end);
+            if at < run.start {
+                loads.push(Load {
+                    range: at..run.start,
+                    flags,
+                    data: Vec::new(),
+                });
+            }
+            let data = process
+                .mem
+                .load_range(tick, run.start, run.end - run.start)
+                .into_iter()
+                .map(|b| b.unwrap_or(0))
+                .collect();
+            at = run.end;
+            loads.push(Load {
+                range: run,
+                flags,
+                data,
+            });
+        }
+        if at < region.end {
+            loads.push(Load {
+                range: at..region.end,
+                flags,
+                data: Vec::new(),
+            });
+        }
+    }
+    loads
+}
+
+fn write(arch: Arch, notes: &[Note], loads: &[Load]) -> Vec<u8> {
+    let mut note = Buf::default();
+    for n in notes {
+        note.u32(n.name.len() as u32 + 1);
+        note.u32(n.desc.len() as u32);
+        note.u32(n.kind);
+        note.0.extend_from_slice(n.name.as_bytes());
+        note.0.push(0);
+        note.align(4);
+        note.0.extend_from_slice(&n.desc);
+        note.align(4);
+    }
+
+    let phnum = 1 + loads.len() as u64;
+    let note_offset = EHDR_SIZE + phnum * PHDR_SIZE;
+    let data_offset = (note_offset + note.0.len() as u64).next_multiple_of(PAGE);
+
+    let mut buf = Buf::default();
+    buf.0.extend_from_slice(b"\x7fELF");
+    // 64 bit, little endian, version 1, System V
+    buf.0.extend_from_slice(&[2, 1, 1, 0]);
+    buf.pad_to(16);
+    // ET_CORE
+    buf.u16(4);
+    buf.u16(match arch {
+        Arch::X86_64 => 62,
+        Arch::ARM64 => 183,
+    });
+    buf.u32(1);
+    // e_entry, e_phoff, e_shoff, e_flags
+    buf.u64(0);
+    buf.u64(EHDR_SIZE);
+    buf.u64(0);
+    buf.u32(0);
+    buf.u16(EHDR_SIZE as u16);
+    buf.u16(PHDR_SIZE as u16);
+    buf.u16(phnum as u16);
+    // e_shentsize, e_shnum, e_shstrndx
+    buf.u16(64);
+    buf.u16(0);
+    buf.u16(0);
+
+    let mut phdr = |kind, flags, offset, vaddr, filesz, memsz, align| {
+        buf.u32(kind);
+        buf.u32(flags);
+        buf.u64(offset);
+        buf.u64(vaddr);
+        // p_paddr
+        buf.u64(0);
+        buf.u64(filesz);
+        buf.u64(memsz);
+        buf.u64(align);
+    };
+    phdr(PT_NOTE, 0, note_offset, 0, note.0.len() as u64, 0, 4);
+    let mut offset = data_offset;
+    for load in loads {
+        let len = load.range.end - load.range.start;
+        phdr(
+            PT_LOAD,
+            load.flags,
+            offset,
+            load.range.start,
+            load.data.len() as u64,
+            len,
+            PAGE,
+        );
+        offset += load.data.len() as u64;
+    }
+
+    buf.0.extend_from_slice(&note.0);
+    buf.pad_to(data_offset as usize);
+    for load in loads {
+        buf.0.extend_from_slice(&load.data);
+    }
+    buf.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analyzer::testing,
+        state::X64Step,
+        tracer::parser::{Message, RegisterMessage},
+    };
+
+    /// The notes of a core, as `(kind, name, desc)`
+    fn notes(core: &[u8]) -> Vec<(u32, String, Vec<u8>)> {
+        let u32_at = |at: usize| u32::from_le_bytes(core[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(core[at..at + 8].try_into().unwrap());
+
+        // the first program header is the PT_NOTE
+        assert_eq!(u32_at(64), PT_NOTE);
+        let (mut at, len) = (u64_at(64 + 8) as usize, u64_at(64 + 32) as usize);
+        let end = at + len;
+
+        let mut notes = Vec::new();
+        while at < end {
+            let (namesz, descsz) = (u32_at(at) as usize, u32_at(at + 4) as usize);
+            let kind = u32_at(at + 8);
+            let name = &core[at + 12..at + 12 + namesz - 1];
+            let desc = at + 12 + namesz.next_multiple_of(4);
+            notes.push((
+                kind,
+                String::from_utf8(name.to_vec()).unwrap(),
+                core[desc..desc + descsz].to_vec(),
+            ));
+            at = desc + descsz.next_multiple_of(4);
+        }
+        notes
+    }
+
+    #[test]
+    fn notes_of_analysis() {
+        // every register tells which one it is
+        let regs: [u64; 16] = std::array::from_fn(|i| 0x1000 + i as u64);
+        let step = |pc: u64| {
+            [
+                Message::Address(pc),
+                Message::Code(vec![0x90].into()),
+                Message::Registers(RegisterMessage {
+                    pc,
+                    flags: 0x246,
+                    regs: regs.into(),
+                    bases: Box::new([]),
+                }),
+                Message::Separator,
+            ]
+            .iter()
+            .flat_map(testing::encode)
+            .collect::<Vec<_>>()
+        };
+
+        // a PIE, so the segments land where the libload says
+        let base = 0x5555_5555_4000;
+        let libload = [
+            Message::LibLoad(testing::bin("memory-amd64").into(), base, base + 0x5000),
+            Message::Separator,
+        ];
+        let mut raw: Vec<u8> = libload.iter().flat_map(testing::encode).collect();
+        raw.extend(step(base + 0x1139));
+        raw.extend(step(base + 0x113a));
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let core = coredump(&analysis, Arch::X86_64, Tick(1));
+        let notes = notes(&core);
+        let kinds: Vec<_> = notes
+            .iter()
+            .map(|(kind, name, _)| (*kind, name.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (NT_PRSTATUS, "CORE"),
+                (NT_PRPSINFO, "CORE"),
+                (NT_FILE, "CORE")
+            ]
+        );
+
+        // user_regs_struct comes after the 112 bytes of signal, ids and times
+        let prstatus = &notes[0].2;
+        let reg = |i: usize| u64::from_le_bytes(prstatus[112 + i * 8..][..8].try_into().unwrap());
+        assert_eq!(
+            u32::from_le_bytes(prstatus[..4].try_into().unwrap()),
+            SIGTRAP as u32
+        );
+        let names = [
+            "r15", "r14", "r13", "r12", "rbp", "rbx", "r11", "r10", "r9", "r8", "rax", "rcx",
+            "rdx", "rsi", "rdi",
+        ];
+        let order = [15, 14, 13, 12, 5, 3, 11, 10, 9, 8, 0, 1, 2, 6, 7];
+        for (i, (name, idx)) in names.iter().zip(order).enumerate() {
+            assert_eq!(reg(i), 0x1000 + idx, "{}", name);
+        }
+        // orig_rax, rip, cs, eflags, rsp
+        assert_eq!(reg(15), u64::MAX);
+        assert_eq!(reg(16), base + 0x113a);
+        assert_eq!(reg(18), 0x246);
+        assert_eq!(reg(19), 0x1004);
+        assert_eq!(prstatus.len(), 112 + 27 * 8 + 8);
+
+        // every mapping of the binary has a file
+        let file = &notes[2].2;
+        let u64_at = |at: usize| u64::from_le_bytes(file[at..at + 8].try_into().unwrap());
+        let count = u64_at(0) as usize;
+        assert!(count > 0);
+        assert_eq!(u64_at(8), PAGE);
+        let entries: Vec<_> = (0..count)
+            .map(|i| {
+                (
+                    u64_at(16 + i * 24),
+                    u64_at(24 + i * 24),
+                    u64_at(32 + i * 24),
+                )
+            })
+            .collect();
+        let paths: Vec<_> = file[16 + count * 24..]
+            .split(|&b| b == 0)
+            .take(count)
+            .map(|p| std::str::from_utf8(p).unwrap())
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (base, base + 0x1000, 0),
+                (base + 0x1000, base + 0x2000, 1),
+                (base + 0x2000, base + 0x3000, 2),
+                // .data is at 0x2dc8 in the file
+                (base + 0x3000, base + 0x5000, 2),
+            ]
+        );
+        let path = testing::bin("memory-amd64");
+        assert!(paths.iter().all(|&p| p == path));
+    }
+
+    #[test]
+    fn layout() {
+        let notes = [Note {
+            name: "CORE",
+            kind: NT_PRPSINFO,
+            desc: vec![1, 2, 3],
+        }];
+        let loads = [
+            Load {
+                range: 0x1000..0x2000,
+                flags: 5,
+                data: vec![0x41; 0x1000],
+            },
+            Load {
+                range: 0x2000..0x4000,
+                flags: 6,
+                data: Vec::new(),
+            },
+        ];
+        let core = write(Arch::X86_64, &notes, &loads);
+
+        let u16_at = |at: usize| u16::from_le_bytes(core[at..at + 2].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(core[at..at + 8].try_into().unwrap());
+        assert_eq!(&core[..4], b"\x7fELF");
+        assert_eq!(u16_at(16), 4);
+        assert_eq!(u16_at(56), 3);
+
+        // the note comes right after the headers, padded to 4 bytes
+        let note = 64 + 3 * 56;
+        assert_eq!(u64_at(64 + 8), note as u64);
+        assert_eq!(&core[note + 12..note + 16], b"CORE");
+        assert_eq!(u64_at(64 + 32), 12 + 8 + 4);
+
+        // data is page aligned, the unknown mapping takes no space
+        let load = 64 + 56;
+        assert_eq!(u64_at(load + 8), 0x1000);
+        assert_eq!(u64_at(load + 56 + 8), 0x2000);
+        assert_eq!(u64_at(load + 56 + 32), 0);
+        assert_eq!(u64_at(load + 56 + 40), 0x2000);
+        assert_eq!(core.len(), 0x2000);
+        assert_eq!(core[0x1000], 0x41);
+    }
+}
@@ -0,0 +1,236 @@
+use super::dump::SIGNAL_FRAME;
+use crate::{
+    signal,
+    state::{Instrumentation, MemoryOp, MemoryOpKind, State, Step},
+    syms::SymbolTable,
+    tick::Tick,
+};
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+/// How many instructions leading up to the crash we keep around
+const CONTEXT: usize = 16;
+
+/// What we know about how the program died
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    /// Fatal signal, if it was killed by one
+    pub signal: Option<u64>,
+    /// The trace stopped without the tracer telling us it was done
+    pub truncated: bool,
+    /// Last tick executed by the crashing thread
+    pub tick: Option<Tick>,
+    pub pc: Option<u64>,
+    /// (tick, op) of the last memory access done by the crashing thread
+    pub last_access: Option<(Tick, MemoryOp)>,
+    /// Return addresses, innermost last
+    pub backtrace: Vec<u64>,
+    /// (tick, pc, disassembly), oldest first
+    pub last_insns: Vec<(Tick, u64, String)>,
+}
+
+impl CrashReport {
+    /// Whether the tracer was killed by a signal. Inside docker that is
+    /// only reported as an exit code of 128 + signo, which a program can just
+    /// as well exit with, so there we rely on the tracer telling us.
+    pub fn fatal_signal(status: &ExitStatus) -> Option<u64> {
+        status.signal().map(|signo| signo as u64)
+    }
+
+    /// Report on the last steps of `ticks`, which should be the crashing
+    /// thread
+    pub fn new<STEP, const N: usize>(
+        signal: Option<u64>,
+        truncated: bool,
+        trace: &[STEP],
+        instrumentations: &[Instrumentation],
+        ticks: &[Tick],
+        backtrace: Vec<u64>,
+    ) -> Self
+    where
+        STEP: Step<N>,
+    {
+        let tick = ticks.last().copied();
+        let pc = tick.map(|t| trace[t.index()].state().pc());
+
+        let last_access = ticks
+            .iter()
+            .rev()
+            .find_map(|&t| trace[t.index()].memory_ops().last().map(|op| (t, *op)));
+
+        let last_insns = ticks[ticks.len().saturating_sub(CONTEXT)..]
+            .iter()
+            .map(|&t| {
+                (
+                    t,
+                    trace[t.index()].state().pc(),
+                    instrumentations[t.index()].disassembly.clone(),
+                )
+            })
+            .collect();
+
+        Self {
+            signal,
+            truncated,
+            tick,
+            pc,
+            last_access,
+            backtrace,
+            last_insns,
+        }
+    }
+
+    /// Nothing was traced at all
+    pub fn empty(signal: Option<u64>, truncated: bool) -> Self {
+        Self {
+            signal,
+            truncated,
+            tick: None,
+            pc: None,
+            last_access: None,
+            backtrace: Vec::new(),
+            last_insns: Vec::new(),
+        }
+    }
+
+    pub fn print(&self, table: Option<&SymbolTable>) {
+        let sym = |adr: u64| {
+            table
+                .and_then(|t| t.lookup(adr))
+                .map(|s| format!(" <{}>", s))
+                .unwrap_or_default()
+        };
+
+        let reason = match (self.signal, self.truncated) {
+            (Some(signo), _) => signal::name(signo)
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| format!("signal {}", signo)),
+            (None, true) => String::from("trace ended early"),
+            (None, false) => String::from("unknown reason"),
+        };
+
+        match (self.tick, self.pc) {
+            (Some(tick), Some(pc)) => {
+                println!("Crashed: {} at {:x}{} (tick {})", reason, pc, sym(pc), tick)
+            }
+            _ => println!("Crashed: {} before anything was traced", reason),
+        }
+
+        if let Some((tick, op)) = &self.last_access {
+            let kind = match op.kind {
+                MemoryOpKind::Read => "read",
+                MemoryOpKind::Write => "write",
+            };
+            println!(
+                "last memory access: {} {:x} = {:x} (tick {})",
+                kind,
+                op.address,
+                op.value.as_u64(),
+                tick
+            );
+        }
+
+        if let Some(pc) = self.pc {
+            println!("backtrace:");
+            println!("  #0 {:x}{}", pc, sym(pc));
+            for (i, adr) in self.backtrace.iter().rev().enumerate() {
+                if *adr == SIGNAL_FRAME {
+                    println!("  #{} <signal handler called>", i + 1);
+                } else {
+                    println!("  #{} {:x}{}", i + 1, adr, sym(*adr));
+                }
+            }
+        }
+
+        if !self.last_insns.is_empty() {
+            println!("last instructions:");
+            for (tick, pc, dis) in &self.last_insns {
+                println!("  {:6} {:x}{} {}", tick, pc, sym(*pc), dis);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CrashReport;
+    use crate::{
+        analyzer::{
+            testing::{self, x64_step as step},
+            Analysis,
+        },
+        arch::Arch,
+        state::X64Step,
+        tick::Tick,
+        tracer::parser::Message,
+    };
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    // mov rax, [rbx]
+    const LOAD: &[u8] = &[0x48, 0x8b, 0x03];
+
+    fn trace(end: &[Message], status: i32) -> Analysis<X64Step, 16> {
+        let mut raw = testing::libload();
+        raw.extend(step(0x401000, &[0x90], &[]));
+        raw.extend(step(0x401001, LOAD, &[Message::Load(0x1000, 0x41, 8)]));
+        raw.extend(step(0x401004, LOAD, &[]));
+        raw.extend(end.iter().flat_map(testing::encode));
+
+        testing::analyze(testing::parse(&raw[..], status), Arch::X86_64)
+    }
+
+    #[test]
+    fn wait_status() {
+        assert_eq!(
+            CrashReport::fatal_signal(&ExitStatus::from_raw(11)),
+            Some(11)
+        );
+        // exit(139)
+        assert_eq!(
+            CrashReport::fatal_signal(&ExitStatus::from_raw(139 << 8)),
+            None
+        );
+        assert_eq!(CrashReport::fatal_signal(&ExitStatus::from_raw(0)), None);
+    }
+
+    #[test]
+    fn exit_code() {
+        // looks like docker reporting a SIGINT, but it just exited
+        let analysis = trace(&[], 130 << 8);
+        assert!(analysis.crash.is_none());
+    }
+
+    #[test]
+    fn killed() {
+        // docker tells us it exited with 139, the tracer saw the signal
+        let analysis = trace(&[Message::Signal(11)], 139 << 8);
+        let crash = analysis.crash.unwrap();
+
+        assert_eq!(crash.signal, Some(11));
+        assert!(!crash.truncated);
+        assert_eq!(crash.tick, Some(Tick(2)));
+        assert_eq!(crash.pc, Some(0x401004));
+        assert_eq!(
+            crash.last_access.map(|(t, op)| (t, op.address)),
+            Some((Tick(1), 0x1000))
+        );
+        assert_eq!(
+            crash.last_insns.iter().map(|i| i.0).collect::<Vec<_>>(),
+            vec![Tick(0), Tick(1), Tick(2)]
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let mut raw = testing::libload();
+        raw.extend(step(0x401000, &[0x90], &[]));
+        raw.extend(step(0x401001, LOAD, &[Message::Load(0x1000, 0x41, 8)]));
+        // cut off in the middle of the load
+        raw.truncate(raw.len() - 5);
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+
+        let crash = testing::analyze(steps, Arch::X86_64).crash.unwrap();
+        assert_eq!(crash.signal, None);
+        assert!(crash.truncated);
+        assert_eq!(crash.tick, Some(Tick(0)));
+    }
+}
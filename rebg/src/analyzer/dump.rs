@@ -1,30 +1,57 @@
-use crate::analyzer::Analysis;
+use crate::abi::CallingConvention;
+use crate::analyzer::maps::{self, MemoryMap, Perms, STACK_SIZE};
+use crate::analyzer::{
+    coredump,
+    crash::CrashReport,
+    fds::FdTable,
+    heap, ltrace, taint, uninit,
+    watch::{AccessIndex, Watch},
+    Analysis, Invocation, Process,
+};
 use crate::binary::Binary;
 use crate::dis::{self, Dis, Instruction};
 use crate::mem::HistMem;
-use crate::state::{Branching, Instrument, MemoryValue};
+use crate::state::{Branching, Instrument, SignalEvent};
+use crate::tick::{self, Tick};
 use crate::{
     arch::Arch,
     host::Host,
-    rstate,
+    rstate, signal,
     state::{Instrumentation, MemoryOp, MemoryOpKind, State, Step},
     syms::SymbolTable,
+    syscall::{Arg, Syscall},
     tracer::ParsedStep,
 };
+use itertools::Itertools;
 use lazy_static::lazy_static;
-use object::Object;
+use object::{Object, ObjectSegment};
 use regex::Regex;
-use std::cell::RefCell;
 use std::rc::Rc;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 use tracing::{debug, trace as trace_log, warn};
 
+/// Pushed on the backtrace when a signal handler starts, so everything the
+/// handler calls can be dropped again on sigreturn
+pub(crate) const SIGNAL_FRAME: u64 = u64::MAX;
+
 /// Dumps the log
 pub struct TraceDumper {
     pub print: bool,
+    /// Where to write the library call log, if anywhere
+    pub ltrace: Option<PathBuf>,
+    /// Print heap misuse found in the trace
+    pub heap: bool,
+    /// Print reads of uninitialized stack and heap memory
+    pub uninit: bool,
+    /// Print the branches that depend on data from these sources
+    pub taint: Vec<taint::Source>,
+    /// Print every access to these address ranges
+    pub watch: Vec<Watch>,
+    /// Write core files of these ticks to `core.<tick>`
+    pub core: Vec<Tick>,
 }
 
 impl TraceDumper {
@@ -47,23 +74,36 @@ impl TraceDumper {
         let cs = Rc::new(arch.make_capstone().unwrap());
         let dis = Dis { cs, arch };
 
-        let offsets = match iter.next().unwrap() {
-            ParsedStep::LibLoad(x) => x,
-            ParsedStep::TraceStep(s) => panic!("Expected libload: {:#?}", s),
-            ParsedStep::Final(f) => {
-                let code = f.status.code();
-                if code == Some(139) {
-                    panic!("Segmentation fault");
-                } else {
-                    panic!("Expected libload: {:#?}", f);
-                }
+        let offsets = match iter.next() {
+            Some(ParsedStep::LibLoad(x)) => x,
+            Some(ParsedStep::TraceStep(s)) => panic!("Expected libload: {:#?}", s),
+            Some(ParsedStep::Killed(signo)) => {
+                let crash = CrashReport::empty(Some(signo), false);
+                crash.print(None);
+                return Analysis::empty(crash);
+            }
+            Some(ParsedStep::Final(f)) => {
+                // died before we got to trace anything
+                let crash = CrashReport::empty(CrashReport::fatal_signal(&f.status), false);
+                print_output(&f);
+                crash.print(None);
+                return Analysis::empty(crash);
+            }
+            Some(ParsedStep::Truncated) | None => {
+                let crash = CrashReport::empty(None, true);
+                crash.print(None);
+                return Analysis::empty(crash);
             }
         };
 
         // get symbol table from all binaries
         let mut symbol_tables = Vec::new();
+        let mut maps = MemoryMap::new();
+        let mut mem = HistMem::new();
         for (path, pie) in offsets {
             let binary = Binary::from_path(launcher, &PathBuf::from(path.clone())).unwrap();
+            maps.map_binary(&binary, pie.0, &path, Tick(0));
+            seed_binary(&mut mem, &binary, pie.0, Tick(0));
 
             let mut table = SymbolTable::from_elf(path.clone(), &binary);
 
@@ -78,7 +118,7 @@ impl TraceDumper {
                 }
             }
 
-            table = table.add_offset(pie.0);
+            table = table.add_offset(pie.0).loaded_at(pie, Tick(0));
 
             symbol_tables.push(table);
         }
@@ -92,10 +132,14 @@ impl TraceDumper {
             .unwrap();
 
         let mut trace = Vec::new();
+        let mut killed = None;
         let result = loop {
             let v = match iter.next() {
                 Some(v) => v,
-                None => panic!("prematurely closed"),
+                None => {
+                    warn!("Trace closed without a final message");
+                    break None;
+                }
             };
 
             match v {
@@ -103,20 +147,27 @@ impl TraceDumper {
                 ParsedStep::TraceStep(step) => {
                     trace.push(step);
                 }
+                ParsedStep::Killed(signo) => {
+                    killed = Some(signo);
+                }
+                ParsedStep::Truncated => {
+                    warn!("Trace ended part-way through a step");
+                    break None;
+                }
                 ParsedStep::Final(f) => {
                     // make sure it's done
                     match iter.next() {
                         None => (),
                         Some(_) => panic!("Got message after final"),
                     }
-                    break f;
+                    break Some(f);
                 }
             }
         };
 
-        let table = Rc::new(RefCell::new(table));
-        // analyzer will insert new symbols into table
-        let mut analyzer = RealAnalyzer::new(dis, arch, table.clone(), self.print);
+        // analyzer will insert new symbols into the table of the process
+        let mut analyzer = RealAnalyzer::new(dis, arch, self.print);
+        let mut root = Some((table, maps, mem));
 
         // we want changes to instantly show up in the UI, but we are also
         // dependent on the next step for some analysis, so we need to first
@@ -125,39 +176,119 @@ impl TraceDumper {
         let mut instrumentations = Vec::new();
         let mut insns = Vec::new();
 
-        let mut bt = Vec::new();
+        // threads interleave, so the backtrace and what "previous" means is
+        // kept per thread
+        let mut bts: HashMap<u64, Vec<u64>> = HashMap::new();
         let mut bt_lens = Vec::new();
+        let mut threads: BTreeMap<u64, Vec<Tick>> = BTreeMap::new();
+
+        // every call we've seen, and per thread which of them each frame of
+        // the backtrace belongs to (`None` for signal frames)
+        let mut invocations: Vec<Invocation> = Vec::new();
+        let mut frames: HashMap<u64, Vec<Option<usize>>> = HashMap::new();
+        let cc = CallingConvention::of(arch);
+
+        let mut processes: Vec<ProcessState> = Vec::new();
+        // pid -> index of the current image of that process
+        let mut current: HashMap<u64, usize> = HashMap::new();
+        let mut process_of: Vec<usize> = Vec::new();
+
+        for (tick, cur_step) in tick::enumerate(&trace) {
+            let thread = cur_step.thread();
+            let pid = cur_step.pid();
+
+            let proc_idx = *current.entry(pid).or_insert_with(|| {
+                let image = match root.take() {
+                    Some((table, mut maps, mem)) => {
+                        // we don't know how big it is, assume the default
+                        let sp = cur_step.state().sp();
+                        let top = maps::page_up(sp);
+                        let bottom = top.saturating_sub(STACK_SIZE);
+                        maps.map(bottom, top - bottom, Perms::RW, "[stack]".into(), tick);
+
+                        ProcessState::new(pid, table, maps, mem)
+                    }
+                    None => {
+                        // one we didn't see being forked, most likely by
+                        // whoever ran last, so it starts out as a copy of that
+                        let last = *process_of.last().expect("root process");
+                        let mut image = processes[last].fork(pid);
+                        image.parent = None;
+                        image
+                    }
+                };
 
-        let mut mem = HistMem::new();
-
-        for (tick, cur_step) in trace.iter().enumerate() {
-            let (insn, instrumentation) = analyzer.step(launcher, cur_step);
+                processes.push(image);
+                processes.len() - 1
+            });
+            process_of.push(proc_idx);
+
+            let process = &mut processes[proc_idx];
+            process.ticks.push(tick);
+
+            let (insn, instrumentation, event) = analyzer.step(
+                launcher,
+                cur_step,
+                &mut process.table,
+                &mut process.syscalls,
+                &mut process.mem,
+                tick,
+            );
             instrumentations.push(instrumentation);
             insns.push(insn);
-            let prev_instrumentation = instrumentations.iter().rev().nth(1);
-
-            let next_tick = (tick + 1) as u32;
 
-            // apply memory operations
-            for op in cur_step
-                .memory_ops()
-                .iter()
-                .filter(|m| matches!(m.kind, MemoryOpKind::Write))
-            {
-                match op.value {
-                    MemoryValue::Byte(b) => mem.store8(next_tick, op.address, b),
-                    MemoryValue::Word(w) => mem.store16(next_tick, op.address, w),
-                    MemoryValue::Dword(d) => mem.store32(next_tick, op.address, d),
-                    MemoryValue::Qword(q) => mem.store64(next_tick, op.address, q),
+            let thread_ticks = threads.entry(thread).or_default();
+            let prev_tick = thread_ticks.last().copied();
+            let prev_instrumentation = prev_tick.map(|t| &instrumentations[t.index()]);
+            thread_ticks.push(tick);
+
+            let bt = bts.entry(thread).or_default();
+            let frames = frames.entry(thread).or_default();
+
+            let next_tick = tick.next();
+
+            // apply memory operations, loads tell us what was there before
+            // the step, stores what's there after it. `HistMem` holds the
+            // bytes as they are in memory, so they line up with what the
+            // loader and the kernel put there.
+            for op in cur_step.memory_ops() {
+                let mem = &mut process.mem;
+                let bytes = op.value.bytes();
+                match op.kind {
+                    MemoryOpKind::Read => mem.observe_bytes(tick, op.address, &bytes),
+                    MemoryOpKind::Write => mem.store_bytes(next_tick, op.address, &bytes),
                 }
                 .unwrap();
             }
 
+            // the kernel writes show up as this step having written them
+            for write in cur_step.syscall_writes() {
+                process
+                    .mem
+                    .store_bytes(next_tick, write.address, &write.bytes)
+                    .unwrap();
+            }
+
+            // only meaningful if we just came back from a call
+            let ret_value = cc.ret(cur_step.state().regs());
+
             // do for the PREVIOUS branch
             match prev_instrumentation {
+                Some(Instrumentation {
+                    signal: Some(SignalEvent::Return),
+                    ..
+                }) => {
+                    // back where the signal interrupted us
+                    if let Some(idx) = bt.iter().rposition(|v| *v == SIGNAL_FRAME) {
+                        unwind(bt, frames, &mut invocations, idx, tick, false, ret_value);
+                        debug!(">>> {:3} Sigreturn", bt.len());
+                    } else {
+                        warn!("sigreturn without a signal frame at tick {}", tick);
+                    }
+                }
                 Some(Instrumentation {
                     branch: Some(prev_branch),
-                    disassembly: _,
+                    ..
                 }) => match prev_branch {
                     Branching::Call(target, return_address) => {
                         // 1. if we are at target, it's a normal call
@@ -173,16 +304,34 @@ impl TraceDumper {
                         } else {
                             bt.push(*return_address);
 
-                            let sym_txt = {
-                                let syms = analyzer.syms.borrow();
-                                let sym = syms.lookup(*target);
-                                if let Some(sym) = sym {
-                                    format!(" = <{}>", sym)
-                                } else {
-                                    String::new()
-                                }
+                            let sym = process.table.lookup(*target);
+                            let sym_txt = if let Some(sym) = &sym {
+                                format!(" = <{}>", sym)
+                            } else {
+                                String::new()
                             };
-                            debug!(">>> {:3} Calling {:x}{}", bt.len(), target, sym_txt);
+                            let args = cc.args(cur_step.state().regs());
+                            debug!(
+                                ">>> {:3} Calling {:x}{}({})",
+                                bt.len(),
+                                target,
+                                sym_txt,
+                                args.iter().map(|a| format!("{:#x}", a)).join(", ")
+                            );
+
+                            invocations.push(Invocation {
+                                callee: *target,
+                                symbol: sym.map(|s| s.symbol.name.clone()),
+                                thread,
+                                call: prev_tick.unwrap(),
+                                ret: None,
+                                caller: frames.iter().rev().flatten().next().copied(),
+                                depth: bt.len(),
+                                args,
+                                ret_value: None,
+                                observed: false,
+                            });
+                            frames.push(Some(invocations.len() - 1));
                         }
                     }
                     Branching::Return => {
@@ -190,12 +339,9 @@ impl TraceDumper {
                         let idx = bt.iter().position(|v| *v == cur_step.state().pc());
 
                         if let Some(idx) = idx {
-                            let removed: Vec<_> = bt.drain(idx..).collect();
-                            trace_log!(
-                                ">>> {:3} RETURN: removing {} elements",
-                                bt.len(),
-                                removed.len()
-                            );
+                            let removed =
+                                unwind(bt, frames, &mut invocations, idx, tick, true, ret_value);
+                            trace_log!(">>> {:3} RETURN: removing {} elements", bt.len(), removed);
                         } else {
                             trace_log!(">>> WARNING RETURN: could not find in backtrace!");
                         }
@@ -209,88 +355,352 @@ impl TraceDumper {
 
                     if let Some(idx) = idx {
                         // TODO also make sure sp changed, as a measure to reduce false positives
-                        drop(bt.drain(idx..));
+                        unwind(bt, frames, &mut invocations, idx, tick, false, ret_value);
                     }
                 }
             }
 
+            // the handler runs on top of whatever was interrupted
+            if let Some(signo) = cur_step.signal() {
+                bt.push(SIGNAL_FRAME);
+                frames.push(None);
+                debug!(
+                    ">>> {:3} Signal {}",
+                    bt.len(),
+                    signal::name(signo).unwrap_or("unknown")
+                );
+            }
+
             bt_lens.push(bt.len());
+
+            match event {
+                Some(ProcessEvent::Fork(child)) => {
+                    debug!("{} forked {}", pid, child);
+                    let image = processes[proc_idx].fork(child);
+                    processes.push(image);
+                    current.insert(child, processes.len() - 1);
+                }
+                Some(ProcessEvent::Exec(path)) => {
+                    debug!("{} exec'd {}", pid, path);
+                    let image = processes[proc_idx].exec(launcher, path, tick);
+                    processes.push(image);
+                    current.insert(pid, processes.len() - 1);
+                }
+                None => {}
+            }
         }
 
         // last instruction can be a RET now that we allow tracing only main part of program.
         // assert_eq!(instrumentations.last().and_then(|x| x.branch.clone()), None);
 
-        if !result.status.success() {
-            println!("Failed with code: {}", result.status);
-        }
-        if !result.stdout.is_empty() {
-            println!("stdout:\n{}", String::from_utf8(result.stdout).unwrap());
-        }
-        if !result.stderr.is_empty() {
-            println!("stderr:\n{}", String::from_utf8(result.stderr).unwrap());
+        if let Some(result) = &result {
+            print_output(result);
         }
 
+        // killed by a signal, or the tracer went away mid-trace
+        let signal = killed.or_else(|| {
+            result
+                .as_ref()
+                .and_then(|r| CrashReport::fatal_signal(&r.status))
+        });
+        let crash = if signal.is_some() || result.is_none() {
+            let thread = trace.last().map(|s| s.thread()).unwrap_or_default();
+            let crash = CrashReport::new(
+                signal,
+                result.is_none(),
+                &trace,
+                &instrumentations,
+                threads
+                    .get(&thread)
+                    .map(|t| t.as_slice())
+                    .unwrap_or_default(),
+                bts.remove(&thread).unwrap_or_default(),
+            );
+            crash.print(process_of.last().map(|&p| &processes[p].table));
+            Some(crash)
+        } else {
+            None
+        };
+
         assert_eq!(trace.len(), instrumentations.len());
         assert_eq!(trace.len(), bt_lens.len());
 
-        let table = table.borrow();
-        let table = table.clone();
+        let processes = processes.into_iter().map(ProcessState::finish).collect();
 
-        Analysis {
+        let accesses = AccessIndex::new(&trace, &process_of);
+
+        let analysis = Analysis {
             trace,
             insns,
             instrumentations,
             bt_lens,
-            table,
-            mem,
+            threads,
+            processes,
+            process_of,
+            invocations,
+            accesses,
+            crash,
+        };
+
+        if let Some(path) = &self.ltrace {
+            let log: String = ltrace::library_calls(&analysis)
+                .iter()
+                .map(|call| format!("{}\n", call))
+                .collect();
+            if let Err(e) = std::fs::write(path, log) {
+                warn!("Could not write ltrace log to {}: {}", path.display(), e);
+            }
         }
+
+        if self.heap {
+            let report = heap::heap(&analysis);
+            println!(
+                "{} allocations, {} heap issues",
+                report.allocations.len(),
+                report.issues.len()
+            );
+            for issue in &report.issues {
+                let chunk = &report.allocations[issue.allocation];
+                println!(
+                    "{} (chunk {:x}+{:x} allocated at {})",
+                    issue, chunk.address, chunk.size, chunk.allocated
+                );
+            }
+        }
+
+        if self.uninit {
+            let reads = uninit::uninit(&analysis);
+            println!("{} uninitialized reads", reads.len());
+            for read in &reads {
+                println!("{}", read);
+            }
+        }
+
+        if !self.taint.is_empty() {
+            let report = taint::taint(&analysis, arch, self.taint.clone(), None);
+            println!(
+                "{} tainted steps, {} tainted branches",
+                report.steps.len(),
+                report.branches.len()
+            );
+            for branch in &report.branches {
+                println!("{}", branch);
+            }
+        }
+
+        for watch in &self.watch {
+            let end = Tick(analysis.trace.len() as u64);
+            let ticks = watch.ticks.clone().unwrap_or(Tick(0)..end);
+            let Some(&process) = analysis.process_of.get(ticks.start.index()) else {
+                continue;
+            };
+            let accesses = &analysis.accesses;
+
+            println!("accesses to {}", watch);
+            let before = accesses.last_write(process, watch.address, watch.len, ticks.start);
+            for access in before.into_iter().chain(accesses.accesses(
+                process,
+                watch.address,
+                watch.len,
+                ticks,
+            )) {
+                let pc = analysis.trace[access.tick.index()].state().pc();
+                let location = analysis
+                    .process(access.tick)
+                    .and_then(|p| p.table.lookup_at(pc, access.tick))
+                    .map(|s| format!(" <{}>", s))
+                    .unwrap_or_default();
+                let value = access
+                    .value
+                    .map(|v| format!("{:x}", v))
+                    .unwrap_or_else(|| String::from("syscall"));
+                let kind = match access.kind {
+                    MemoryOpKind::Read => "->",
+                    MemoryOpKind::Write => "<-",
+                };
+
+                println!(
+                    "{:6} {:x}{} {:x} {} {}",
+                    access.tick, pc, location, access.address, kind, value
+                );
+            }
+        }
+
+        for &tick in &self.core {
+            if tick.index() >= analysis.trace.len() {
+                warn!(
+                    "No core file for tick {}, the trace has {} steps",
+                    tick,
+                    analysis.trace.len()
+                );
+                continue;
+            }
+            let core = coredump::coredump(&analysis, arch, tick);
+            let path = format!("core.{}", tick);
+            match std::fs::write(&path, &core) {
+                Ok(()) => println!("wrote {} ({} bytes)", path, core.len()),
+                Err(e) => warn!("Could not write core file to {}: {}", path, e),
+            }
+        }
+
+        analysis
     }
 }
 
-fn decompose_syscall(strace: &str) -> Option<(String, Vec<String>, String)> {
-    lazy_static! {
-        // kinda low effort, will fail if there is a string argument with a comma
-        static ref RE: Regex = Regex::new(r"(\w+)\(([^)]*)\) = (\w+)").unwrap();
+/// Pops the frames from `idx` and up, which returned to the caller at `tick`.
+/// Only the frame at `idx` is the one the return actually went through,
+/// anything above it was skipped over. Returns how many were popped.
+fn unwind(
+    bt: &mut Vec<u64>,
+    frames: &mut Vec<Option<usize>>,
+    invocations: &mut [Invocation],
+    idx: usize,
+    tick: Tick,
+    observed: bool,
+    ret_value: u64,
+) -> usize {
+    let removed = bt.len() - idx;
+    bt.truncate(idx);
+
+    for (i, frame) in frames.drain(idx..).enumerate() {
+        if let Some(frame) = frame {
+            let invocation = &mut invocations[frame];
+            invocation.ret = Some(tick);
+            invocation.observed = observed && i == 0;
+            invocation.ret_value = (i == 0).then_some(ret_value);
+        }
     }
 
-    let captures = RE.captures(strace);
+    removed
+}
 
-    let captures = captures.map(|c| {
-        c.iter()
-            .skip(1)
-            .flatten()
-            .map(|m| m.as_str())
-            .collect::<Vec<_>>()
-    })?;
+fn print_output(result: &std::process::Output) {
+    if !result.status.success() {
+        println!("Failed with code: {}", result.status);
+    }
+    if !result.stdout.is_empty() {
+        println!("stdout:\n{}", String::from_utf8_lossy(&result.stdout));
+    }
+    if !result.stderr.is_empty() {
+        println!("stderr:\n{}", String::from_utf8_lossy(&result.stderr));
+    }
+}
+
+/// What the loader copied from the file, at the addresses it ended up at.
+/// The zeroed rest of each segment (`.bss`) is left out.
+fn seed_binary(mem: &mut HistMem, binary: &Binary, base: u64, tick: Tick) {
+    for segment in binary.obj().segments() {
+        if let Ok(data) = segment.data() {
+            mem.observe_bytes(tick, base + segment.address(), data)
+                .unwrap();
+        }
+    }
+}
+
+/// A process while it's being analyzed
+struct ProcessState {
+    pid: u64,
+    parent: Option<u64>,
+    path: Option<String>,
+    ticks: Vec<Tick>,
+    table: SymbolTable,
+    mem: HistMem,
+    syscalls: SyscallState,
+}
+
+impl ProcessState {
+    fn new(pid: u64, table: SymbolTable, maps: MemoryMap, mem: HistMem) -> Self {
+        let mut syscalls = SyscallState::new();
+        syscalls.maps = maps;
+
+        Self {
+            pid,
+            parent: None,
+            path: None,
+            ticks: Vec::new(),
+            table,
+            mem,
+            syscalls,
+        }
+    }
 
-    let mut captures = captures.into_iter();
+    /// The child starts out as a copy of us
+    fn fork(&self, child: u64) -> Self {
+        Self {
+            pid: child,
+            parent: Some(self.pid),
+            path: self.path.clone(),
+            ticks: Vec::new(),
+            table: self.table.clone(),
+            mem: self.mem.clone(),
+            syscalls: self.syscalls.clone(),
+        }
+    }
 
-    let name = captures.next().expect("no name");
+    /// Same pid, but a fresh address space. Only the fds survive, minus the
+    /// close-on-exec ones.
+    fn exec<LAUNCHER>(&self, launcher: &LAUNCHER, path: String, tick: Tick) -> Self
+    where
+        LAUNCHER: Host,
+        <LAUNCHER as Host>::Error: std::fmt::Debug,
+    {
+        let mut syscalls = self.syscalls.clone();
+        syscalls.fds.exec(tick);
+        syscalls.maps = MemoryMap::new();
+        let mut mem = HistMem::new();
 
-    let arguments = captures.next().expect("no arguments group");
-    let arguments = arguments.split(',').map(|x| x.to_string()).collect();
+        // we don't know where it gets loaded, so this is only right for non-pie
+        let table = match Binary::from_path(launcher, Path::new(&path)) {
+            Ok(binary) => {
+                syscalls.maps.map_binary(&binary, 0, &path, tick);
+                seed_binary(&mut mem, &binary, 0, tick);
+                SymbolTable::from_elf(path.clone(), &binary)
+            }
+            Err(e) => {
+                warn!("Could not read exec'd binary {}: {:?}", path, e);
+                SymbolTable::empty(path.clone())
+            }
+        };
 
-    let ret = captures.next().expect("no return value").to_string();
+        Self {
+            pid: self.pid,
+            parent: self.parent,
+            path: Some(path),
+            ticks: Vec::new(),
+            table,
+            mem,
+            syscalls,
+        }
+    }
 
-    Some((name.to_string(), arguments, ret))
+    fn finish(self) -> Process {
+        Process {
+            pid: self.pid,
+            parent: self.parent,
+            path: self.path,
+            ticks: self.ticks,
+            table: self.table,
+            mem: self.mem,
+            fds: self.syscalls.fds,
+            maps: self.syscalls.maps,
+        }
+    }
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
 enum SyscallError {
     #[error("bad format")]
     BadFormat,
-    #[error("missing hex prefix")]
-    MissingHexPrefix,
-    #[error("unqoted string literal")]
-    UnqotedStringLiteral,
+    #[error("missing or malformed argument {0}")]
+    BadArgument(usize),
     #[error("unknown fd")]
     UnknownFd,
-    #[error("parse error: {0}")]
-    Parse(#[from] std::num::ParseIntError),
 }
 
+#[derive(Clone)]
 struct SyscallState {
-    fds: HashMap<i32, String>,
+    fds: FdTable,
+    maps: MemoryMap,
 }
 
 enum StateUpdate {
@@ -300,73 +710,193 @@ enum StateUpdate {
         offset: u64,
         size: u64,
     },
-    // todo, actually use this
     Munmap {
-        #[allow(dead_code)]
         addr: u64,
-        #[allow(dead_code)]
         size: u64,
     },
+    /// A new process, not a thread
+    Fork {
+        child: u64,
+    },
+    Exec {
+        path: String,
+    },
+    Sigreturn,
+}
+
+/// Things the analysis loop has to act on, since they change which process
+/// we're in
+enum ProcessEvent {
+    Fork(u64),
+    Exec(String),
 }
 
 impl SyscallState {
     fn new() -> Self {
         Self {
-            fds: HashMap::new(),
+            fds: FdTable::new(),
+            maps: MemoryMap::new(),
         }
     }
 
-    fn register(&mut self, raw: &str) -> Result<Option<StateUpdate>, SyscallError> {
-        let (name, args, ret) = decompose_syscall(raw).ok_or(SyscallError::BadFormat)?;
-
-        let combined = args.join(", ");
-        debug!("parsed: {}({}) -> {}", name, combined, ret);
-
-        match name.as_str() {
-            "openat" => {
-                let fd = ret.parse::<i32>()?;
-                let file = args[1]
-                    .strip_prefix('"')
-                    .ok_or(SyscallError::UnqotedStringLiteral)?
-                    .strip_suffix('"')
-                    .ok_or(SyscallError::UnqotedStringLiteral)?
-                    .to_string();
-                self.fds.insert(fd, file);
+    /// `tick` is when the syscall was made
+    fn register(
+        &mut self,
+        syscall: &Syscall,
+        tick: Tick,
+    ) -> Result<Option<StateUpdate>, SyscallError> {
+        let arg = |idx: usize| syscall.arg(idx).ok_or(SyscallError::BadArgument(idx));
+        let int = |idx: usize| arg(idx)?.as_i64().ok_or(SyscallError::BadArgument(idx));
+        let string = |idx: usize| {
+            arg(idx)?
+                .as_str()
+                .map(str::to_string)
+                .ok_or(SyscallError::BadArgument(idx))
+        };
+
+        // O_CLOEXEC and SOCK_CLOEXEC are the same bit on both arches
+        let cloexec = |idx: usize| {
+            syscall.arg(idx).is_some_and(|a| {
+                a.has_flag("O_CLOEXEC")
+                    || a.has_flag("SOCK_CLOEXEC")
+                    || a.as_u64().is_some_and(|v| v & 0x80000 != 0)
+            })
+        };
+        let new_fd = syscall.value().map(|fd| fd as i32);
+
+        debug!("parsed: {:?}", syscall);
+
+        match syscall.name.as_str() {
+            // never returns, so there is no result to look at
+            "sigreturn" | "rt_sigreturn" => Ok(Some(StateUpdate::Sigreturn)),
+            "open" | "openat" => {
+                let path_idx = if syscall.name == "open" { 0 } else { 1 };
+                let file = string(path_idx)?;
+                if let Some(fd) = new_fd {
+                    let cloexec = cloexec(path_idx + 1);
+                    self.fds.open(fd, file.clone(), Some(file), tick, cloexec);
+                }
                 Ok(None)
             }
             "close" => {
-                let fd = args[0].parse::<i32>()?;
-                let err = ret.parse::<i32>()?;
-                if err == 0 {
-                    self.fds.remove(&fd);
+                let fd = int(0)? as i32;
+                if syscall.value().is_some() {
+                    self.fds.close(fd, tick);
+                }
+                Ok(None)
+            }
+            "dup" => {
+                let old = int(0)? as i32;
+                if let Some(new) = new_fd {
+                    self.fds.dup(old, new, tick, false);
+                }
+                Ok(None)
+            }
+            "dup2" | "dup3" => {
+                let old = int(0)? as i32;
+                if let Some(new) = new_fd {
+                    let cloexec = syscall.name == "dup3" && cloexec(2);
+                    self.fds.dup(old, new, tick, cloexec);
+                }
+                Ok(None)
+            }
+            "fcntl" | "fcntl64" => {
+                let fd = int(0)? as i32;
+                let cmd = arg(1)?;
+                let is = |name: &str, num: u64| cmd.has_flag(name) || cmd.as_u64() == Some(num);
+
+                if is("F_DUPFD", 0) || is("F_DUPFD_CLOEXEC", 1030) {
+                    if let Some(new) = new_fd {
+                        self.fds.dup(fd, new, tick, is("F_DUPFD_CLOEXEC", 1030));
+                    }
+                } else if is("F_SETFD", 2) && syscall.value().is_some() {
+                    let flag = arg(2)?;
+                    let set =
+                        flag.has_flag("FD_CLOEXEC") || flag.as_u64().is_some_and(|v| v & 1 != 0);
+                    self.fds.set_cloexec(fd, set);
+                }
+                Ok(None)
+            }
+            "pipe" | "pipe2" => {
+                if syscall.value().is_none() {
+                    return Ok(None);
+                }
+
+                // only works if the tracer shows us the array, not just the pointer
+                let ends: Vec<i32> = match arg(0)? {
+                    Arg::Raw(raw) => raw
+                        .trim_matches(|c| c == '[' || c == ']')
+                        .split(',')
+                        .map(|fd| fd.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| SyscallError::BadArgument(0))?,
+                    _ => return Err(SyscallError::BadArgument(0)),
+                };
+                let [read, write] = ends[..] else {
+                    return Err(SyscallError::BadArgument(0));
+                };
+
+                let cloexec = syscall.name == "pipe2" && cloexec(1);
+                self.fds
+                    .open(read, format!("pipe [{}]", tick), None, tick, cloexec);
+                self.fds
+                    .open(write, format!("pipe [{}]", tick), None, tick, cloexec);
+                Ok(None)
+            }
+            "socket" => {
+                if let Some(fd) = new_fd {
+                    let description = format!("socket({}, {})", arg(0)?, arg(1)?);
+                    self.fds.open(fd, description, None, tick, cloexec(1));
+                }
+                Ok(None)
+            }
+            "connect" | "bind" => {
+                let fd = int(0)? as i32;
+                if syscall.value().is_some() {
+                    let verb = if syscall.name == "connect" {
+                        "connected to"
+                    } else {
+                        "bound to"
+                    };
+                    self.fds.describe(fd, &format!(" {} {}", verb, arg(1)?));
+                }
+                Ok(None)
+            }
+            "accept" | "accept4" => {
+                let fd = int(0)? as i32;
+                if let Some(new) = new_fd {
+                    let on = self
+                        .fds
+                        .get(fd)
+                        .map(|f| f.description.clone())
+                        .unwrap_or_else(|| format!("fd {}", fd));
+                    let cloexec = syscall.name == "accept4" && cloexec(3);
+                    self.fds
+                        .open(new, format!("accepted on {}", on), None, tick, cloexec);
                 }
                 Ok(None)
             }
             "mmap" => {
-                let len = args[1].parse::<u64>()?;
-                let fd = args[4].parse::<i32>()?;
-                let addr = u64::from_str_radix(
-                    ret.strip_prefix("0x")
-                        .ok_or(SyscallError::MissingHexPrefix)?,
-                    16,
-                )?;
-                let offset = if args[5] == "0" {
-                    0
-                } else {
-                    u64::from_str_radix(
-                        args[5]
-                            .strip_prefix("0x")
-                            .ok_or(SyscallError::MissingHexPrefix)?,
-                        16,
-                    )?
+                let len = int(1)? as u64;
+                let fd = int(4)? as i32;
+                let offset = int(5)? as u64;
+                let prot = Perms::from_prot(arg(2)?);
+                let addr = match syscall.value() {
+                    Some(addr) => addr,
+                    None => return Ok(None),
                 };
 
+                let file = self.fds.get(fd).filter(|_| fd != -1);
+                let region = match file {
+                    Some(f) => maps::label(f.path.as_ref().unwrap_or(&f.description)),
+                    None if fd == -1 => String::from("[anon]"),
+                    None => format!("fd {}", fd),
+                };
+                let path = file.and_then(|f| f.path.clone());
+                self.maps.map(addr, len, prot, region, tick);
+
                 if fd != -1 {
-                    let path = self
-                        .fds
-                        .get(&fd)
-                        .ok_or(SyscallError::UnknownFd)?
-                        .to_string();
+                    let path = path.ok_or(SyscallError::UnknownFd)?;
                     debug!("mmap {} {} {} {}", fd, path, offset, len);
 
                     Ok(Some(StateUpdate::Mmap {
@@ -380,20 +910,65 @@ impl SyscallState {
                 }
             }
             "munmap" => {
-                let addr = if args[0] == "NULL" {
-                    0
-                } else {
-                    u64::from_str_radix(
-                        args[0]
-                            .strip_prefix("0x")
-                            .ok_or(SyscallError::MissingHexPrefix)?,
-                        16,
-                    )?
-                };
-                let len = args[1].parse::<u64>().unwrap();
+                let addr = int(0)? as u64;
+                let len = int(1)? as u64;
 
+                if syscall.value().is_none() {
+                    return Ok(None);
+                }
+
+                self.maps.unmap(addr, len, tick);
                 Ok(Some(StateUpdate::Munmap { addr, size: len }))
             }
+            "mprotect" => {
+                let addr = int(0)? as u64;
+                let len = int(1)? as u64;
+                let prot = Perms::from_prot(arg(2)?);
+
+                if syscall.value().is_some() {
+                    self.maps.protect(addr, len, prot, tick);
+                }
+                Ok(None)
+            }
+            "mremap" => {
+                let old = int(0)? as u64;
+                let old_len = int(1)? as u64;
+                let new_len = int(2)? as u64;
+
+                if let Some(new) = syscall.value() {
+                    let (perms, label) = match self.maps.lookup(old, tick) {
+                        Some(r) => (r.perms, r.label.clone()),
+                        None => (Perms::RW, String::from("[anon]")),
+                    };
+                    self.maps.unmap(old, old_len, tick);
+                    self.maps.map(new, new_len, perms, label, tick);
+                }
+                Ok(None)
+            }
+            "brk" => {
+                if let Some(ret) = syscall.value() {
+                    self.maps.brk(ret, tick);
+                }
+                Ok(None)
+            }
+            "fork" | "vfork" | "clone" | "clone3" => {
+                let is_thread = syscall.args.iter().any(|a| a.has_flag("CLONE_THREAD"));
+
+                match syscall.value() {
+                    Some(child) if child > 0 && !is_thread => Ok(Some(StateUpdate::Fork { child })),
+                    _ => Ok(None),
+                }
+            }
+            "execve" | "execveat" => {
+                let path_idx = if syscall.name == "execve" { 0 } else { 1 };
+                let path = string(path_idx)?;
+
+                if syscall.value() == Some(0) {
+                    Ok(Some(StateUpdate::Exec { path }))
+                } else {
+                    Ok(None)
+                }
+            }
             _ => Ok(None),
         }
     }
@@ -403,11 +978,10 @@ struct RealAnalyzer<STEP, const N: usize>
 where
     STEP: Step<N>,
 {
-    hist: Vec<STEP::STATE>,
+    /// last state of each thread
+    hist: HashMap<u64, STEP::STATE>,
     dis: Dis,
-    syms: Rc<RefCell<SymbolTable>>,
     arch: Arch,
-    syscall_state: SyscallState,
     print: bool,
 }
 
@@ -415,23 +989,30 @@ impl<STEP, const N: usize> RealAnalyzer<STEP, N>
 where
     STEP: Step<N>,
 {
-    fn new(dis: Dis, arch: Arch, syms: Rc<RefCell<SymbolTable>>, print: bool) -> Self {
+    fn new(dis: Dis, arch: Arch, print: bool) -> Self {
         Self {
-            hist: Vec::new(),
+            hist: HashMap::new(),
             dis,
-            syms,
             arch,
-            syscall_state: SyscallState::new(),
             print,
         }
     }
 
-    fn step<LAUNCHER>(&mut self, launcher: &LAUNCHER, step: &STEP) -> (Instruction, Instrumentation)
+    /// `syms`, `syscall_state` and `mem` belong to the process executing `step`
+    fn step<LAUNCHER>(
+        &mut self,
+        launcher: &LAUNCHER,
+        step: &STEP,
+        syms: &mut SymbolTable,
+        syscall_state: &mut SyscallState,
+        mem: &mut HistMem,
+        tick: Tick,
+    ) -> (Instruction, Instrumentation, Option<ProcessEvent>)
     where
         LAUNCHER: Host,
         <LAUNCHER as Host>::Error: std::fmt::Debug,
     {
-        if let Some(previous) = self.hist.last() {
+        if let Some(previous) = self.hist.get(&step.thread()) {
             let current = step.state();
 
             if self.print {
@@ -444,9 +1025,8 @@ where
         let code = step.code();
 
         let insn = self.dis.disassemble_one(code, address).unwrap();
-        let op = inst_to_str(&insn, Some(&self.syms.borrow()));
+        let op = inst_to_str(&insn, Some(syms));
 
-        let syms = self.syms.borrow();
         let symbol = syms.lookup(address);
 
         let location = if let Some(ref symbol) = symbol {
@@ -456,8 +1036,6 @@ where
             format!("0x{:016x}", address)
         };
 
-        drop(syms);
-
         if self.print {
             println!("{}: {}", location, op);
         }
@@ -465,12 +1043,18 @@ where
         // EDIT: it seems like it happens when branching to somewhere doing a syscall. it results in two regs| messages, and the last one is the one that "counts"..., i guess where it jump to after the syscall is done or something...?
         assert_eq!(address, step.state().pc());
 
+        let mut event = None;
+        let mut signal = step.signal().map(SignalEvent::Delivered);
+
         if let Some(strace) = step.strace() {
             if self.print {
                 println!("syscall: {}", strace);
             }
 
-            let update = self.syscall_state.register(strace);
+            let update = step
+                .syscall()
+                .ok_or(SyscallError::BadFormat)
+                .and_then(|syscall| syscall_state.register(syscall, tick));
             match update {
                 Ok(Some(StateUpdate::Mmap {
                     path,
@@ -481,6 +1065,12 @@ where
                     let binary = Binary::from_path(launcher, Path::new(&path));
 
                     if let Ok(binary) = binary {
+                        // the file contents are there once the syscall is done
+                        let from = (offset as usize).min(binary.raw().len());
+                        let to = (offset + size).min(binary.raw().len() as u64) as usize;
+                        mem.observe_bytes(tick.next(), addr, &binary.raw()[from..to])
+                            .unwrap();
+
                         let mut new_symbol_table = SymbolTable::from_elf(path, &binary);
 
                         if binary.obj().symbols().next().is_none() {
@@ -502,14 +1092,24 @@ where
                             }
                         }
 
-                        // TODO size
-                        new_symbol_table = new_symbol_table.add_offset(addr);
+                        new_symbol_table = new_symbol_table.mapped(addr, size, offset, tick);
 
-                        self.syms.borrow_mut().push_table(new_symbol_table);
+                        // anything that was mapped there before is gone
+                        syms.unload(addr, addr + size, tick);
+                        syms.push_table(new_symbol_table);
                     }
                 }
-                Ok(Some(StateUpdate::Munmap { addr: _, size: _ })) => {
-                    // TODO remove the symbols
+                Ok(Some(StateUpdate::Munmap { addr, size })) => {
+                    syms.unload(addr, addr + size, tick);
+                }
+                Ok(Some(StateUpdate::Fork { child })) => {
+                    event = Some(ProcessEvent::Fork(child));
+                }
+                Ok(Some(StateUpdate::Exec { path })) => {
+                    event = Some(ProcessEvent::Exec(path));
+                }
+                Ok(Some(StateUpdate::Sigreturn)) => {
+                    signal = Some(SignalEvent::Return);
                 }
                 Ok(None) => {}
                 Err(e) => {
@@ -537,14 +1137,16 @@ where
             }
         }
 
-        self.hist.push(step.state().clone());
+        self.hist.insert(step.thread(), step.state().clone());
 
         (
             insn,
             Instrumentation {
                 branch,
                 disassembly: op,
+                signal,
             },
+            event,
         )
     }
 }
@@ -589,3 +1191,210 @@ fn inst_to_str(insn: &dis::Instruction, table: Option<&SymbolTable>) -> String {
 
     format!("{} {}", mn, op)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analyzer::testing::{self, x64_step as step},
+        state::X64Step,
+        tracer::parser::Message,
+    };
+
+    #[test]
+    fn signal_frames() {
+        const NOP: &[u8] = &[0x90];
+        const RET: &[u8] = &[0xc3];
+
+        let mut raw = testing::libload();
+
+        let steps = [
+            // main calls f
+            step(0x401000, &[0xe8, 0xfb, 0x00, 0x00, 0x00], &[]),
+            step(0x401100, NOP, &[]),
+            // interrupted, the handler calls g
+            step(0x401200, NOP, &[Message::Signal(10)]),
+            step(0x401201, &[0xe8, 0xfa, 0x00, 0x00, 0x00], &[]),
+            step(0x401300, RET, &[]),
+            step(
+                0x401206,
+                &[0x0f, 0x05],
+                &[Message::Syscall("rt_sigreturn(0x0)".into())],
+            ),
+            // back in f, which returns to main
+            step(0x401101, RET, &[]),
+            step(0x401005, NOP, &[]),
+        ];
+        raw.extend(steps.concat());
+
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let signals: Vec<_> = analysis.instrumentations.iter().map(|i| i.signal).collect();
+        assert_eq!(signals[2], Some(SignalEvent::Delivered(10)));
+        assert_eq!(signals[5], Some(SignalEvent::Return));
+        assert_eq!(signals.iter().flatten().count(), 2);
+
+        assert_eq!(analysis.bt_lens, vec![0, 1, 2, 2, 3, 2, 1, 0]);
+
+        let calls: Vec<_> = analysis
+            .invocations
+            .iter()
+            .map(|i| (i.callee, i.call, i.ret, i.caller))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                (0x401100, Tick(0), Some(Tick(7)), None),
+                (0x401300, Tick(3), Some(Tick(5)), Some(0))
+            ]
+        );
+        assert!(analysis.crash.is_none());
+    }
+
+    #[test]
+    fn threads() {
+        const NOP: &[u8] = &[0x90];
+        const RET: &[u8] = &[0xc3];
+        const CALL: &[u8] = &[0xe8, 0xfb, 0x00, 0x00, 0x00];
+
+        let on = |thread: u64, pc: u64, code: &[u8]| step(pc, code, &[Message::Thread(thread)]);
+
+        let mut raw = testing::libload();
+        let steps = [
+            // 1 calls f, 2 calls h, f calls g
+            on(1, 0x401000, CALL),
+            on(2, 0x402000, NOP),
+            on(1, 0x401100, CALL),
+            on(2, 0x402001, &[0xe8, 0xfa, 0x00, 0x00, 0x00]),
+            // g returns, the next step of 2 doesn't count as where it
+            // returned to
+            on(1, 0x401200, RET),
+            on(2, 0x402100, NOP),
+            on(1, 0x401105, NOP),
+            on(2, 0x402101, RET),
+            on(1, 0x401106, RET),
+            on(2, 0x402006, NOP),
+            on(1, 0x401005, NOP),
+        ];
+        raw.extend(steps.concat());
+
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let ticks = |ticks: &[u64]| ticks.iter().copied().map(Tick).collect::<Vec<_>>();
+        assert_eq!(
+            analysis.threads,
+            BTreeMap::from([
+                (1, ticks(&[0, 2, 4, 6, 8, 10])),
+                (2, ticks(&[1, 3, 5, 7, 9]))
+            ])
+        );
+
+        // each thread has its own backtrace, a call counts from the first
+        // step of the callee
+        assert_eq!(analysis.bt_lens, vec![0, 0, 1, 0, 2, 1, 1, 1, 1, 0, 0]);
+
+        let calls: Vec<_> = analysis
+            .invocations
+            .iter()
+            .map(|i| (i.callee, i.thread, i.call, i.ret, i.caller))
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                (0x401100, 1, Tick(0), Some(Tick(10)), None),
+                (0x401200, 1, Tick(2), Some(Tick(6)), Some(0)),
+                (0x402100, 2, Tick(3), Some(Tick(9)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn fork_and_exec() {
+        const SYSCALL: &[u8] = &[0x0f, 0x05];
+        const NOP: &[u8] = &[0x90];
+
+        let path = testing::bin("regs-arm64");
+        let exec = format!("execve(\"{}\", 0x0, 0x0) = 0x0", path);
+
+        let mut raw = testing::libload();
+        let steps = [
+            step(
+                0x401000,
+                SYSCALL,
+                &[
+                    Message::Process(100),
+                    Message::Syscall("fork() = 200".into()),
+                ],
+            ),
+            step(0x401002, NOP, &[Message::Process(100)]),
+            // the child replaces itself
+            step(
+                0x401000,
+                SYSCALL,
+                &[Message::Process(200), Message::Syscall(exec.into())],
+            ),
+            step(0x10000000, NOP, &[Message::Process(200)]),
+            step(0x401003, NOP, &[Message::Process(100)]),
+        ];
+        raw.extend(steps.concat());
+
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        assert_eq!(analysis.process_of, vec![0, 0, 1, 2, 0]);
+
+        let processes: Vec<_> = analysis
+            .processes
+            .iter()
+            .map(|p| (p.pid, p.parent, p.path.as_deref(), p.ticks.clone()))
+            .collect();
+        assert_eq!(
+            processes,
+            vec![
+                (100, None, None, vec![Tick(0), Tick(1), Tick(4)]),
+                (200, Some(100), None, vec![Tick(2)]),
+                (200, Some(100), Some(path.as_str()), vec![Tick(3)]),
+            ]
+        );
+
+        // the fork kept the parent's stack, the new image starts out empty
+        let labels = |idx: usize, tick| -> Vec<String> {
+            analysis.processes[idx]
+                .maps
+                .at(tick)
+                .iter()
+                .map(|r| r.label.clone())
+                .collect()
+        };
+        assert!(labels(1, Tick(2)).contains(&"[stack]".to_string()));
+        assert!(!labels(2, Tick(3)).contains(&"[stack]".to_string()));
+        assert_eq!(analysis.processes[2].table.binary_path, path);
+    }
+
+    #[test]
+    fn plt_calls() {
+        let steps = testing::recording::<X64Step, 16>("memory-amd64.trace");
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let called = |name| {
+            analysis
+                .invocations
+                .iter()
+                .filter(|i| i.is_call_to(name))
+                .collect::<Vec<_>>()
+        };
+
+        // the binary is PIE, and the recorder disables ASLR
+        let malloc = called("malloc");
+        assert_eq!(malloc.len(), 1);
+        assert_eq!(malloc[0].callee, 0x5555_5555_5030);
+        assert_eq!(malloc[0].args[0], 16);
+        assert!(malloc[0].ret.is_some());
+
+        assert_eq!(called("printf").len(), 6);
+        assert_eq!(called("memory_dyn").len(), 1);
+        assert!(analysis.crash.is_none());
+    }
+}
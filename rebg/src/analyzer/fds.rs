@@ -0,0 +1,170 @@
+use crate::tick::Tick;
+use std::collections::HashMap;
+
+/// One lifetime of an fd, from the tick it was opened until it was closed
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FileDescriptor {
+    pub fd: i32,
+    /// What it refers to, the path for files
+    pub description: String,
+    /// Set for regular files, so we can find mmap'd binaries
+    pub path: Option<String>,
+    pub opened: Tick,
+    pub closed: Option<Tick>,
+    pub cloexec: bool,
+}
+
+/// The fds of a single process, and every fd it ever had
+#[derive(Clone, Debug)]
+pub struct FdTable {
+    /// fd -> index into `timeline`
+    open: HashMap<i32, usize>,
+    pub timeline: Vec<FileDescriptor>,
+}
+
+impl FdTable {
+    /// Starts out with stdin, stdout and stderr
+    pub fn new() -> Self {
+        let mut table = Self {
+            open: HashMap::new(),
+            timeline: Vec::new(),
+        };
+
+        for (fd, name) in ["stdin", "stdout", "stderr"].into_iter().enumerate() {
+            table.open(fd as i32, name.to_string(), None, Tick(0), false);
+        }
+
+        table
+    }
+
+    /// The fd as it is right now
+    pub fn get(&self, fd: i32) -> Option<&FileDescriptor> {
+        self.open.get(&fd).map(|&idx| &self.timeline[idx])
+    }
+
+    /// What `fd` referred to at `tick`
+    pub fn at(&self, fd: i32, tick: Tick) -> Option<&FileDescriptor> {
+        self.timeline
+            .iter()
+            .rev()
+            .filter(|f| f.fd == fd && f.opened <= tick)
+            .find(|f| f.closed.is_none_or(|closed| tick < closed))
+    }
+
+    pub fn open(
+        &mut self,
+        fd: i32,
+        description: String,
+        path: Option<String>,
+        tick: Tick,
+        cloexec: bool,
+    ) {
+        // reusing an fd we never saw get closed
+        self.close(fd, tick);
+
+        self.timeline.push(FileDescriptor {
+            fd,
+            description,
+            path,
+            opened: tick,
+            closed: None,
+            cloexec,
+        });
+        self.open.insert(fd, self.timeline.len() - 1);
+    }
+
+    pub fn close(&mut self, fd: i32, tick: Tick) {
+        if let Some(idx) = self.open.remove(&fd) {
+            self.timeline[idx].closed = Some(tick);
+        }
+    }
+
+    /// `new` refers to the same thing as `old`, the close-on-exec flag is not
+    /// shared
+    pub fn dup(&mut self, old: i32, new: i32, tick: Tick, cloexec: bool) {
+        let (description, path) = match self.get(old) {
+            Some(f) => (f.description.clone(), f.path.clone()),
+            None => (format!("dup of unknown fd {}", old), None),
+        };
+
+        self.open(new, description, path, tick, cloexec);
+    }
+
+    pub fn set_cloexec(&mut self, fd: i32, cloexec: bool) {
+        if let Some(&idx) = self.open.get(&fd) {
+            self.timeline[idx].cloexec = cloexec;
+        }
+    }
+
+    /// Appends to the description, e.g. when a socket gets connected
+    pub fn describe(&mut self, fd: i32, extra: &str) {
+        if let Some(&idx) = self.open.get(&fd) {
+            self.timeline[idx].description.push_str(extra);
+        }
+    }
+
+    /// Closes everything marked close-on-exec
+    pub fn exec(&mut self, tick: Tick) {
+        let cloexec: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, &idx)| self.timeline[idx].cloexec)
+            .map(|(&fd, _)| fd)
+            .collect();
+
+        for fd in cloexec {
+            self.close(fd, tick);
+        }
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FdTable;
+    use crate::tick::Tick;
+
+    #[test]
+    fn lifetimes() {
+        let mut fds = FdTable::new();
+        fds.open(
+            3,
+            "/etc/passwd".into(),
+            Some("/etc/passwd".into()),
+            Tick(10),
+            true,
+        );
+        fds.dup(3, 1, Tick(20), false);
+        fds.close(3, Tick(30));
+        fds.open(3, "socket".into(), None, Tick(40), false);
+        fds.exec(Tick(50));
+
+        assert_eq!(fds.at(3, Tick(9)).map(|f| f.opened), None);
+        assert_eq!(fds.at(3, Tick(10)).unwrap().description, "/etc/passwd");
+        assert_eq!(fds.at(3, Tick(35)).map(|f| f.opened), None);
+        assert_eq!(fds.at(3, Tick(45)).unwrap().description, "socket");
+
+        assert_eq!(fds.at(1, Tick(19)).unwrap().description, "stdout");
+        assert_eq!(fds.at(1, Tick(20)).unwrap().description, "/etc/passwd");
+        // dup'd fd doesn't inherit close-on-exec
+        assert!(fds.get(1).is_some());
+    }
+
+    #[test]
+    fn cloexec() {
+        let mut fds = FdTable::new();
+        fds.open(3, "a".into(), None, Tick(1), true);
+        fds.open(4, "b".into(), None, Tick(2), false);
+        fds.set_cloexec(4, true);
+        fds.exec(Tick(5));
+
+        assert!(fds.get(3).is_none());
+        assert!(fds.get(4).is_none());
+        assert_eq!(fds.at(4, Tick(4)).unwrap().closed, Some(Tick(5)));
+    }
+}
@@ -0,0 +1,564 @@
+use super::{Analysis, Invocation};
+use crate::{
+    state::{MemoryOpKind, State, Step},
+    tick::{self, Tick},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// Accesses this close to a chunk are blamed on it
+const REDZONE: u64 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HeapFn {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+/// `__libc_malloc`, `__GI___libc_free` etc. are the same thing
+fn heap_fn(symbol: &str) -> Option<HeapFn> {
+    let name = symbol.split('@').next()?;
+    let name = name.trim_start_matches('_');
+    let name = name
+        .strip_prefix("GI_")
+        .unwrap_or(name)
+        .trim_start_matches('_');
+    let name = name.strip_prefix("libc_").unwrap_or(name);
+
+    match name {
+        "malloc" => Some(HeapFn::Malloc),
+        "calloc" => Some(HeapFn::Calloc),
+        "realloc" => Some(HeapFn::Realloc),
+        "free" | "cfree" => Some(HeapFn::Free),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Allocation {
+    pub address: u64,
+    pub size: u64,
+    /// tick malloc returned
+    pub allocated: Tick,
+    /// tick free was called
+    pub freed: Option<Tick>,
+    /// leading bytes that had defined contents right away, all of them for
+    /// calloc and what was kept from the old chunk for realloc
+    pub initialized: u64,
+    /// index into `Analysis::processes`
+    pub process: usize,
+}
+
+impl Allocation {
+    pub fn end(&self) -> u64 {
+        self.address + self.size
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    UseAfterFree,
+    DoubleFree,
+    OutOfBounds,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct HeapIssue {
+    pub kind: IssueKind,
+    pub tick: Tick,
+    pub pc: u64,
+    pub location: Option<String>,
+    pub address: u64,
+    /// `None` for double frees
+    pub access: Option<MemoryOpKind>,
+    /// index into `HeapReport::allocations`
+    pub allocation: usize,
+}
+
+impl fmt::Display for HeapIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match (self.kind, self.access) {
+            (IssueKind::DoubleFree, _) => "double free of",
+            (IssueKind::UseAfterFree, Some(MemoryOpKind::Write)) => "write after free to",
+            (IssueKind::UseAfterFree, _) => "read after free from",
+            (IssueKind::OutOfBounds, Some(MemoryOpKind::Write)) => "out of bounds write to",
+            (IssueKind::OutOfBounds, _) => "out of bounds read from",
+        };
+
+        write!(f, "{:6} {} {:x}", self.tick, what, self.address)?;
+        match &self.location {
+            Some(location) => write!(f, " at {:x} <{}>", self.pc, location),
+            None => write!(f, " at {:x}", self.pc),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct HeapReport {
+    pub allocations: Vec<Allocation>,
+    pub issues: Vec<HeapIssue>,
+}
+
+enum Event {
+    Alloc {
+        address: u64,
+        size: u64,
+        zeroed: bool,
+        /// the old chunk, for realloc
+        from: Option<u64>,
+    },
+    Free {
+        address: u64,
+    },
+}
+
+/// Live and freed chunks of one process
+#[derive(Default)]
+struct Heap {
+    live: BTreeMap<u64, usize>,
+    freed: BTreeMap<u64, usize>,
+}
+
+impl Heap {
+    /// The chunk in `chunks` closest below `adr`
+    fn below(chunks: &BTreeMap<u64, usize>, adr: u64) -> Option<usize> {
+        chunks.range(..=adr).next_back().map(|(_, &idx)| idx)
+    }
+
+    /// The chunk in `chunks` closest above `adr`
+    fn above(chunks: &BTreeMap<u64, usize>, adr: u64) -> Option<usize> {
+        chunks.range(adr + 1..).next().map(|(_, &idx)| idx)
+    }
+}
+
+/// Finds every malloc/calloc/realloc/free, and checks all memory accesses
+/// made outside of them against the chunks. Nested calls (calloc calling
+/// malloc) only count once.
+pub fn heap<STEP, const N: usize>(analysis: &Analysis<STEP, N>) -> HeapReport
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let mut report = HeapReport::default();
+    let mut events: BTreeMap<Tick, Vec<Event>> = BTreeMap::new();
+
+    // ticks spent inside the allocator shouldn't be checked, it's allowed to
+    // touch the chunk headers
+    let mut inside: HashMap<u64, Vec<(Tick, Tick)>> = HashMap::new();
+    let mut listed = vec![false; analysis.invocations.len()];
+
+    for (idx, invocation) in analysis.invocations.iter().enumerate() {
+        let Some(func) = invocation.symbol.as_deref().and_then(heap_fn) else {
+            continue;
+        };
+
+        let nested = std::iter::successors(invocation.caller, |&c| analysis.invocations[c].caller)
+            .any(|c| listed[c]);
+        if nested {
+            continue;
+        }
+        listed[idx] = true;
+
+        let end = invocation.ret.unwrap_or(Tick::MAX);
+        inside
+            .entry(invocation.thread)
+            .or_default()
+            .push((invocation.call, end));
+
+        collect(func, invocation, &mut events);
+    }
+
+    // sorted and without overlaps, so the only range a tick can be in is the
+    // last one that starts before it
+    for ranges in inside.values_mut() {
+        ranges.sort();
+        ranges.dedup_by(|next, prev| {
+            let overlaps = next.0 < prev.1;
+            if overlaps {
+                prev.1 = prev.1.max(next.1);
+            }
+            overlaps
+        });
+    }
+
+    let mut heaps: HashMap<usize, Heap> = HashMap::new();
+
+    for (tick, step) in tick::enumerate(&analysis.trace) {
+        let process = analysis.process_of[tick.index()];
+        let heap = heaps.entry(process).or_default();
+
+        for event in events.remove(&tick).unwrap_or_default() {
+            match event {
+                Event::Alloc {
+                    address,
+                    size,
+                    zeroed,
+                    from,
+                } => {
+                    let initialized = match from.and_then(|old| heap.freed.get(&old)) {
+                        _ if zeroed => size,
+                        Some(&old) => report.allocations[old].size.min(size),
+                        None => 0,
+                    };
+
+                    // the memory is reused, old frees there don't matter anymore
+                    heap.freed.retain(|_, idx| {
+                        let old = &report.allocations[*idx];
+                        old.address + old.size.max(1) <= address
+                            || address + size.max(1) <= old.address
+                    });
+
+                    heap.live.insert(address, report.allocations.len());
+                    report.allocations.push(Allocation {
+                        address,
+                        size,
+                        allocated: tick,
+                        freed: None,
+                        initialized,
+                        process,
+                    });
+                }
+                Event::Free { address } => {
+                    if let Some(idx) = heap.live.remove(&address) {
+                        report.allocations[idx].freed = Some(tick);
+                        heap.freed.insert(address, idx);
+                    } else if let Some(&idx) = heap.freed.get(&address) {
+                        report.issues.push(issue(
+                            analysis,
+                            IssueKind::DoubleFree,
+                            tick,
+                            address,
+                            None,
+                            idx,
+                        ));
+                    }
+                }
+            }
+        }
+
+        let internal = inside.get(&step.thread()).is_some_and(|ranges| {
+            let after = ranges.partition_point(|&(from, _)| from < tick);
+            after > 0 && tick < ranges[after - 1].1
+        });
+        if internal {
+            continue;
+        }
+
+        for op in step.memory_ops() {
+            let (start, end) = (op.address, op.address + op.value.size());
+
+            if let Some(idx) = Heap::below(&heap.live, start) {
+                let chunk = &report.allocations[idx];
+                let chunk_end = chunk.end();
+
+                if start < chunk_end {
+                    if end > chunk_end {
+                        // starts inside, but goes past the end
+                        report.issues.push(issue(
+                            analysis,
+                            IssueKind::OutOfBounds,
+                            tick,
+                            op.address,
+                            Some(op.kind),
+                            idx,
+                        ));
+                    }
+                    continue;
+                }
+
+                if start < chunk_end + REDZONE {
+                    report.issues.push(issue(
+                        analysis,
+                        IssueKind::OutOfBounds,
+                        tick,
+                        op.address,
+                        Some(op.kind),
+                        idx,
+                    ));
+                    continue;
+                }
+            }
+
+            // underflows into the chunk header
+            if let Some(idx) = Heap::above(&heap.live, start) {
+                if report.allocations[idx].address < end + REDZONE {
+                    report.issues.push(issue(
+                        analysis,
+                        IssueKind::OutOfBounds,
+                        tick,
+                        op.address,
+                        Some(op.kind),
+                        idx,
+                    ));
+                    continue;
+                }
+            }
+
+            if let Some(idx) = Heap::below(&heap.freed, start) {
+                let chunk = &report.allocations[idx];
+                if start < chunk.end() {
+                    report.issues.push(issue(
+                        analysis,
+                        IssueKind::UseAfterFree,
+                        tick,
+                        op.address,
+                        Some(op.kind),
+                        idx,
+                    ));
+                }
+            }
+        }
+    }
+
+    report
+}
+
+fn collect(func: HeapFn, invocation: &Invocation, events: &mut BTreeMap<Tick, Vec<Event>>) {
+    let arg = |i: usize| invocation.args.get(i).copied().unwrap_or_default();
+
+    // the chunk is ours once the call returns
+    let mut alloc = |size: u64, zeroed: bool, from: Option<u64>| {
+        if let (Some(ret), Some(address)) = (invocation.ret, invocation.ret_value) {
+            if address != 0 {
+                events.entry(ret).or_default().push(Event::Alloc {
+                    address,
+                    size,
+                    zeroed,
+                    from,
+                });
+            }
+        }
+    };
+
+    match func {
+        HeapFn::Malloc => alloc(arg(0), false, None),
+        HeapFn::Calloc => alloc(arg(0).saturating_mul(arg(1)), true, None),
+        HeapFn::Realloc => {
+            alloc(arg(1), false, Some(arg(0)).filter(|&old| old != 0));
+            if arg(0) != 0 {
+                events
+                    .entry(invocation.call)
+                    .or_default()
+                    .push(Event::Free { address: arg(0) });
+            }
+        }
+        HeapFn::Free => {
+            if arg(0) != 0 {
+                events
+                    .entry(invocation.call)
+                    .or_default()
+                    .push(Event::Free { address: arg(0) });
+            }
+        }
+    }
+}
+
+fn issue<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    kind: IssueKind,
+    tick: Tick,
+    address: u64,
+    access: Option<MemoryOpKind>,
+    allocation: usize,
+) -> HeapIssue
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let pc = analysis.trace[tick.index()].state().pc();
+    let location = analysis
+        .process(tick)
+        .and_then(|p| p.table.lookup_at(pc, tick))
+        .map(|s| s.to_string());
+
+    HeapIssue {
+        kind,
+        tick,
+        pc,
+        location,
+        address,
+        access,
+        allocation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{heap, heap_fn, HeapFn, HeapReport, IssueKind};
+    use crate::{
+        analyzer::testing,
+        arch::Arch,
+        state::{MemoryOpKind, X64Step},
+        tick::Tick,
+        tracer::parser::Message,
+    };
+
+    /// A made up trace with a memory access per step, and the heap calls put
+    /// in by hand as `(name, call, ret, args, ret_value)`
+    fn report(accesses: &[Option<Message>], calls: &[(&str, u64, u64, &[u64], u64)]) -> HeapReport {
+        let mut raw = testing::libload();
+        for (i, access) in accesses.iter().enumerate() {
+            let extra: Vec<_> = access.iter().cloned().collect();
+            raw.extend(testing::x64_step(0x401000 + i as u64, &[0x90], &extra));
+        }
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let mut analysis = testing::analyze(steps, Arch::X86_64);
+
+        analysis.invocations = calls
+            .iter()
+            .map(|&(name, call, ret, args, ret_value)| {
+                testing::invocation(name, call, ret, args, ret_value)
+            })
+            .collect();
+        heap(&analysis)
+    }
+
+    fn issues(report: &HeapReport) -> Vec<(IssueKind, Tick, u64, Option<MemoryOpKind>, usize)> {
+        report
+            .issues
+            .iter()
+            .map(|i| (i.kind, i.tick, i.address, i.access, i.allocation))
+            .collect()
+    }
+
+    #[test]
+    fn misuse() {
+        let load = |adr| Some(Message::Load(adr, 0, 8));
+        let store = |adr| Some(Message::Store(adr, 0, 8));
+        let report = report(
+            &[
+                None,
+                None,
+                None,
+                // past the end, and into the header of the chunk
+                store(0x500c),
+                load(0x4ff8),
+                load(0x5000),
+                None,
+                // free is allowed to write into the chunk it frees
+                store(0x5000),
+                None,
+                load(0x5008),
+                None,
+                None,
+            ],
+            &[
+                ("malloc", 0, 2, &[16], 0x5000),
+                ("free", 6, 8, &[0x5000], 0),
+                ("__GI___libc_free", 10, 11, &[0x5000], 0),
+            ],
+        );
+
+        let chunk = &report.allocations[0];
+        assert_eq!((chunk.address, chunk.size), (0x5000, 16));
+        assert_eq!((chunk.allocated, chunk.freed), (Tick(2), Some(Tick(6))));
+        assert_eq!(
+            issues(&report),
+            vec![
+                (
+                    IssueKind::OutOfBounds,
+                    Tick(3),
+                    0x500c,
+                    Some(MemoryOpKind::Write),
+                    0
+                ),
+                (
+                    IssueKind::OutOfBounds,
+                    Tick(4),
+                    0x4ff8,
+                    Some(MemoryOpKind::Read),
+                    0
+                ),
+                (
+                    IssueKind::UseAfterFree,
+                    Tick(9),
+                    0x5008,
+                    Some(MemoryOpKind::Read),
+                    0
+                ),
+                (IssueKind::DoubleFree, Tick(10), 0x5000, None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn realloc() {
+        let store = |adr| Some(Message::Store(adr, 0, 8));
+        let report = report(
+            &[
+                None,
+                None,
+                None,
+                None,
+                store(0x6000),
+                store(0x7018),
+                None,
+                None,
+            ],
+            &[
+                ("malloc", 0, 1, &[8], 0x6000),
+                // moved to a bigger chunk, the old one is freed
+                ("realloc", 2, 3, &[0x6000, 32], 0x7000),
+                ("calloc", 6, 7, &[4, 8], 0x8000),
+            ],
+        );
+
+        let chunks: Vec<_> = report
+            .allocations
+            .iter()
+            .map(|a| (a.address, a.size, a.allocated, a.freed, a.initialized))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (0x6000, 8, Tick(1), Some(Tick(2)), 0),
+                // what was in the old chunk came along
+                (0x7000, 32, Tick(3), None, 8),
+                (0x8000, 32, Tick(7), None, 32),
+            ]
+        );
+        assert_eq!(
+            issues(&report),
+            vec![(
+                IssueKind::UseAfterFree,
+                Tick(4),
+                0x6000,
+                Some(MemoryOpKind::Write),
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(heap_fn("malloc"), Some(HeapFn::Malloc));
+        assert_eq!(heap_fn("__libc_malloc"), Some(HeapFn::Malloc));
+        assert_eq!(heap_fn("__GI___libc_free"), Some(HeapFn::Free));
+        assert_eq!(heap_fn("realloc@plt"), Some(HeapFn::Realloc));
+        assert_eq!(heap_fn("malloc_consolidate"), None);
+    }
+
+    #[test]
+    fn recorded() {
+        let steps = testing::recording::<X64Step, 16>("memory-amd64.trace");
+        let analysis = testing::analyze(steps, Arch::X86_64);
+        let report = heap(&analysis);
+
+        // memory_dyn's malloc(sizeof(int) * 4), called through the PLT
+        let malloc = analysis
+            .invocations
+            .iter()
+            .find(|i| i.is_call_to("malloc"))
+            .unwrap();
+        assert_eq!(report.allocations.len(), 1);
+        let chunk = &report.allocations[0];
+        assert_eq!((chunk.address, chunk.size), (0x5555_5555_a2b0, 16));
+        assert_eq!(Some(chunk.allocated), malloc.ret);
+        assert_eq!(chunk.freed, None);
+        // the recording has no heap accesses to check
+        assert!(report.issues.is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+use super::{Analysis, Invocation};
+use crate::{mem::HistMem, state::Step, tick::Tick};
+use std::fmt;
+
+/// Longest string we follow before giving up
+const MAX_STR: usize = 64;
+/// Most bytes of a buffer we show
+const MAX_BUF: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+enum Arg {
+    Int,
+    Long,
+    Size,
+    Char,
+    Ptr,
+    Str,
+    /// buffer whose length is given by another argument
+    Buf(usize),
+    Void,
+}
+
+struct Prototype {
+    name: &'static str,
+    args: &'static [Arg],
+    ret: Arg,
+}
+
+macro_rules! prototypes {
+    ($($ret:ident $name:ident($($arg:expr),*);)*) => {
+        &[$(Prototype { name: stringify!($name), args: &[$($arg),*], ret: Arg::$ret },)*]
+    };
+}
+
+use Arg::*;
+const PROTOTYPES: &[Prototype] = prototypes! {
+    Size strlen(Str);
+    Size strnlen(Str, Size);
+    Int strcmp(Str, Str);
+    Int strncmp(Str, Str, Size);
+    Int strcasecmp(Str, Str);
+    Ptr strcpy(Ptr, Str);
+    Ptr strncpy(Ptr, Str, Size);
+    Ptr strcat(Ptr, Str);
+    Ptr strchr(Str, Char);
+    Ptr strrchr(Str, Char);
+    Ptr strstr(Str, Str);
+    Ptr strdup(Str);
+    Ptr memcpy(Ptr, Buf(2), Size);
+    Ptr memmove(Ptr, Buf(2), Size);
+    Ptr memset(Ptr, Char, Size);
+    Int memcmp(Buf(2), Buf(2), Size);
+    Ptr memchr(Buf(2), Char, Size);
+    Int puts(Str);
+    Int putchar(Char);
+    Int printf(Str);
+    Int sprintf(Ptr, Str);
+    Int snprintf(Ptr, Size, Str);
+    Int fprintf(Ptr, Str);
+    Int scanf(Str);
+    Int sscanf(Str, Str);
+    Int atoi(Str);
+    Long atol(Str);
+    Long strtol(Str, Ptr, Int);
+    Size strtoul(Str, Ptr, Int);
+    Ptr malloc(Size);
+    Ptr calloc(Size, Size);
+    Ptr realloc(Ptr, Size);
+    Void free(Ptr);
+    Ptr fopen(Str, Str);
+    Int fclose(Ptr);
+    Ptr fgets(Ptr, Int, Ptr);
+    Int fputs(Str, Ptr);
+    Int open(Str, Int);
+    Int close(Int);
+    Long read(Int, Ptr, Size);
+    Long write(Int, Buf(2), Size);
+    Ptr getenv(Str);
+    Int system(Str);
+    Void exit(Int);
+};
+
+/// A call to a known library function
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct LibraryCall {
+    /// index into `Analysis::invocations`
+    pub invocation: usize,
+    pub tick: Tick,
+    pub thread: u64,
+    pub text: String,
+}
+
+impl fmt::Display for LibraryCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {:6} {}", self.thread, self.tick, self.text)
+    }
+}
+
+/// `strcmp@plt` and `strcmp@@GLIBC_2.2.5` are `strcmp`. So are libc's
+/// internal names for it, which start with an underscore and may have
+/// `_<variant>` after the name, like `__strcmp_avx2`, `__GI_strcmp` or
+/// `__memmove_avx_unaligned_erms`. The fortified `_chk` ones aren't, they
+/// take extra arguments (`__printf_chk(flag, fmt, ...)`).
+fn prototype(symbol: &str) -> Option<&'static Prototype> {
+    let symbol = symbol.split('@').next()?;
+    let name = symbol.trim_start_matches('_');
+    let name = name
+        .strip_prefix("GI_")
+        .unwrap_or(name)
+        .trim_start_matches('_');
+    let internal = name.len() < symbol.len();
+
+    PROTOTYPES
+        .iter()
+        .filter(|p| match name.strip_prefix(p.name) {
+            Some(rest) => {
+                let fortified = rest == "_chk" || rest.starts_with("_chk_");
+                rest.is_empty() || (internal && rest.starts_with('_') && !fortified)
+            }
+            None => false,
+        })
+        // `strncmp` over `str`, if there was one
+        .max_by_key(|p| p.name.len())
+}
+
+fn render_str(mem: &HistMem, tick: Tick, adr: u64) -> Option<String> {
+    let mut out = String::new();
+
+    for i in 0..MAX_STR as u64 {
+        match mem.load8(tick, adr + i)? {
+            0 => return Some(format!("\"{}\"", out)),
+            b => out.extend(std::ascii::escape_default(b).map(char::from)),
+        }
+    }
+
+    Some(format!("\"{}\"...", out))
+}
+
+fn render_buf(mem: &HistMem, tick: Tick, adr: u64, len: u64) -> Option<String> {
+    let shown = len.min(MAX_BUF as u64);
+
+    let mut out = String::new();
+    for i in 0..shown {
+        let b = mem.load8(tick, adr + i)?;
+        out.extend(std::ascii::escape_default(b).map(char::from));
+    }
+
+    let more = if shown < len { "..." } else { "" };
+    Some(format!("\"{}\"{}", out, more))
+}
+
+fn render(arg: Arg, value: u64, args: &[u64], mem: &HistMem, tick: Tick) -> String {
+    match arg {
+        Int => (value as i32).to_string(),
+        Long => (value as i64).to_string(),
+        Size => value.to_string(),
+        Char => match value as u8 {
+            c if c.is_ascii_graphic() || c == b' ' => format!("'{}'", c as char),
+            c => format!("{:#x}", c),
+        },
+        Ptr | Void => format!("{:#x}", value),
+        Str => render_str(mem, tick, value).unwrap_or_else(|| format!("{:#x}", value)),
+        Buf(len) => {
+            render_buf(mem, tick, value, args[len]).unwrap_or_else(|| format!("{:#x}", value))
+        }
+    }
+}
+
+fn describe(prototype: &Prototype, invocation: &Invocation, mem: &HistMem) -> String {
+    // memory as it was when the call was made
+    let tick = invocation.call;
+
+    // anything past the register arguments is on the stack, which we don't follow
+    let args = prototype
+        .args
+        .iter()
+        .zip(&invocation.args)
+        .map(|(arg, value)| render(*arg, *value, &invocation.args, mem, tick))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let ret = match (prototype.ret, invocation.ret_value) {
+        (Void, _) => String::new(),
+        (ret, Some(value)) => format!(" = {}", render(ret, value, &[], mem, tick)),
+        (_, None) => String::from(" = ?"),
+    };
+
+    format!("{}({}){}", prototype.name, args, ret)
+}
+
+/// Every call into a function we have a prototype for, rendered like ltrace.
+/// Calls made from inside another listed call (`printf` calling `strlen`) are
+/// left out.
+pub fn library_calls<STEP, const N: usize>(analysis: &Analysis<STEP, N>) -> Vec<LibraryCall>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let mut listed = vec![false; analysis.invocations.len()];
+    let mut calls = Vec::new();
+
+    for (idx, invocation) in analysis.invocations.iter().enumerate() {
+        let prototype = match invocation.symbol.as_deref().and_then(prototype) {
+            Some(prototype) => prototype,
+            None => continue,
+        };
+
+        // callers always come before their callees, so they are already marked
+        let nested = std::iter::successors(invocation.caller, |&c| analysis.invocations[c].caller)
+            .any(|c| listed[c]);
+        if nested {
+            continue;
+        }
+        listed[idx] = true;
+
+        let Some(process) = analysis.process(invocation.call) else {
+            continue;
+        };
+        let mem = &process.mem;
+        calls.push(LibraryCall {
+            invocation: idx,
+            tick: invocation.call,
+            thread: invocation.thread,
+            text: describe(prototype, invocation, mem),
+        });
+    }
+
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{library_calls, prototype, render_str};
+    use crate::{analyzer::testing, arch::Arch, mem::HistMem, state::X64Step, tick::Tick};
+
+    #[test]
+    fn names() {
+        assert_eq!(prototype("strcmp").unwrap().name, "strcmp");
+        assert_eq!(prototype("__strcmp_avx2").unwrap().name, "strcmp");
+        assert_eq!(prototype("memcpy@plt").unwrap().name, "memcpy");
+        assert_eq!(prototype("puts@@GLIBC_2.2.5").unwrap().name, "puts");
+        assert_eq!(
+            prototype("__memmove_avx_unaligned_erms").unwrap().name,
+            "memmove"
+        );
+        assert_eq!(prototype("__strlen_avx2").unwrap().name, "strlen");
+        assert_eq!(prototype("__GI_strlen").unwrap().name, "strlen");
+        assert_eq!(prototype("__strncmp_sse42").unwrap().name, "strncmp");
+        assert!(prototype("main").is_none());
+        // the program's own, not a variant of `read`
+        assert!(prototype("read_config").is_none());
+        // fortified, the arguments don't line up
+        assert!(prototype("__printf_chk").is_none());
+        assert!(prototype("__sprintf_chk").is_none());
+        assert!(prototype("__memcpy_chk").is_none());
+        assert!(prototype("__memmove_chk_avx_unaligned_erms").is_none());
+        assert!(prototype("__read_chk@plt").is_none());
+    }
+
+    #[test]
+    fn strings() {
+        let mut mem = HistMem::new();
+        for (i, b) in b"hunter2\n\0".iter().enumerate() {
+            mem.store8(Tick(1), 0x1000 + i as u64, *b).unwrap();
+        }
+
+        assert_eq!(
+            render_str(&mem, Tick(1), 0x1000),
+            Some("\"hunter2\\n\"".into())
+        );
+        // not written yet
+        assert_eq!(render_str(&mem, Tick(0), 0x1000), None);
+    }
+
+    #[test]
+    fn recorded() {
+        let steps = testing::recording::<X64Step, 16>("memory-amd64.trace");
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let calls: Vec<_> = library_calls(&analysis)
+            .into_iter()
+            .map(|c| c.text)
+            .collect();
+        assert_eq!(
+            calls,
+            vec![
+                "printf(\"stat: %d\\n\") = 11",
+                "malloc(16) = 0x55555555a2b0",
+                "printf(\"dyn: %d\\n\") = 11",
+                "printf(\"arr[]: %p\\n\") = 22",
+                "printf(\"main: %p\\n\") = 21",
+                "printf(\"sp: %p\\n\") = 19",
+                "printf(\"printf: %p\\n\") = 23",
+            ]
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{binary::Binary, syscall::Arg, tick::Tick};
+use object::{Object, ObjectSegment, SegmentFlags};
+use std::{collections::BTreeMap, fmt};
+
+const PAGE: u64 = 0x1000;
+
+/// The stack size we assume, the default `RLIMIT_STACK`
+pub const STACK_SIZE: u64 = 8 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl Perms {
+    pub const RW: Self = Self {
+        read: true,
+        write: true,
+        exec: false,
+    };
+
+    /// From the prot argument of mmap/mprotect, symbolic or numeric
+    pub fn from_prot(prot: &Arg) -> Self {
+        match prot.as_u64() {
+            Some(v) => Self::from_bits(v as u32),
+            None => Self {
+                read: prot.has_flag("PROT_READ"),
+                write: prot.has_flag("PROT_WRITE"),
+                exec: prot.has_flag("PROT_EXEC"),
+            },
+        }
+    }
+
+    /// PROT_* and ELF PF_* use different bit orders
+    pub fn from_elf(p_flags: u32) -> Self {
+        Self {
+            read: p_flags & 4 != 0,
+            write: p_flags & 2 != 0,
+            exec: p_flags & 1 != 0,
+        }
+    }
+
+    fn from_bits(prot: u32) -> Self {
+        Self {
+            read: prot & 1 != 0,
+            write: prot & 2 != 0,
+            exec: prot & 4 != 0,
+        }
+    }
+}
+
+impl fmt::Display for Perms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bit = |set, c| if set { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            bit(self.read, 'r'),
+            bit(self.write, 'w'),
+            bit(self.exec, 'x')
+        )
+    }
+}
+
+/// A mapping, alive from `mapped` until `unmapped`
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Region {
+    pub start: u64,
+    pub end: u64,
+    pub perms: Perms,
+    /// file name, `[heap]`, `[stack]` or `[anon]`
+    pub label: String,
+    pub mapped: Tick,
+    pub unmapped: Option<Tick>,
+}
+
+impl Region {
+    fn alive(&self, tick: Tick) -> bool {
+        self.mapped <= tick && self.unmapped.is_none_or(|u| tick < u)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.label, self.perms)
+    }
+}
+
+/// The address space of a process over time
+#[derive(Clone, Debug, Default)]
+pub struct MemoryMap {
+    /// start -> index into `timeline`, only what is mapped right now
+    live: BTreeMap<u64, usize>,
+    pub timeline: Vec<Region>,
+    /// (start, current) of the program break
+    brk: Option<(u64, u64)>,
+}
+
+pub fn page_up(adr: u64) -> u64 {
+    adr.div_ceil(PAGE) * PAGE
+}
+
+/// `/usr/lib/libc.so.6` is just `libc.so.6`
+pub fn label(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything mapped at `tick`, ordered by address
+    pub fn at(&self, tick: Tick) -> Vec<&Region> {
+        let mut regions: Vec<_> = self.timeline.iter().filter(|r| r.alive(tick)).collect();
+        regions.sort_by_key(|r| r.start);
+        regions
+    }
+
+    /// The region containing `adr` at `tick`
+    pub fn lookup(&self, adr: u64, tick: Tick) -> Option<&Region> {
+        self.timeline
+            .iter()
+            .rev()
+            .find(|r| r.start <= adr && adr < r.end && r.alive(tick))
+    }
+
+    /// The loadable segments of `binary`, loaded at `base`
+    pub fn map_binary(&mut self, binary: &Binary, base: u64, path: &str, tick: Tick) {
+        for segment in binary.obj().segments() {
+            let perms = match segment.flags() {
+                SegmentFlags::Elf { p_flags } => Perms::from_elf(p_flags),
+                _ => Perms::RW,
+            };
+            self.map(
+                base + segment.address(),
+                segment.size(),
+                perms,
+                label(path),
+                tick,
+            );
+        }
+    }
+
+    pub fn map(&mut self, start: u64, len: u64, perms: Perms, label: String, tick: Tick) {
+        let end = page_up(start + len);
+        // MAP_FIXED and friends silently replace what was there
+        self.unmap(start, len, tick);
+
+        self.insert(Region {
+            start,
+            end,
+            perms,
+            label,
+            mapped: tick,
+            unmapped: None,
+        });
+    }
+
+    pub fn unmap(&mut self, start: u64, len: u64, tick: Tick) {
+        let end = page_up(start + len);
+
+        for idx in self.overlapping(start, end) {
+            let old = self.timeline[idx].clone();
+            self.live.remove(&old.start);
+            self.timeline[idx].unmapped = Some(tick);
+
+            // what's left on either side lives on as its own region
+            for (from, to) in [(old.start, start), (end, old.end)] {
+                if from < to {
+                    self.insert(Region {
+                        start: from,
+                        end: to,
+                        mapped: tick,
+                        ..old.clone()
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn protect(&mut self, start: u64, len: u64, perms: Perms, tick: Tick) {
+        let end = page_up(start + len);
+        let affected: Vec<_> = self
+            .overlapping(start, end)
+            .into_iter()
+            .map(|idx| self.timeline[idx].clone())
+            .collect();
+
+        self.unmap(start, len, tick);
+
+        for old in affected {
+            self.insert(Region {
+                start: old.start.max(start),
+                end: old.end.min(end),
+                perms,
+                mapped: tick,
+                ..old
+            });
+        }
+    }
+
+    /// `ret` is what brk returned, the first call tells us where the heap starts
+    pub fn brk(&mut self, ret: u64, tick: Tick) {
+        match self.brk {
+            None => self.brk = Some((ret, ret)),
+            Some((start, cur)) if ret != cur && ret >= start => {
+                self.unmap(start, cur - start, tick);
+                if ret > start {
+                    self.map(start, ret - start, Perms::RW, "[heap]".into(), tick);
+                }
+                self.brk = Some((start, ret));
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn insert(&mut self, region: Region) {
+        self.live.insert(region.start, self.timeline.len());
+        self.timeline.push(region);
+    }
+
+    fn overlapping(&self, start: u64, end: u64) -> Vec<usize> {
+        self.live
+            .range(..end)
+            .map(|(_, &idx)| idx)
+            .filter(|&idx| self.timeline[idx].end > start)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryMap, Perms};
+    use crate::tick::Tick;
+
+    const RX: Perms = Perms {
+        read: true,
+        write: false,
+        exec: true,
+    };
+
+    #[test]
+    fn split() {
+        let mut maps = MemoryMap::new();
+        maps.map(0x10000, 0x4000, Perms::RW, "[anon]".into(), Tick(1));
+        maps.protect(0x11000, 0x1000, RX, Tick(2));
+        maps.unmap(0x13000, 0x1000, Tick(3));
+
+        let at = |tick| {
+            maps.at(Tick(tick))
+                .iter()
+                .map(|r| (r.start, r.end, r.perms.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(at(0), vec![]);
+        assert_eq!(at(1), vec![(0x10000, 0x14000, "rw-".into())]);
+        assert_eq!(
+            at(3),
+            vec![
+                (0x10000, 0x11000, "rw-".into()),
+                (0x11000, 0x12000, "r-x".into()),
+                (0x12000, 0x13000, "rw-".into()),
+            ]
+        );
+        assert_eq!(
+            maps.lookup(0x11800, Tick(3)).unwrap().to_string(),
+            "[anon] r-x"
+        );
+        assert!(maps.lookup(0x13800, Tick(3)).is_none());
+        assert!(maps.lookup(0x13800, Tick(2)).is_some());
+    }
+
+    #[test]
+    fn heap() {
+        let mut maps = MemoryMap::new();
+        maps.brk(0x5000, Tick(1));
+        maps.brk(0x7000, Tick(2));
+        maps.brk(0x9000, Tick(3));
+
+        assert!(maps.lookup(0x5000, Tick(1)).is_none());
+        assert_eq!(maps.lookup(0x6000, Tick(2)).unwrap().label, "[heap]");
+        assert_eq!(maps.at(Tick(3)).len(), 1);
+        assert_eq!(maps.at(Tick(3))[0].end, 0x9000);
+    }
+}
@@ -1,11 +1,28 @@
+pub mod coredump;
+pub mod crash;
 pub mod dump;
+pub mod fds;
+pub mod heap;
+pub mod ltrace;
+pub mod maps;
+pub mod slice;
+pub mod taint;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod uninit;
+pub mod watch;
 use crate::{
     dis::Instruction,
     mem::HistMem,
     state::{Instrumentation, Step},
     syms::SymbolTable,
+    tick::Tick,
 };
-use std::fmt;
+use crash::CrashReport;
+use fds::FdTable;
+use maps::MemoryMap;
+use std::{collections::BTreeMap, fmt};
+use watch::AccessIndex;
 
 #[derive(Clone, Debug)]
 pub struct Analysis<STEP, const N: usize>
@@ -16,6 +33,95 @@ where
     pub insns: Vec<Instruction>,
     pub instrumentations: Vec<Instrumentation>,
     pub bt_lens: Vec<usize>,
+    /// thread id -> the ticks it executed
+    pub threads: BTreeMap<u64, Vec<Tick>>,
+    /// One per process image, a fork or an exec starts a new one
+    pub processes: Vec<Process>,
+    /// tick -> index into `processes`
+    pub process_of: Vec<usize>,
+    /// Every function call, in the order they happened
+    pub invocations: Vec<Invocation>,
+    /// Every memory access, by address
+    pub accesses: AccessIndex,
+    /// Set if the program died from a signal or the trace was cut short
+    pub crash: Option<CrashReport>,
+}
+
+/// A single process image with its own address space
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub pid: u64,
+    /// pid of the parent, if we saw it fork
+    pub parent: Option<u64>,
+    /// What was exec'd, `None` for the original program and plain forks
+    pub path: Option<String>,
+    /// the ticks this process executed
+    pub ticks: Vec<Tick>,
     pub table: SymbolTable,
     pub mem: HistMem,
+    pub fds: FdTable,
+    pub maps: MemoryMap,
+}
+
+/// A single call, from the call instruction until we got back to the caller
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Invocation {
+    pub callee: u64,
+    pub symbol: Option<String>,
+    pub thread: u64,
+    /// tick of the call instruction
+    pub call: Tick,
+    /// first tick back in the caller, `None` if it never returned
+    pub ret: Option<Tick>,
+    /// index of the invocation this was called from
+    pub caller: Option<usize>,
+    /// backtrace length inside the call
+    pub depth: usize,
+    /// argument registers of the calling convention, at entry
+    pub args: Vec<u64>,
+    /// return register once back in the caller, `None` if we skipped past it
+    pub ret_value: Option<u64>,
+    /// whether we saw the return, or had to infer it
+    pub observed: bool,
+}
+
+impl Invocation {
+    /// Whether this calls `name`, directly or through its PLT stub
+    pub fn is_call_to(&self, name: &str) -> bool {
+        self.symbol
+            .as_deref()
+            .is_some_and(|s| s == name || s.strip_suffix("@plt") == Some(name))
+    }
+}
+
+impl<STEP, const N: usize> Analysis<STEP, N>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    /// Nothing got traced, all we have is how it died
+    pub fn empty(crash: CrashReport) -> Self {
+        Self {
+            trace: Vec::new(),
+            insns: Vec::new(),
+            instrumentations: Vec::new(),
+            bt_lens: Vec::new(),
+            threads: BTreeMap::new(),
+            processes: Vec::new(),
+            process_of: Vec::new(),
+            invocations: Vec::new(),
+            accesses: AccessIndex::default(),
+            crash: Some(crash),
+        }
+    }
+
+    /// The process executing at `tick`. Ticks past the end belong to the last
+    /// process, there is none if nothing was traced.
+    pub fn process(&self, tick: Tick) -> Option<&Process> {
+        let idx = self
+            .process_of
+            .get(tick.index())
+            .or(self.process_of.last())?;
+
+        Some(&self.processes[*idx])
+    }
 }
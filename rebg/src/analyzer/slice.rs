@@ -0,0 +1,355 @@
+use super::Analysis;
+use crate::{
+    arch::Arch,
+    dis::{regs::Reg, Instruction},
+    state::{MemoryOpKind, State, Step},
+    tick::Tick,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+};
+
+/// Steps we look at before giving up, the graph explodes quickly
+pub const DEFAULT_LIMIT: usize = 1000;
+
+/// A register or memory range, the value of which we want the origin of
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Location {
+    /// canonical name, `rax` rather than `eax`
+    Reg(&'static str),
+    Mem {
+        address: u64,
+        len: u64,
+    },
+}
+
+impl Location {
+    /// `rax`, `w0`, or an address like `0x7ffc1000`, which is a single byte
+    pub fn parse(arch: Arch, s: &str) -> Option<Self> {
+        if let Some(hex) = s.strip_prefix("0x") {
+            let address = u64::from_str_radix(hex, 16).ok()?;
+            return Some(Location::Mem { address, len: 1 });
+        }
+
+        (0..=u16::MAX)
+            .filter_map(|num| Reg::from_num(arch, num))
+            .find(|reg| reg.as_str() == s)
+            .map(|reg| Location::Reg(reg.canonical().as_str()))
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Reg(reg) => write!(f, "{}", reg),
+            Location::Mem { address, len } => write!(f, "[{:x}; {}]", address, len),
+        }
+    }
+}
+
+/// An input of a step, and the step that produced it. `None` if it was
+/// there before the trace started, or came from somewhere we can't see.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Dependency {
+    pub location: Location,
+    pub producer: Option<Tick>,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SliceNode {
+    pub tick: Tick,
+    pub pc: u64,
+    pub insn: String,
+    pub deps: Vec<Dependency>,
+}
+
+/// The dependency graph of a value, each node is a step
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Slice {
+    pub tick: Tick,
+    /// where the value we asked about came from
+    pub root: Vec<Dependency>,
+    pub nodes: BTreeMap<Tick, SliceNode>,
+    /// set if we hit the limit and some producers weren't expanded
+    pub truncated: bool,
+}
+
+/// Registers an instruction takes as input. `xor eax, eax` and friends don't
+/// really read anything.
+pub(crate) fn reads(insn: &Instruction) -> Vec<&'static str> {
+    let zeroing = matches!(
+        insn.mnemonic.as_deref(),
+        Some("xor" | "sub" | "pxor" | "xorps" | "xorpd" | "eor")
+    ) && insn.op_str.as_deref().is_some_and(|ops| {
+        let ops: Vec<_> = ops.split(", ").collect();
+        ops.len() >= 2 && ops[1..].iter().all(|op| *op == ops[0])
+    });
+    if zeroing {
+        return Vec::new();
+    }
+
+    let mut regs: Vec<_> = insn.read.iter().map(|r| r.canonical().as_str()).collect();
+    regs.sort_unstable();
+    regs.dedup();
+    regs
+}
+
+fn writes(insn: &Instruction, reg: &str) -> bool {
+    insn.write.iter().any(|r| r.canonical().as_str() == reg)
+}
+
+/// Where the value of `location` at the start of `tick` came from, and
+/// recursively where the inputs of those steps came from. Both the data and
+/// the registers used to compute addresses count as inputs.
+pub fn slice<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    tick: Tick,
+    location: Location,
+    limit: usize,
+) -> Slice
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let root = producers(analysis, tick, &location);
+
+    let mut nodes = BTreeMap::new();
+    let mut queue: VecDeque<Tick> = root.iter().filter_map(|d| d.producer).collect();
+    let mut truncated = false;
+
+    while let Some(tick) = queue.pop_front() {
+        if nodes.contains_key(&tick) {
+            continue;
+        }
+        if nodes.len() >= limit {
+            truncated = true;
+            break;
+        }
+
+        let step = &analysis.trace[tick.index()];
+        let insn = &analysis.insns[tick.index()];
+
+        let mut deps = Vec::new();
+        for reg in reads(insn) {
+            deps.extend(producers(analysis, tick, &Location::Reg(reg)));
+        }
+        for op in step.memory_ops() {
+            if op.kind == MemoryOpKind::Read {
+                let location = Location::Mem {
+                    address: op.address,
+                    len: op.value.size(),
+                };
+                deps.extend(producers(analysis, tick, &location));
+            }
+        }
+
+        queue.extend(deps.iter().filter_map(|d| d.producer));
+
+        let text = match (&insn.mnemonic, &insn.op_str) {
+            (Some(mnemonic), Some(op_str)) => format!("{} {}", mnemonic, op_str),
+            (Some(mnemonic), None) => mnemonic.to_string(),
+            _ => String::from("??"),
+        };
+
+        nodes.insert(
+            tick,
+            SliceNode {
+                tick,
+                pc: step.state().pc(),
+                insn: text,
+                deps,
+            },
+        );
+    }
+
+    Slice {
+        tick,
+        root,
+        nodes,
+        truncated,
+    }
+}
+
+/// The last steps before `tick` that wrote `location`. Memory is split up
+/// into runs of bytes with the same writer.
+fn producers<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    tick: Tick,
+    location: &Location,
+) -> Vec<Dependency>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    match *location {
+        Location::Reg(reg) => {
+            // registers belong to the thread
+            let thread = analysis.trace[tick.index()].thread();
+            let ticks = &analysis.threads[&thread];
+            let before = ticks.partition_point(|&t| t < tick);
+
+            let producer = ticks[..before]
+                .iter()
+                .rev()
+                .copied()
+                .find(|&t| writes(&analysis.insns[t.index()], reg));
+
+            vec![Dependency {
+                location: location.clone(),
+                producer,
+            }]
+        }
+        Location::Mem { address, len } => {
+            let process = analysis.process_of[tick.index()];
+            let mem = &analysis.processes[process].mem;
+
+            // stores land at tick + 1, so the writer is the step before
+            let writer = |adr| mem.written_at(tick, adr).map(|w| Tick(w.0 - 1));
+
+            let mut deps: Vec<Dependency> = Vec::new();
+            for adr in address..address + len {
+                let producer = writer(adr);
+                match deps.last_mut() {
+                    Some(Dependency {
+                        location: Location::Mem { len, .. },
+                        producer: last,
+                    }) if *last == producer => *len += 1,
+                    _ => deps.push(Dependency {
+                        location: Location::Mem {
+                            address: adr,
+                            len: 1,
+                        },
+                        producer,
+                    }),
+                }
+            }
+            deps
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{slice, Location};
+    use crate::{
+        analyzer::testing::{self, x64_step as step},
+        arch::Arch,
+        state::X64Step,
+        tick::Tick,
+        tracer::parser::Message,
+    };
+
+    #[test]
+    fn dependencies() {
+        let thread = |id, extra: &[Message]| [&[Message::Thread(id)], extra].concat();
+
+        let mut raw = testing::libload();
+        let steps = [
+            // mov rax, 1
+            step(0x401000, &[0x48, 0xc7, 0xc0, 1, 0, 0, 0], &thread(1, &[])),
+            // mov rax, 2 on another thread
+            step(0x402000, &[0x48, 0xc7, 0xc0, 2, 0, 0, 0], &thread(2, &[])),
+            // mov [rbx], rax
+            step(
+                0x401007,
+                &[0x48, 0x89, 0x03],
+                &thread(1, &[Message::Store(0x5000, 1, 8)]),
+            ),
+            // xor ecx, ecx
+            step(0x40100a, &[0x31, 0xc9], &thread(1, &[])),
+            // mov rdx, [rbx]
+            step(
+                0x40100c,
+                &[0x48, 0x8b, 0x13],
+                &thread(1, &[Message::Load(0x5000, 1, 8)]),
+            ),
+            // add rdx, rcx
+            step(0x40100f, &[0x48, 0x01, 0xca], &thread(1, &[])),
+            step(0x401012, &[0x90], &thread(1, &[])),
+        ];
+        raw.extend(steps.concat());
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let deps = |tick: u64, location: Location, limit| {
+            let slice = slice(&analysis, Tick(tick), location, limit);
+            let root: Vec<_> = slice
+                .root
+                .iter()
+                .map(|d| (d.location.to_string(), d.producer))
+                .collect();
+            let nodes: Vec<_> = slice
+                .nodes
+                .values()
+                .map(|n| {
+                    let deps: Vec<_> = n
+                        .deps
+                        .iter()
+                        .map(|d| (d.location.to_string(), d.producer))
+                        .collect();
+                    (n.tick, deps)
+                })
+                .collect();
+            (root, nodes, slice.truncated)
+        };
+        let dep =
+            |location: &str, producer: Option<u64>| (location.to_string(), producer.map(Tick));
+
+        let (root, nodes, truncated) = deps(6, Location::Reg("rdx"), 10);
+        assert_eq!(root, vec![dep("rdx", Some(5))]);
+        assert_eq!(
+            nodes,
+            vec![
+                (Tick(0), vec![]),
+                // the rax of this thread, not the one written in between
+                (Tick(2), vec![dep("rax", Some(0)), dep("rbx", None)]),
+                // xor with itself doesn't depend on rcx
+                (Tick(3), vec![]),
+                (Tick(4), vec![dep("rbx", None), dep("[5000; 8]", Some(2))]),
+                (Tick(5), vec![dep("rcx", Some(3)), dep("rdx", Some(4))]),
+            ]
+        );
+        assert!(!truncated);
+
+        // the store lands after its step, half of this was never written
+        let (root, _, _) = deps(
+            6,
+            Location::Mem {
+                address: 0x4ffc,
+                len: 8,
+            },
+            10,
+        );
+        assert_eq!(
+            root,
+            vec![dep("[4ffc; 4]", None), dep("[5000; 4]", Some(2))]
+        );
+
+        let (_, nodes, truncated) = deps(6, Location::Reg("rdx"), 2);
+        assert_eq!(
+            nodes.iter().map(|n| n.0).collect::<Vec<_>>(),
+            vec![Tick(3), Tick(5)]
+        );
+        assert!(truncated);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Location::parse(Arch::X86_64, "eax"),
+            Some(Location::Reg("rax"))
+        );
+        assert_eq!(
+            Location::parse(Arch::ARM64, "w3"),
+            Some(Location::Reg("x3"))
+        );
+        assert_eq!(
+            Location::parse(Arch::X86_64, "0x1000"),
+            Some(Location::Mem {
+                address: 0x1000,
+                len: 1
+            })
+        );
+        assert_eq!(Location::parse(Arch::X86_64, "nope"), None);
+    }
+}
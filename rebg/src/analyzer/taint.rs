@@ -0,0 +1,512 @@
+use super::{maps, slice, Analysis};
+use crate::{
+    arch::Arch,
+    dis::{regs::Reg, Instruction},
+    state::{MemoryOpKind, State, Step},
+    tick::{self, Tick},
+};
+use capstone::arch::{arm64::Arm64OperandType, x86::X86OperandType, ArchOperand};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+/// One bit per source, in the order they were given
+pub type Taint = u64;
+
+/// Where untrusted data comes from
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// `read` from fd 0 while it still is stdin
+    Stdin,
+    /// `read` from an fd opened from this path
+    File(String),
+    /// `recv`, `recvfrom`, and `read` from sockets
+    Recv,
+    /// the argument strings on the initial stack
+    Argv,
+    /// the environment strings on the initial stack
+    Env,
+}
+
+/// `stdin`, `recv`, `argv`, `env` or `file:<path>`
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdin" => Ok(Source::Stdin),
+            "recv" => Ok(Source::Recv),
+            "argv" => Ok(Source::Argv),
+            "env" => Ok(Source::Env),
+            _ => s
+                .strip_prefix("file:")
+                .map(|p| Source::File(p.to_string()))
+                .ok_or_else(|| format!("unknown taint source: {}", s)),
+        }
+    }
+}
+
+/// A conditional (or indirect) branch that depended on tainted data
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct TaintedBranch {
+    pub tick: Tick,
+    pub pc: u64,
+    pub location: Option<String>,
+    pub insn: String,
+    pub taint: Taint,
+    /// whether the thread went somewhere other than the next instruction
+    pub taken: Option<bool>,
+}
+
+impl fmt::Display for TaintedBranch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:6} {:x}", self.tick, self.pc)?;
+        if let Some(location) = &self.location {
+            write!(f, " <{}>", location)?;
+        }
+        write!(f, " {} (taint {:b}", self.insn, self.taint)?;
+        match self.taken {
+            Some(true) => write!(f, ", taken)"),
+            Some(false) => write!(f, ", not taken)"),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// What is tainted at the start of a tick
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct TaintState {
+    /// thread -> tainted registers
+    pub regs: BTreeMap<u64, BTreeMap<&'static str, Taint>>,
+    /// (start, len, taint), runs of bytes with the same taint
+    pub mem: Vec<(u64, u64, Taint)>,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct TaintReport {
+    pub sources: Vec<Source>,
+    /// every tick that produced tainted data, and which taint it was
+    pub steps: Vec<(Tick, Taint)>,
+    pub branches: Vec<TaintedBranch>,
+    /// only if asked for a tick
+    pub state: Option<TaintState>,
+}
+
+/// Registers that are only used to compute an address, loading through a
+/// tainted pointer doesn't taint what's loaded.
+fn address_regs(insn: &Instruction, arch: Arch) -> Vec<&'static str> {
+    let reg = |id: capstone::RegId| Reg::from_num(arch, id.0).map(|r| r.canonical().as_str());
+
+    let mut regs = Vec::new();
+    let mut data = Vec::new();
+    for operand in insn.operands.iter() {
+        match operand {
+            ArchOperand::X86Operand(o) => match &o.op_type {
+                X86OperandType::Mem(mem) => regs.extend([reg(mem.base()), reg(mem.index())]),
+                X86OperandType::Reg(r) => data.push(reg(*r)),
+                _ => {}
+            },
+            ArchOperand::Arm64Operand(o) => match &o.op_type {
+                Arm64OperandType::Mem(mem) => regs.extend([reg(mem.base()), reg(mem.index())]),
+                Arm64OperandType::Reg(r) => data.push(reg(*r)),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    regs.into_iter()
+        .flatten()
+        .filter(|r| !data.contains(&Some(*r)))
+        .collect()
+}
+
+/// Runs the trace forward, marking the bytes that come from `sources` and
+/// everything computed from them. If `until` is set, the report includes
+/// what was tainted at the start of that tick.
+pub fn taint<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    arch: Arch,
+    sources: Vec<Source>,
+    until: Option<Tick>,
+) -> TaintReport
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let sp = match arch {
+        Arch::ARM64 => "sp",
+        Arch::X86_64 => "rsp",
+    };
+    let bit = |source: &Source| {
+        sources
+            .iter()
+            .position(|s| s == source)
+            .filter(|&i| i < Taint::BITS as usize)
+            .map_or(0, |i| 1 << i)
+    };
+
+    let mut report = TaintReport::default();
+    // per process
+    let mut mems: HashMap<usize, HashMap<u64, Taint>> = HashMap::new();
+    // per thread
+    let mut regs: HashMap<u64, HashMap<&'static str, Taint>> = HashMap::new();
+
+    if let Some(first) = analysis.trace.first() {
+        let (argv, env) = startup_strings(analysis, first.state().sp());
+        let mem = mems.entry(0).or_default();
+        for (range, source) in [(argv, Source::Argv), (env, Source::Env)] {
+            let bit = bit(&source);
+            if let (Some((from, to)), true) = (range, bit != 0) {
+                for adr in from..to {
+                    mem.insert(adr, bit);
+                }
+            }
+        }
+    }
+
+    // pid -> index of its current image
+    let mut current: HashMap<u64, usize> = HashMap::new();
+
+    for (tick, step) in tick::enumerate(&analysis.trace) {
+        let process = analysis.process_of[tick.index()];
+        let thread = step.thread();
+
+        // a forked child starts out with the memory of its parent, an exec
+        // starts from scratch
+        if !mems.contains_key(&process) {
+            let image = &analysis.processes[process];
+            let parent = match (current.get(&image.pid), image.parent) {
+                (None, Some(parent)) => current.get(&parent),
+                _ => None,
+            };
+            let inherited = parent.and_then(|p| mems.get(p)).cloned();
+            mems.insert(process, inherited.unwrap_or_default());
+        }
+        current.insert(step.pid(), process);
+
+        let mem = mems.entry(process).or_default();
+        if until == Some(tick) {
+            report.state = Some(snapshot(&regs, mem));
+        }
+        let regs = regs.entry(thread).or_default();
+        let insn = &analysis.insns[tick.index()];
+
+        let ops = step.memory_ops();
+        let touches_memory = !ops.is_empty();
+        let address = address_regs(insn, arch);
+
+        let mut input: Taint = 0;
+        for reg in slice::reads(insn) {
+            if address.contains(&reg) || (touches_memory && reg == sp) {
+                continue;
+            }
+            input |= regs.get(reg).copied().unwrap_or(0);
+        }
+        for op in ops.iter().filter(|op| op.kind == MemoryOpKind::Read) {
+            for adr in op.address..op.address + op.value.size() {
+                input |= mem.get(&adr).copied().unwrap_or(0);
+            }
+        }
+
+        if insn.groups.iter().any(|g| g.is_jump()) && input != 0 {
+            let ticks = &analysis.threads[&thread];
+            let next = ticks
+                .get(ticks.partition_point(|&t| t <= tick))
+                .map(|&t| analysis.trace[t.index()].state().pc());
+
+            report.branches.push(TaintedBranch {
+                tick,
+                pc: step.state().pc(),
+                location: analysis.processes[process]
+                    .table
+                    .lookup_at(step.state().pc(), tick)
+                    .map(|s| s.to_string()),
+                insn: format!(
+                    "{} {}",
+                    insn.mnemonic.as_deref().unwrap_or("??"),
+                    insn.op_str.as_deref().unwrap_or("")
+                ),
+                taint: input,
+                taken: next.map(|pc| pc != insn.address + insn.len as u64),
+            });
+        }
+
+        // whatever the kernel returns is its own, unless it's a source
+        let output = if step.syscall().is_some() { 0 } else { input };
+
+        for reg in insn.write.iter().map(|r| r.canonical().as_str()) {
+            if output == 0 {
+                regs.remove(reg);
+            } else {
+                regs.insert(reg, output);
+            }
+        }
+        for op in ops.iter().filter(|op| op.kind == MemoryOpKind::Write) {
+            for adr in op.address..op.address + op.value.size() {
+                if output == 0 {
+                    mem.remove(&adr);
+                } else {
+                    mem.insert(adr, output);
+                }
+            }
+        }
+        for write in step.syscall_writes() {
+            for adr in write.address..write.address + write.bytes.len() as u64 {
+                mem.remove(&adr);
+            }
+        }
+
+        let mut produced =
+            if insn.write.is_empty() && !ops.iter().any(|op| op.kind == MemoryOpKind::Write) {
+                0
+            } else {
+                output
+            };
+
+        if let Some((buf, len, source)) = syscall_source(analysis, tick) {
+            let bit = sources
+                .iter()
+                .filter(|s| source.matches(s))
+                .fold(0, |acc, s| acc | bit(s));
+            if bit != 0 {
+                for adr in buf..buf + len {
+                    mem.insert(adr, bit);
+                }
+                produced |= bit;
+            }
+        }
+
+        if produced != 0 {
+            report.steps.push((tick, produced));
+        }
+    }
+
+    report.sources = sources;
+    report
+}
+
+/// What a `read`-like syscall read from
+enum Origin<'a> {
+    Fd { stdin: bool, path: Option<&'a str> },
+    Socket,
+}
+
+impl Origin<'_> {
+    fn matches(&self, source: &Source) -> bool {
+        match (self, source) {
+            (Origin::Fd { stdin, .. }, Source::Stdin) => *stdin,
+            (Origin::Fd { path, .. }, Source::File(p)) => *path == Some(p.as_str()),
+            (Origin::Socket, Source::Recv) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The buffer a `read`/`recv` at `tick` filled, and where it came from
+fn syscall_source<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    tick: Tick,
+) -> Option<(u64, u64, Origin<'_>)>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let syscall = analysis.trace[tick.index()].syscall()?;
+    if !matches!(
+        syscall.name.as_str(),
+        "read" | "pread64" | "recv" | "recvfrom"
+    ) {
+        return None;
+    }
+
+    let fd = syscall.arg(0)?.as_i64()? as i32;
+    let buf = syscall.arg(1)?.as_u64()?;
+    let len = syscall.value()?;
+
+    let fd = analysis.process(tick)?.fds.at(fd, tick)?;
+    let origin = if fd.description.starts_with("socket") {
+        Origin::Socket
+    } else {
+        Origin::Fd {
+            stdin: fd.description == "stdin",
+            path: fd.path.as_deref(),
+        }
+    };
+
+    Some((buf, len, origin))
+}
+
+/// Start and end address
+type Span = (u64, u64);
+
+/// The argv and environment strings the kernel put above the initial stack
+/// pointer. We only know the pointers to them if something loaded them.
+fn startup_strings<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    sp: u64,
+) -> (Option<Span>, Option<Span>)
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let mem = &analysis.processes[0].mem;
+    let load = |adr| {
+        let bytes: Option<Vec<u8>> = mem.load_range(Tick::MAX, adr, 8).into_iter().collect();
+        Some(u64::from_le_bytes(bytes?.try_into().ok()?))
+    };
+    let top = maps::page_up(sp);
+
+    let Some(argc) = load(sp) else {
+        return (None, None);
+    };
+
+    let argv = (0..argc).filter_map(|i| load(sp + 8 + 8 * i)).min();
+    let env = (argc + 1..)
+        .map(|i| load(sp + 8 + 8 * i))
+        .take_while(|p| p.is_some_and(|p| p != 0))
+        .flatten()
+        .min();
+
+    let argv = argv.map(|from| (from, env.unwrap_or(top)));
+    let env = env.map(|from| (from, top));
+    (argv, env)
+}
+
+fn snapshot(
+    regs: &HashMap<u64, HashMap<&'static str, Taint>>,
+    mem: &HashMap<u64, Taint>,
+) -> TaintState {
+    let regs = regs
+        .iter()
+        .map(|(&thread, regs)| (thread, regs.iter().map(|(&r, &t)| (r, t)).collect()))
+        .collect();
+
+    let mut bytes: Vec<_> = mem.iter().map(|(&a, &t)| (a, t)).collect();
+    bytes.sort_unstable();
+
+    let mut runs: Vec<(u64, u64, Taint)> = Vec::new();
+    for (adr, taint) in bytes {
+        match runs.last_mut() {
+            Some((start, len, t)) if *start + *len == adr && *t == taint => *len += 1,
+            _ => runs.push((adr, 1, taint)),
+        }
+    }
+
+    TaintState { regs, mem: runs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{taint, Source};
+    use crate::{
+        analyzer::testing::{self, x64_step as step},
+        arch::Arch,
+        state::X64Step,
+        tick::Tick,
+        tracer::parser::Message,
+    };
+
+    #[test]
+    fn propagation() {
+        let parent = [Message::Process(100), Message::Thread(100)];
+        let child = [Message::Process(200), Message::Thread(200)];
+        let with = |ids: &[Message], extra: &[Message]| [ids, extra].concat();
+
+        let mut raw = testing::libload();
+        let steps = [
+            // 4 bytes of input at 0x5000
+            step(
+                0x401000,
+                &[0x0f, 0x05],
+                &with(
+                    &parent,
+                    &[Message::Syscall("read(0, 0x5000, 0x10) = 0x4".into())],
+                ),
+            ),
+            // mov rax, [rbx]
+            step(
+                0x401002,
+                &[0x48, 0x8b, 0x03],
+                &with(&parent, &[Message::Load(0x5000, 0x41, 8)]),
+            ),
+            // mov [rcx], rax
+            step(
+                0x401005,
+                &[0x48, 0x89, 0x01],
+                &with(&parent, &[Message::Store(0x6000, 0x41, 8)]),
+            ),
+            // cmp rax, 5
+            step(0x401008, &[0x48, 0x83, 0xf8, 0x05], &parent),
+            // jne 0x40101e, taken
+            step(0x40100c, &[0x75, 0x10], &parent),
+            // mov rdx, [rax], the pointer is tainted but not what it points to
+            step(
+                0x40101e,
+                &[0x48, 0x8b, 0x10],
+                &with(&parent, &[Message::Load(0x7000, 0, 8)]),
+            ),
+            step(
+                0x401021,
+                &[0x0f, 0x05],
+                &with(&parent, &[Message::Syscall("fork() = 200".into())]),
+            ),
+            // mov rsi, [rdi] in the child, which has its parent's memory
+            step(
+                0x401023,
+                &[0x48, 0x8b, 0x37],
+                &with(&child, &[Message::Load(0x6000, 0x41, 8)]),
+            ),
+            step(0x401026, &[0x90], &child),
+        ];
+        raw.extend(steps.concat());
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let report = taint(
+            &analysis,
+            Arch::X86_64,
+            vec![Source::Recv, Source::Stdin],
+            Some(Tick(8)),
+        );
+
+        // stdin is the second source
+        let stdin = 0b10;
+        assert_eq!(
+            report.steps,
+            vec![
+                (Tick(0), stdin),
+                (Tick(1), stdin),
+                (Tick(2), stdin),
+                (Tick(3), stdin),
+                (Tick(7), stdin),
+            ]
+        );
+
+        let branches: Vec<_> = report
+            .branches
+            .iter()
+            .map(|b| (b.tick, b.pc, b.taint, b.taken))
+            .collect();
+        assert_eq!(branches, vec![(Tick(4), 0x40100c, stdin, Some(true))]);
+
+        let state = report.state.unwrap();
+        assert_eq!(state.mem, vec![(0x5000, 4, stdin), (0x6000, 8, stdin)]);
+        assert_eq!(state.regs[&100].get("rflags"), Some(&stdin));
+        assert_eq!(state.regs[&100].get("rdx"), None);
+        assert_eq!(
+            state.regs[&200].iter().collect::<Vec<_>>(),
+            vec![(&"rsi", &stdin)]
+        );
+    }
+
+    #[test]
+    fn sources() {
+        assert_eq!("stdin".parse(), Ok(Source::Stdin));
+        assert_eq!(
+            "file:/etc/passwd".parse(),
+            Ok(Source::File("/etc/passwd".into()))
+        );
+        assert!("bogus".parse::<Source>().is_err());
+    }
+}
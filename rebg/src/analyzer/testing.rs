@@ -0,0 +1,237 @@
+//! Runs `TraceDumper::analyze` on a trace that is already on disk or made up
+//! in a test, in the tracer's wire format so the parser is covered as well.
+
+use super::{dump::TraceDumper, Analysis, Invocation};
+use crate::{
+    arch::Arch,
+    host::native::Native,
+    state::Step,
+    tick::Tick,
+    tracer::{
+        parser::{read_step, Message, RegisterMessage},
+        ParsedStep, Tracer, TracerCmd,
+    },
+};
+use std::{fmt, io::Read, os::unix::process::ExitStatusExt, path::Path, process};
+
+/// Hands out steps that were parsed up front
+struct Replay;
+
+impl<STEP, const N: usize> Tracer<STEP, N> for Replay
+where
+    STEP: Step<N>,
+{
+    type ITER = std::vec::IntoIter<ParsedStep<STEP, N>>;
+
+    fn command(&self, _executable: &Path, _arch: Arch, _localhost: &str) -> TracerCmd<STEP, N> {
+        unreachable!("nothing to launch")
+    }
+
+    fn parse(&self, _proc: process::Child) -> Self::ITER {
+        unreachable!("nothing to launch")
+    }
+}
+
+/// What the tracer sends for a message, the inverse of `get_next_message`.
+/// Only covers what the tests need.
+pub fn encode(msg: &Message) -> Vec<u8> {
+    let mut out = Vec::new();
+    let u64 = |out: &mut Vec<u8>, v: u64| out.extend_from_slice(&v.to_le_bytes());
+    let bytes = |out: &mut Vec<u8>, b: &[u8]| {
+        out.extend_from_slice(&(b.len() as u64).to_le_bytes());
+        out.extend_from_slice(b);
+    };
+
+    match msg {
+        Message::Separator => out.push(0x55),
+        Message::LibLoad(name, from, to) => {
+            out.push(0xee);
+            bytes(&mut out, name.as_bytes());
+            u64(&mut out, *from);
+            u64(&mut out, *to);
+        }
+        Message::Address(adr) => {
+            out.push(0xaa);
+            u64(&mut out, *adr);
+        }
+        Message::Code(code) => {
+            out.push(0xff);
+            bytes(&mut out, code);
+        }
+        Message::Registers(regs) => {
+            out.push(0x77);
+            out.push(regs.regs.len() as u8);
+            u64(&mut out, regs.flags);
+            u64(&mut out, regs.pc);
+            for reg in regs.regs.iter() {
+                u64(&mut out, *reg);
+            }
+        }
+        Message::Load(adr, value, size) | Message::Store(adr, value, size) => {
+            out.push(if matches!(msg, Message::Load(..)) {
+                0x33
+            } else {
+                0x44
+            });
+            out.push(*size);
+            u64(&mut out, *adr);
+            u64(&mut out, *value);
+        }
+        Message::Thread(tid) => {
+            out.push(0x7a);
+            u64(&mut out, *tid);
+        }
+        Message::Process(pid) => {
+            out.push(0x7b);
+            u64(&mut out, *pid);
+        }
+        Message::Signal(signo) => {
+            out.push(0x7c);
+            u64(&mut out, *signo);
+        }
+        Message::Syscall(strace) => {
+            out.push(0x99);
+            bytes(&mut out, strace.as_bytes());
+        }
+        Message::SyscallWrite(adr, data) => {
+            out.push(0x9b);
+            u64(&mut out, *adr);
+            bytes(&mut out, data);
+        }
+        _ => unimplemented!("{:?}", msg),
+    }
+
+    out
+}
+
+/// Parses a whole recording, the same way `GenericParser` splits it into
+/// steps. `status` is the raw wait status the tracer exited with, there's no
+/// final step if the recording is cut off.
+pub fn parse<STEP, const N: usize, R: Read>(mut reader: R, status: i32) -> Vec<ParsedStep<STEP, N>>
+where
+    STEP: Step<N> + fmt::Debug,
+    STEP: for<'a> TryFrom<&'a [Message], Error = anyhow::Error>,
+{
+    let mut steps = Vec::new();
+
+    while let Some(step) = read_step(&mut reader) {
+        if let ParsedStep::Truncated = step {
+            steps.push(step);
+            return steps;
+        }
+        steps.push(step);
+    }
+
+    steps.push(ParsedStep::Final(process::Output {
+        status: process::ExitStatus::from_raw(status),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }));
+    steps
+}
+
+/// Analysis with everything optional turned off, binaries are read from disk
+pub fn analyze<STEP, const N: usize>(
+    steps: Vec<ParsedStep<STEP, N>>,
+    arch: Arch,
+) -> Analysis<STEP, N>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let dumper = TraceDumper {
+        print: false,
+        ltrace: None,
+        heap: false,
+        uninit: false,
+        taint: Vec::new(),
+        watch: Vec::new(),
+        core: Vec::new(),
+    };
+
+    dumper.analyze::<STEP, _, Replay, _, N>(&Native {}, steps.into_iter(), arch)
+}
+
+/// A file in `bins/`
+pub fn bin(name: &str) -> String {
+    format!("{}/../bins/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// A trace in `bins/` recorded with `tools/ptrace/record.c`, its binaries
+/// are looked up in `bins/` too
+pub fn recording<STEP, const N: usize>(name: &str) -> Vec<ParsedStep<STEP, N>>
+where
+    STEP: Step<N> + fmt::Debug,
+    STEP: for<'a> TryFrom<&'a [Message], Error = anyhow::Error>,
+{
+    let raw = std::fs::read(bin(name)).unwrap();
+
+    parse(&raw[..], 0)
+        .into_iter()
+        .map(|step| match step {
+            ParsedStep::LibLoad(map) => ParsedStep::LibLoad(
+                map.into_iter()
+                    .map(|(path, range)| {
+                        let name = Path::new(&path).file_name().unwrap().to_str().unwrap();
+                        (bin(name), range)
+                    })
+                    .collect(),
+            ),
+            step => step,
+        })
+        .collect()
+}
+
+/// Start of a made up trace. It needs some binary, so it's one that stays
+/// out of the way of the code in the steps.
+pub fn libload() -> Vec<u8> {
+    [
+        Message::LibLoad(bin("regs-arm64").into(), 0x1000_0000, 0x1001_0000),
+        Message::Separator,
+    ]
+    .iter()
+    .flat_map(encode)
+    .collect()
+}
+
+/// A made up x86_64 step, all registers zero except the stack pointer
+pub fn x64_step(pc: u64, code: &[u8], extra: &[Message]) -> Vec<u8> {
+    let mut regs = [0; 16];
+    regs[4] = 0x7fff_f000; // rsp
+
+    x64_step_with(pc, regs, code, extra)
+}
+
+/// A made up x86_64 step with all of its registers
+pub fn x64_step_with(pc: u64, regs: [u64; 16], code: &[u8], extra: &[Message]) -> Vec<u8> {
+    let mut msgs = vec![
+        Message::Address(pc),
+        Message::Code(code.into()),
+        Message::Registers(RegisterMessage {
+            pc,
+            flags: 0,
+            regs: regs.into(),
+            bases: Box::new([]),
+        }),
+    ];
+    msgs.extend_from_slice(extra);
+    msgs.push(Message::Separator);
+
+    msgs.iter().flat_map(encode).collect()
+}
+
+/// A call that returned, for analyses that only look at the symbol. Made up
+/// traces don't call anything with a name.
+pub fn invocation(name: &str, call: u64, ret: u64, args: &[u64], ret_value: u64) -> Invocation {
+    Invocation {
+        callee: 0,
+        symbol: Some(name.to_string()),
+        thread: 0,
+        call: Tick(call),
+        ret: Some(Tick(ret)),
+        caller: None,
+        depth: 1,
+        args: args.to_vec(),
+        ret_value: Some(ret_value),
+        observed: true,
+    }
+}
@@ -0,0 +1,293 @@
+use super::{heap, maps::STACK_SIZE, Analysis};
+use crate::{
+    state::{MemoryOpKind, State, Step},
+    tick::{self, Tick},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// A bigger jump of the stack pointer is a stack switch, not a pop
+const MAX_POP: u64 = 64 << 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Memory {
+    Stack,
+    /// index into `HeapReport::allocations`
+    Heap(usize),
+}
+
+/// A read of bytes that were never written since they became part of a stack
+/// frame or heap chunk
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct UninitRead {
+    pub tick: Tick,
+    pub pc: u64,
+    pub location: Option<String>,
+    /// first undefined byte of the read
+    pub address: u64,
+    /// how many of the bytes read were undefined
+    pub undefined: u64,
+    pub memory: Memory,
+}
+
+impl fmt::Display for UninitRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.memory {
+            Memory::Stack => "stack",
+            Memory::Heap(_) => "heap",
+        };
+
+        write!(
+            f,
+            "{:6} read of {} uninitialized {} byte(s) at {:x}",
+            self.tick, self.undefined, what, self.address
+        )?;
+        match &self.location {
+            Some(location) => write!(f, " from {:x} <{}>", self.pc, location),
+            None => write!(f, " from {:x}", self.pc),
+        }
+    }
+}
+
+struct Thread {
+    /// stack pointer at the first step, everything above was set up before
+    /// we started watching
+    top: u64,
+    sp: u64,
+}
+
+/// Checks every read from the stack or a heap chunk against `HistMem`. A
+/// stack byte is born when the stack pointer last moved up past it, a chunk
+/// when malloc returned it. This flags the read itself, not the use of the
+/// value, so copying a struct with padding shows up too.
+pub fn uninit<STEP, const N: usize>(analysis: &Analysis<STEP, N>) -> Vec<UninitRead>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let report = heap::heap(analysis);
+    let mut reads = Vec::new();
+
+    let mut born: BTreeMap<Tick, Vec<usize>> = BTreeMap::new();
+    let mut died: BTreeMap<Tick, Vec<usize>> = BTreeMap::new();
+    for (idx, chunk) in report.allocations.iter().enumerate() {
+        born.entry(chunk.allocated).or_default().push(idx);
+        if let Some(freed) = chunk.freed {
+            died.entry(freed).or_default().push(idx);
+        }
+    }
+
+    let mut threads: HashMap<u64, Thread> = HashMap::new();
+    // per process, stack address -> tick it was last popped
+    let mut popped: HashMap<usize, HashMap<u64, Tick>> = HashMap::new();
+    // per process, chunk start -> index
+    let mut live: HashMap<usize, BTreeMap<u64, usize>> = HashMap::new();
+
+    for (tick, step) in tick::enumerate(&analysis.trace) {
+        let process = analysis.process_of[tick.index()];
+
+        for &idx in died.remove(&tick).iter().flatten() {
+            let chunk = &report.allocations[idx];
+            live.entry(chunk.process)
+                .or_default()
+                .remove(&chunk.address);
+        }
+        for &idx in born.remove(&tick).iter().flatten() {
+            let chunk = &report.allocations[idx];
+            live.entry(chunk.process)
+                .or_default()
+                .insert(chunk.address, idx);
+        }
+
+        let sp = step.state().sp();
+        let thread = threads
+            .entry(step.thread())
+            .or_insert(Thread { top: sp, sp });
+        let popped = popped.entry(process).or_default();
+        if sp > thread.sp && sp - thread.sp <= MAX_POP {
+            for adr in thread.sp..sp {
+                popped.insert(adr, tick);
+            }
+        }
+        thread.sp = sp;
+
+        let live = live.entry(process).or_default();
+        let mem = &analysis.processes[process].mem;
+
+        for op in step.memory_ops() {
+            if op.kind != MemoryOpKind::Read {
+                continue;
+            }
+
+            let memory = if op.address < thread.top && thread.top - op.address <= STACK_SIZE {
+                Memory::Stack
+            } else {
+                match live.range(..=op.address).next_back() {
+                    Some((_, &idx)) if op.address < report.allocations[idx].end() => {
+                        Memory::Heap(idx)
+                    }
+                    _ => continue,
+                }
+            };
+
+            // tick after which a byte needs a write to be defined, `None` if
+            // it has been defined all along
+            let birth = |adr: u64| match memory {
+                Memory::Stack => Some(popped.get(&adr).copied().unwrap_or_default()),
+                Memory::Heap(idx) => {
+                    let chunk = &report.allocations[idx];
+                    (adr >= chunk.address + chunk.initialized).then_some(chunk.allocated)
+                }
+            };
+
+            let undefined: Vec<u64> = (op.address..op.address + op.value.size())
+                .filter(|&adr| {
+                    // stores land at tick + 1, see `HistMem`
+                    birth(adr)
+                        .is_some_and(|birth| mem.written_at(tick, adr).is_none_or(|w| w <= birth))
+                })
+                .collect();
+
+            let Some(&address) = undefined.first() else {
+                continue;
+            };
+
+            let pc = step.state().pc();
+            reads.push(UninitRead {
+                tick,
+                pc,
+                location: analysis.processes[process]
+                    .table
+                    .lookup_at(pc, tick)
+                    .map(|s| s.to_string()),
+                address,
+                undefined: undefined.len() as u64,
+                memory,
+            });
+        }
+    }
+
+    reads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{uninit, Memory};
+    use crate::{
+        analyzer::testing, arch::Arch, state::X64Step, tick::Tick, tracer::parser::Message,
+    };
+
+    const TOP: u64 = 0x7fff_f000;
+
+    /// `(tick, address, undefined, memory)` of the uninitialized reads in a
+    /// made up trace, with a stack pointer and memory accesses per step and
+    /// the heap calls put in by hand
+    fn reads(
+        steps: &[(u64, &[Message])],
+        calls: &[(&str, u64, u64, &[u64], u64)],
+    ) -> Vec<(Tick, u64, u64, Memory)> {
+        let mut raw = testing::libload();
+        for (i, &(sp, extra)) in steps.iter().enumerate() {
+            let mut regs = [0; 16];
+            regs[4] = sp;
+            raw.extend(testing::x64_step_with(
+                0x401000 + i as u64,
+                regs,
+                &[0x90],
+                extra,
+            ));
+        }
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let mut analysis = testing::analyze(steps, Arch::X86_64);
+        analysis.invocations = calls
+            .iter()
+            .map(|&(name, call, ret, args, ret_value)| {
+                testing::invocation(name, call, ret, args, ret_value)
+            })
+            .collect();
+
+        uninit(&analysis)
+            .into_iter()
+            .map(|r| (r.tick, r.address, r.undefined, r.memory))
+            .collect()
+    }
+
+    #[test]
+    fn stack() {
+        let below = TOP - 0x10;
+        let reads = reads(
+            &[
+                (TOP, &[]),
+                // below the stack pointer, nothing was ever there
+                (TOP, &[Message::Load(below, 0, 8)]),
+                // sub rsp, 0x10 and a write
+                (below, &[Message::Store(below, 1, 8)]),
+                (below, &[Message::Load(below, 1, 8)]),
+                // popped and pushed again, the old write doesn't count
+                (TOP, &[]),
+                (below, &[]),
+                (below, &[Message::Load(below, 1, 4)]),
+                // set up before the trace started
+                (below, &[Message::Load(TOP, 0, 8)]),
+            ],
+            &[],
+        );
+
+        assert_eq!(
+            reads,
+            vec![
+                (Tick(1), below, 8, Memory::Stack),
+                (Tick(6), below, 4, Memory::Stack),
+            ]
+        );
+    }
+
+    #[test]
+    fn malloc() {
+        let reads = reads(
+            &[
+                (TOP, &[]),
+                (TOP, &[]),
+                (TOP, &[Message::Load(0x5000, 0, 8)]),
+                (TOP, &[Message::Store(0x5000, 1, 8)]),
+                (TOP, &[Message::Load(0x5000, 1, 8)]),
+                // only half of it was written
+                (TOP, &[Message::Load(0x5004, 0, 8)]),
+            ],
+            &[("malloc", 0, 1, &[16], 0x5000)],
+        );
+
+        assert_eq!(
+            reads,
+            vec![
+                (Tick(2), 0x5000, 8, Memory::Heap(0)),
+                (Tick(5), 0x5008, 4, Memory::Heap(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn calloc_and_realloc() {
+        let reads = reads(
+            &[
+                (TOP, &[]),
+                (TOP, &[Message::Load(0x6008, 0, 8)]),
+                (TOP, &[Message::Store(0x7000, 1, 8)]),
+                (TOP, &[]),
+                (TOP, &[]),
+                // the first 8 bytes came from the old chunk
+                (TOP, &[Message::Load(0x8000, 1, 8)]),
+                (TOP, &[Message::Load(0x8008, 0, 8)]),
+            ],
+            &[
+                ("calloc", 0, 1, &[2, 8], 0x6000),
+                ("malloc", 1, 2, &[8], 0x7000),
+                ("realloc", 3, 4, &[0x7000, 16], 0x8000),
+            ],
+        );
+
+        assert_eq!(reads, vec![(Tick(6), 0x8008, 8, Memory::Heap(2))]);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    state::{MemoryOpKind, Step},
+    tick::{self, Tick},
+};
+use std::{collections::BTreeMap, fmt, ops::Range, str::FromStr};
+
+/// Nothing accesses more than this many bytes at once, except syscalls
+const MAX_ACCESS: u64 = 8;
+
+/// A single memory access of a step
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Access {
+    pub tick: Tick,
+    pub kind: MemoryOpKind,
+    pub address: u64,
+    pub size: u64,
+    /// `None` for memory written by a syscall
+    pub value: Option<u64>,
+}
+
+impl Access {
+    fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.address < to && from < self.address + self.size
+    }
+}
+
+/// Every memory access of the trace, by address. Built once, so finding who
+/// touched a buffer doesn't need a pass over the trace.
+#[derive(Clone, Debug, Default)]
+pub struct AccessIndex {
+    accesses: Vec<Access>,
+    /// (process, address) -> indices into `accesses`, in tick order
+    by_address: BTreeMap<(usize, u64), Vec<usize>>,
+    /// syscalls can write a lot more than `MAX_ACCESS` at once, so they are
+    /// checked separately: process -> indices into `accesses`
+    large: BTreeMap<usize, Vec<usize>>,
+}
+
+impl AccessIndex {
+    /// `process_of` maps ticks to processes, since each has its own memory
+    pub fn new<STEP, const N: usize>(trace: &[STEP], process_of: &[usize]) -> Self
+    where
+        STEP: Step<N>,
+    {
+        let mut index = Self::default();
+
+        for (tick, step) in tick::enumerate(trace) {
+            let process = process_of[tick.index()];
+
+            for op in step.memory_ops() {
+                index.push(
+                    process,
+                    Access {
+                        tick,
+                        kind: op.kind,
+                        address: op.address,
+                        size: op.value.size(),
+                        value: Some(op.value.as_u64()),
+                    },
+                );
+            }
+
+            for write in step.syscall_writes() {
+                index.push(
+                    process,
+                    Access {
+                        tick,
+                        kind: MemoryOpKind::Write,
+                        address: write.address,
+                        size: write.bytes.len() as u64,
+                        value: None,
+                    },
+                );
+            }
+        }
+
+        index
+    }
+
+    fn push(&mut self, process: usize, access: Access) {
+        let idx = self.accesses.len();
+        if access.size > MAX_ACCESS {
+            self.large.entry(process).or_default().push(idx);
+        } else {
+            self.by_address
+                .entry((process, access.address))
+                .or_default()
+                .push(idx);
+        }
+        self.accesses.push(access);
+    }
+
+    /// Indices of everything touching [from, to) in `process`, unordered
+    fn overlapping(&self, process: usize, from: u64, to: u64) -> Vec<usize> {
+        let start = (process, from.saturating_sub(MAX_ACCESS - 1));
+        let end = (process, to);
+
+        let small = self
+            .by_address
+            .range(start..end)
+            .flat_map(|(_, indices)| indices.iter().copied());
+        let large = self.large.get(&process).into_iter().flatten().copied();
+
+        small
+            .chain(large)
+            .filter(|&idx| self.accesses[idx].overlaps(from, to))
+            .collect()
+    }
+
+    /// Accesses to [from, from + len) during `ticks`, in tick order
+    pub fn accesses(
+        &self,
+        process: usize,
+        from: u64,
+        len: u64,
+        ticks: Range<Tick>,
+    ) -> Vec<&Access> {
+        let mut found: Vec<_> = self
+            .overlapping(process, from, from + len)
+            .into_iter()
+            .map(|idx| &self.accesses[idx])
+            .filter(|a| ticks.contains(&a.tick))
+            .collect();
+        found.sort_by_key(|a| a.tick);
+        found
+    }
+
+    /// The last write to any of [from, from + len) before `tick`
+    pub fn last_write(&self, process: usize, from: u64, len: u64, tick: Tick) -> Option<&Access> {
+        self.overlapping(process, from, from + len)
+            .into_iter()
+            .map(|idx| &self.accesses[idx])
+            .filter(|a| a.kind == MemoryOpKind::Write && a.tick < tick)
+            .max_by_key(|a| a.tick)
+    }
+}
+
+/// `<addr>[+<len>][@<from>..<to>]`, e.g. `0x404040+16@100..2000`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub address: u64,
+    pub len: u64,
+    pub ticks: Option<Range<Tick>>,
+}
+
+impl FromStr for Watch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("bad watch: {}, expected <addr>[+<len>][@<from>..<to>]", s);
+        let number = |s: &str| match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        };
+
+        let (range, ticks) = match s.split_once('@') {
+            Some((range, ticks)) => {
+                let (from, to) = ticks.split_once("..").ok_or_else(err)?;
+                let from = from.parse().map_err(|_| err())?;
+                let to = to.parse().map_err(|_| err())?;
+                (range, Some(from..to))
+            }
+            None => (s, None),
+        };
+
+        let (address, len) = match range.split_once('+') {
+            Some((address, len)) => (number(address), number(len)),
+            None => (number(range), Some(1)),
+        };
+
+        Ok(Self {
+            address: address.ok_or_else(err)?,
+            len: len.ok_or_else(err)?,
+            ticks,
+        })
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}+{}", self.address, self.len)?;
+        if let Some(ticks) = &self.ticks {
+            write!(f, "@{}..{}", ticks.start, ticks.end)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, AccessIndex, Watch};
+    use crate::{state::MemoryOpKind, tick::Tick};
+
+    #[test]
+    fn lookups() {
+        let mut index = AccessIndex::default();
+        let mut access = |tick, kind, address, size| {
+            index.push(
+                0,
+                Access {
+                    tick: Tick(tick),
+                    kind,
+                    address,
+                    size,
+                    value: None,
+                },
+            )
+        };
+        access(1, MemoryOpKind::Write, 0x1000, 8);
+        access(2, MemoryOpKind::Read, 0x1004, 4);
+        access(3, MemoryOpKind::Write, 0x0ffc, 4);
+        access(4, MemoryOpKind::Write, 0x0f00, 0x200);
+        access(5, MemoryOpKind::Read, 0x1008, 1);
+
+        let ticks = |found: Vec<&Access>| found.iter().map(|a| a.tick.0).collect::<Vec<_>>();
+        assert_eq!(
+            ticks(index.accesses(0, 0x1000, 4, Tick(0)..Tick(10))),
+            vec![1, 4]
+        );
+        assert_eq!(
+            ticks(index.accesses(0, 0x1006, 1, Tick(0)..Tick(10))),
+            vec![1, 2, 4]
+        );
+        assert_eq!(
+            ticks(index.accesses(0, 0x1000, 4, Tick(2)..Tick(10))),
+            vec![4]
+        );
+        assert!(index.accesses(1, 0x1000, 4, Tick(0)..Tick(10)).is_empty());
+
+        assert_eq!(
+            index.last_write(0, 0x1004, 1, Tick(4)).map(|a| a.tick),
+            Some(Tick(1))
+        );
+        assert_eq!(
+            index.last_write(0, 0x1008, 1, Tick(4)).map(|a| a.tick),
+            None
+        );
+        assert_eq!(
+            index.last_write(0, 0x1008, 1, Tick(5)).map(|a| a.tick),
+            Some(Tick(4))
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "0x40+16@10..20".parse(),
+            Ok(Watch {
+                address: 0x40,
+                len: 16,
+                ticks: Some(Tick(10)..Tick(20))
+            })
+        );
+        assert_eq!(
+            "64".parse(),
+            Ok(Watch {
+                address: 64,
+                len: 1,
+                ticks: None
+            })
+        );
+        assert!("0x40@10".parse::<Watch>().is_err());
+    }
+}
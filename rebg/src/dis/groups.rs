@@ -144,6 +144,14 @@ impl Group {
         )
     }
 
+    /// Any kind of jump, conditional or not
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Group::Aarch64Group(Aarch64Group::Jump) | Group::X64Group(X64Group::Jump)
+        )
+    }
+
     pub fn is_ret(&self) -> bool {
         matches!(
             self,
@@ -1,6 +1,7 @@
 #![allow(clippy::derive_partial_eq_without_eq)]
 #![allow(clippy::from_str_radix_10)]
 
+pub mod abi;
 pub mod analyzer;
 pub mod arch;
 pub mod binary;
@@ -9,6 +10,9 @@ pub mod host;
 pub mod mem;
 pub mod rstate;
 pub mod serve;
+pub mod signal;
 pub mod state;
 pub mod syms;
+pub mod syscall;
+pub mod tick;
 pub mod tracer;
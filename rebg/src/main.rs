@@ -1,10 +1,13 @@
 use object::Object;
 use rebg::analyzer::dump::TraceDumper;
+use rebg::analyzer::taint::Source;
+use rebg::analyzer::watch::Watch;
 use rebg::binary::Binary;
 use rebg::host::docker::{Docker, DockerArgs};
 use rebg::host::native::{Native, NativeArgs};
 use rebg::serve;
 use rebg::state::{Aarch64Step, Step, X64Step};
+use rebg::tick::Tick;
 use rebg::tracer::parser::{GenericParser, Message};
 use rebg::tracer::qiling::Qiling;
 use rebg::tracer::TracerCmd;
@@ -37,10 +40,41 @@ struct Arguments {
     /// quit instead of opening a ws server
     quit: bool,
 
+    #[argh(option, long = "gdb")]
+    /// serve the trace to gdb on this port instead of opening a ws server
+    gdb: Option<u16>,
+
     #[argh(switch, short = 'p', long = "print")]
     /// print trace
     print: bool,
 
+    #[argh(option, long = "ltrace")]
+    /// write the library calls (ltrace style) to this file
+    ltrace: Option<PathBuf>,
+
+    #[argh(switch, long = "heap")]
+    /// report heap misuse (use after free, double free, overflows)
+    heap: bool,
+
+    #[argh(switch, long = "uninit")]
+    /// report reads of uninitialized stack and heap memory
+    uninit: bool,
+
+    #[argh(option, long = "taint")]
+    /// taint data from a source (stdin, file:<path>, recv, argv, env) and
+    /// report the branches depending on it, can be repeated
+    taint: Vec<Source>,
+
+    #[argh(option, long = "watch")]
+    /// print every access to <addr>[+<len>][@<from>..<to>], starting with the
+    /// last write before <from>, can be repeated
+    watch: Vec<Watch>,
+
+    #[argh(option, long = "core")]
+    /// write an ELF core file of the state at this tick to core.<tick>, can be
+    /// repeated
+    core: Vec<Tick>,
+
     #[argh(option, short = 'a')]
     /// override detected architecture (arm64, amd64, ...)
     target_arch: Option<Arch>,
@@ -107,10 +141,17 @@ fn main() {
     let Arguments {
         program,
         quit,
+        gdb,
         target_arch,
         launcher,
         tracer,
         print,
+        ltrace,
+        heap,
+        uninit,
+        taint,
+        watch,
+        core,
     } = argh::from_env();
 
     let bin = {
@@ -122,7 +163,15 @@ fn main() {
 
     let launcher = launcher.start_tracer(program.clone(), target_arch);
 
-    let dumper = TraceDumper { print };
+    let dumper = TraceDumper {
+        print,
+        ltrace,
+        heap,
+        uninit,
+        taint,
+        watch,
+        core,
+    };
 
     match target_arch {
         Arch::ARM64 => match tracer {
@@ -131,6 +180,7 @@ fn main() {
                 analyze_arch::<Aarch64Step, QEMU, 32>(
                     &dumper,
                     quit,
+                    gdb,
                     &launcher,
                     qemu,
                     target_arch,
@@ -142,6 +192,7 @@ fn main() {
                 analyze_arch::<Aarch64Step, Qiling, 32>(
                     &dumper,
                     quit,
+                    gdb,
                     &launcher,
                     qiling,
                     target_arch,
@@ -155,6 +206,7 @@ fn main() {
                 analyze_arch::<X64Step, QEMU, 16>(
                     &dumper,
                     quit,
+                    gdb,
                     &launcher,
                     qemu,
                     target_arch,
@@ -166,6 +218,7 @@ fn main() {
                 analyze_arch::<X64Step, Qiling, 16>(
                     &dumper,
                     quit,
+                    gdb,
                     &launcher,
                     qiling,
                     target_arch,
@@ -179,6 +232,7 @@ fn main() {
 fn analyze_arch<STEP, TRACER, const N: usize>(
     dumper: &TraceDumper,
     quit: bool,
+    gdb: Option<u16>,
     launcher: &Launchers,
     tracer: TRACER,
     target_arch: Arch,
@@ -190,7 +244,9 @@ fn analyze_arch<STEP, TRACER, const N: usize>(
 {
     let parser = launch_qemu::<_, _, STEP, N>(launcher, tracer, target_arch, program);
     let analysis = dumper.analyze::<_, _, QEMU, _, N>(launcher, parser, target_arch);
-    if !quit {
+    if let Some(port) = gdb {
+        serve::gdb(analysis, target_arch, port);
+    } else if !quit {
         serve::ws(analysis, target_arch);
     }
 }
@@ -1,74 +1,219 @@
-use std::collections::HashMap;
+use crate::tick::Tick;
+use std::{collections::BTreeMap, ops::Range};
+
+/// Cells are grouped into pages, so ranges can be walked without a lookup
+/// per byte
+const PAGE_SIZE: u64 = 0x1000;
 
 #[derive(Debug, Clone)]
 pub struct MCell {
-    values: Vec<(u32, u8)>,
+    values: Vec<(Tick, u8)>,
+    /// values seen by loads, kept apart so we still know what was written
+    observed: Vec<(Tick, u8)>,
 }
 
-impl MCell {
-    fn new() -> Self {
-        Self { values: Vec::new() }
+/// Adds to a list of (tick, value) that is sorted by tick
+fn push(values: &mut Vec<(Tick, u8)>, tick: Tick, value: u8) -> Result<(), ()> {
+    // is there already a tick after what we're adding?
+    if Some(tick) < values.last().map(|x| x.0) {
+        return Err(());
     }
 
-    fn add_tick(&mut self, tick: u32, value: u8) -> Result<(), ()> {
-        // is there already a tick after what we're adding?
-        if Some(tick) < self.values.last().map(|x| x.0) {
-            return Err(());
+    // is the previous tick equal to the current tick?
+    if let Some((t, v)) = values.last_mut() {
+        // note,       /\ this is not a comparison
+        if *t == tick {
+            *v = value;
+            return Ok(());
         }
+    }
 
-        // is the previous tick equal to the current tick?
-        if let Some((t, v)) = self.values.last_mut() {
-            // note,       /\ this is not a comparison
-            if *t == tick {
-                *v = value;
-                return Ok(());
-            }
+    // otherwise, we can just push!
+    values.push((tick, value));
+    Ok(())
+}
+
+/// The last (tick, value) at or before `tick`
+fn last(values: &[(Tick, u8)], tick: Tick) -> Option<(Tick, u8)> {
+    let idx = values.partition_point(|(t, _v)| *t <= tick);
+
+    // we get idx 1 too high
+    // so if it's 0, that means no too early (cus index -1)
+    idx.checked_sub(1).map(|idx| values[idx])
+}
+
+impl MCell {
+    fn new() -> Self {
+        Self {
+            values: Vec::new(),
+            observed: Vec::new(),
         }
+    }
 
-        // otherwise, we can just push!
-        self.values.push((tick, value));
-        Ok(())
+    fn add_tick(&mut self, tick: Tick, value: u8) -> Result<(), ()> {
+        push(&mut self.values, tick, value)
     }
 
-    fn at_tick(&self, tick: u32) -> Option<u8> {
-        let idx = self.values.partition_point(|(t, _v)| *t <= tick);
+    /// A load saw `value` at `tick`, only kept if it tells us something new
+    fn add_observed(&mut self, tick: Tick, value: u8) -> Result<(), ()> {
+        if self.at_tick(tick) == Some(value) {
+            return Ok(());
+        }
+
+        push(&mut self.observed, tick, value)
+    }
 
-        // we get idx 1 too high
-        // so if it's 0, that means no too early (cus index -1)
-        if idx == 0 {
-            return None;
+    /// The last known value, whether it was written or observed
+    fn at_tick(&self, tick: Tick) -> Option<u8> {
+        match (last(&self.values, tick), last(&self.observed, tick)) {
+            (Some((wt, w)), Some((ot, _))) if wt > ot => Some(w),
+            (_, Some((_, o))) => Some(o),
+            (Some((_, w)), None) => Some(w),
+            (None, None) => None,
         }
+    }
 
-        Some(self.values.get(idx - 1).unwrap().1)
+    /// The tick of the store that `at_tick` would return
+    fn written_at(&self, tick: Tick) -> Option<Tick> {
+        last(&self.values, tick).map(|(t, _v)| t)
     }
+
+    /// Every value the cell took on, in tick order
+    fn history(&self) -> Vec<(Tick, u8)> {
+        let mut ticks: Vec<Tick> = self
+            .values
+            .iter()
+            .chain(&self.observed)
+            .map(|(t, _v)| *t)
+            .collect();
+        ticks.sort_unstable();
+        ticks.dedup();
+
+        let mut history: Vec<(Tick, u8)> = Vec::new();
+        for tick in ticks {
+            let value = self.at_tick(tick).expect("logic error");
+            if history.last().map(|(_t, v)| *v) != Some(value) {
+                history.push((tick, value));
+            }
+        }
+        history
+    }
+}
+
+type Page = Box<[Option<MCell>]>;
+
+fn split(adr: u64) -> (u64, usize) {
+    (adr / PAGE_SIZE, (adr % PAGE_SIZE) as usize)
 }
 
 #[derive(Debug, Clone)]
 pub struct HistMem {
-    // ptr -> value
-    cells: HashMap<u64, MCell>,
+    // page number -> cell per byte
+    pages: BTreeMap<u64, Page>,
 }
 
 impl HistMem {
     pub fn new() -> Self {
         Self {
-            cells: HashMap::new(),
+            pages: BTreeMap::new(),
+        }
+    }
+
+    fn cell(&self, adr: u64) -> Option<&MCell> {
+        let (page, offset) = split(adr);
+        self.pages.get(&page)?[offset].as_ref()
+    }
+
+    fn cell_mut(&mut self, adr: u64) -> &mut MCell {
+        let (page, offset) = split(adr);
+        self.pages
+            .entry(page)
+            .or_insert_with(|| (0..PAGE_SIZE).map(|_| None).collect())[offset]
+            .get_or_insert_with(MCell::new)
+    }
+
+    /// Cells of [range.start, range.end) that were ever touched, in order
+    fn cells(&self, range: Range<u64>) -> impl Iterator<Item = (u64, &MCell)> {
+        // `BTreeMap::range` panics on backwards ranges
+        let pages = (!range.is_empty()).then(|| split(range.start).0..=split(range.end - 1).0);
+        pages
+            .into_iter()
+            .flat_map(|pages| self.pages.range(pages))
+            .flat_map(|(&page, cells)| {
+                cells.iter().enumerate().filter_map(move |(offset, cell)| {
+                    Some((page * PAGE_SIZE + offset as u64, cell.as_ref()?))
+                })
+            })
+            .filter(move |(adr, _cell)| range.contains(adr))
+    }
+
+    pub fn load8(&self, tick: Tick, adr: u64) -> Option<u8> {
+        self.cell(adr)?.at_tick(tick)
+    }
+
+    /// When the last store to the byte before `tick` happened, loads don't
+    /// count
+    pub fn written_at(&self, tick: Tick, adr: u64) -> Option<Tick> {
+        self.cell(adr)?.written_at(tick)
+    }
+
+    /// `len` bytes starting at `adr`, `None` for the ones we know nothing of
+    pub fn load_range(&self, tick: Tick, adr: u64, len: u64) -> Vec<Option<u8>> {
+        let mut bytes = vec![None; len as usize];
+        for (at, cell) in self.cells(adr..adr + len) {
+            bytes[(at - adr) as usize] = cell.at_tick(tick);
         }
+        bytes
     }
 
-    pub fn load8(&self, tick: u32, adr: u64) -> Option<u8> {
-        self.cells.get(&adr)?.at_tick(tick)
+    /// Every value the byte at `adr` took on, written or observed, as
+    /// (tick, value) in tick order
+    pub fn history(&self, adr: u64) -> Vec<(Tick, u8)> {
+        self.cell(adr).map(MCell::history).unwrap_or_default()
     }
 
-    pub fn load16(&self, tick: u32, adr: u64) -> Option<u16> {
-        Some(u16::from_be_bytes([
+    /// Runs of bytes in `range` that differ between `from` and `to`
+    pub fn changed_between(&self, from: Tick, to: Tick, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        for (adr, cell) in self.cells(range) {
+            if cell.at_tick(from) == cell.at_tick(to) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if run.end == adr => run.end += 1,
+                _ => runs.push(adr..adr + 1),
+            }
+        }
+        runs
+    }
+
+    /// Page aligned runs of [range.start, range.end) that we know anything
+    /// about at all, at any tick
+    pub fn touched(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        if range.is_empty() {
+            return runs;
+        }
+        let pages = range.start / PAGE_SIZE..=(range.end - 1) / PAGE_SIZE;
+        for &page in self.pages.range(pages).map(|(page, _)| page) {
+            let (start, end) = (page * PAGE_SIZE, (page + 1) * PAGE_SIZE);
+            match runs.last_mut() {
+                Some(run) if run.end == start => run.end = end,
+                _ => runs.push(start..end),
+            }
+        }
+        runs
+    }
+
+    pub fn load16(&self, tick: Tick, adr: u64) -> Option<u16> {
+        Some(u16::from_le_bytes([
             self.load8(tick, adr)?,
             self.load8(tick, adr + 1)?,
         ]))
     }
 
-    pub fn load32(&self, tick: u32, adr: u64) -> Option<u32> {
-        Some(u32::from_be_bytes([
+    pub fn load32(&self, tick: Tick, adr: u64) -> Option<u32> {
+        Some(u32::from_le_bytes([
             self.load8(tick, adr)?,
             self.load8(tick, adr + 1)?,
             self.load8(tick, adr + 2)?,
@@ -76,8 +221,8 @@ impl HistMem {
         ]))
     }
 
-    pub fn load64(&self, tick: u32, adr: u64) -> Option<u64> {
-        Some(u64::from_be_bytes([
+    pub fn load64(&self, tick: Tick, adr: u64) -> Option<u64> {
+        Some(u64::from_le_bytes([
             self.load8(tick, adr)?,
             self.load8(tick, adr + 1)?,
             self.load8(tick, adr + 2)?,
@@ -89,107 +234,206 @@ impl HistMem {
         ]))
     }
 
-    pub fn store8(&mut self, tick: u32, adr: u64, val: u8) -> Result<(), ()> {
-        self.cells.entry(adr).or_insert_with(MCell::new);
-
-        let cell = self.cells.get_mut(&adr).expect("logic error");
+    pub fn store8(&mut self, tick: Tick, adr: u64, val: u8) -> Result<(), ()> {
+        self.cell_mut(adr).add_tick(tick, val)
+    }
 
-        cell.add_tick(tick, val)?;
+    // TODO, <T: Num> or something?
+    pub fn store16(&mut self, tick: Tick, adr: u64, val: u16) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
+            self.store8(tick, adr + offset as u64, byte)?;
+        }
         Ok(())
     }
 
-    // TODO, <T: Num> or something?
-    pub fn store16(&mut self, tick: u32, adr: u64, val: u16) -> Result<(), ()> {
-        for (offset, byte) in val.to_be_bytes().into_iter().enumerate() {
+    pub fn store32(&mut self, tick: Tick, adr: u64, val: u32) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
             self.store8(tick, adr + offset as u64, byte)?;
         }
         Ok(())
     }
 
-    pub fn store32(&mut self, tick: u32, adr: u64, val: u32) -> Result<(), ()> {
-        for (offset, byte) in val.to_be_bytes().into_iter().enumerate() {
+    pub fn store64(&mut self, tick: Tick, adr: u64, val: u64) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
             self.store8(tick, adr + offset as u64, byte)?;
         }
         Ok(())
     }
 
-    pub fn store64(&mut self, tick: u32, adr: u64, val: u64) -> Result<(), ()> {
-        for (offset, byte) in val.to_be_bytes().into_iter().enumerate() {
+    /// Raw bytes written at `tick` all at once, e.g. by a syscall
+    pub fn store_bytes(&mut self, tick: Tick, adr: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (offset, &byte) in bytes.iter().enumerate() {
             self.store8(tick, adr + offset as u64, byte)?;
         }
         Ok(())
     }
+
+    /// A load read `val` at `tick`. Not a write, but `load8` returns it until
+    /// something newer comes along.
+    pub fn observe8(&mut self, tick: Tick, adr: u64, val: u8) -> Result<(), ()> {
+        self.cell_mut(adr).add_observed(tick, val)
+    }
+
+    /// Raw bytes we know are there at `tick`, e.g. from the file a region
+    /// was mapped from
+    pub fn observe_bytes(&mut self, tick: Tick, adr: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.observe8(tick, adr + offset as u64, byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn observe16(&mut self, tick: Tick, adr: u64, val: u16) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
+            self.observe8(tick, adr + offset as u64, byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn observe32(&mut self, tick: Tick, adr: u64, val: u32) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
+            self.observe8(tick, adr + offset as u64, byte)?;
+        }
+        Ok(())
+    }
+
+    pub fn observe64(&mut self, tick: Tick, adr: u64, val: u64) -> Result<(), ()> {
+        for (offset, byte) in val.to_le_bytes().into_iter().enumerate() {
+            self.observe8(tick, adr + offset as u64, byte)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::HistMem;
+    use crate::tick::Tick;
 
     use super::MCell;
 
     #[test]
     fn ticks() {
-        let mut cell = MCell { values: vec![] };
-        cell.add_tick(4, 0x11).unwrap();
-        cell.add_tick(7, 0x22).unwrap();
-        cell.add_tick(8, 0x33).unwrap();
+        let mut cell = MCell {
+            values: vec![],
+            observed: vec![],
+        };
+        cell.add_tick(Tick(4), 0x11).unwrap();
+        cell.add_tick(Tick(7), 0x22).unwrap();
+        cell.add_tick(Tick(8), 0x33).unwrap();
 
         dbg!(&cell);
 
-        assert_eq!(cell.at_tick(0), None);
-        assert_eq!(cell.at_tick(3), None);
-        assert_eq!(cell.at_tick(4), Some(0x11));
-        assert_eq!(cell.at_tick(5), Some(0x11));
-        assert_eq!(cell.at_tick(6), Some(0x11));
-        assert_eq!(cell.at_tick(7), Some(0x22));
-        assert_eq!(cell.at_tick(8), Some(0x33));
-        assert_eq!(cell.at_tick(9), Some(0x33));
-        assert_eq!(cell.at_tick(9999999), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(0)), None);
+        assert_eq!(cell.at_tick(Tick(3)), None);
+        assert_eq!(cell.at_tick(Tick(4)), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(5)), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(6)), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(7)), Some(0x22));
+        assert_eq!(cell.at_tick(Tick(8)), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(9)), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(9999999)), Some(0x33));
+
+        assert_eq!(cell.written_at(Tick(3)), None);
+        assert_eq!(cell.written_at(Tick(6)), Some(Tick(4)));
+        assert_eq!(cell.written_at(Tick(9)), Some(Tick(8)));
+    }
+
+    #[test]
+    fn observed() {
+        let mut m = HistMem::new();
+        m.observe8(Tick(3), 0x10, 0xaa).unwrap();
+        m.store8(Tick(5), 0x10, 0xbb).unwrap();
+        m.observe8(Tick(8), 0x10, 0xcc).unwrap();
+
+        assert_eq!(m.load8(Tick(2), 0x10), None);
+        assert_eq!(m.load8(Tick(3), 0x10), Some(0xaa));
+        assert_eq!(m.load8(Tick(6), 0x10), Some(0xbb));
+        assert_eq!(m.load8(Tick(8), 0x10), Some(0xcc));
+
+        // loads aren't writes
+        assert_eq!(m.written_at(Tick(9), 0x10), Some(Tick(5)));
+        assert_eq!(m.written_at(Tick(4), 0x10), None);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut m = HistMem::new();
+        // straddles a page boundary
+        m.store_bytes(Tick(1), 0xffe, &[1, 2, 3, 4]).unwrap();
+        m.observe8(Tick(2), 0x1001, 3).unwrap();
+        m.store8(Tick(3), 0x1000, 5).unwrap();
+        m.observe8(Tick(4), 0x1000, 6).unwrap();
+
+        assert_eq!(
+            m.load_range(Tick(2), 0xffd, 6),
+            vec![None, Some(1), Some(2), Some(3), Some(3), None]
+        );
+        assert_eq!(
+            m.history(0x1000),
+            vec![(Tick(1), 3), (Tick(3), 5), (Tick(4), 6)]
+        );
+        assert_eq!(m.history(0x1001), vec![(Tick(1), 4), (Tick(2), 3)]);
+        assert_eq!(m.history(0x2000), vec![]);
+
+        assert_eq!(
+            m.changed_between(Tick(0), Tick(4), 0..0x2000),
+            vec![0xffe..0x1002]
+        );
+        assert_eq!(
+            m.changed_between(Tick(1), Tick(4), 0..0x2000),
+            vec![0x1000..0x1002]
+        );
+        assert_eq!(
+            m.changed_between(Tick(1), Tick(4), 0x1001..0x1002),
+            vec![0x1001..0x1002]
+        );
+        assert_eq!(m.changed_between(Tick(4), Tick(4), 0..0x2000), vec![]);
     }
 
     #[test]
     fn overlapping_stores() {
         let mut m = HistMem::new();
-        m.store64(0, 0x1234, 0x1111111111111111).unwrap();
-        m.store64(0, 0x1234, 0x2222222222222222).unwrap();
+        m.store64(Tick(0), 0x1234, 0x1111111111111111).unwrap();
+        m.store64(Tick(0), 0x1234, 0x2222222222222222).unwrap();
 
-        assert_eq!(m.load64(0, 0x1234), Some(0x2222222222222222))
+        assert_eq!(m.load64(Tick(0), 0x1234), Some(0x2222222222222222))
     }
 
     #[test]
     fn stores() {
-        const TICK: u32 = 555;
+        const TICK: Tick = Tick(555);
         let mut v = HistMem::new();
 
-        v.store64(TICK - 1, 0, 0x1111111111111111).unwrap();
-        v.store64(TICK - 1, 8, 0x2222222222222222).unwrap();
+        v.store64(Tick(TICK.0 - 1), 0, 0x1111111111111111).unwrap();
+        v.store64(Tick(TICK.0 - 1), 8, 0x2222222222222222).unwrap();
 
         // u64
         v.store64(TICK, 1, 0xFFFFFFFFFFFFFFFF).unwrap();
 
-        assert_eq!(v.load64(TICK, 0), Some(0x11FFFFFFFFFFFFFF));
-        assert_eq!(v.load64(TICK, 8), Some(0xFF22222222222222));
+        assert_eq!(v.load64(TICK, 0), Some(0xFFFFFFFFFFFFFF11));
+        assert_eq!(v.load64(TICK, 8), Some(0x22222222222222FF));
 
         // u32
         v.store32(TICK + 1, 7, 0x77777777).unwrap();
-        assert_eq!(v.load64(TICK + 1, 0), Some(0x11FFFFFFFFFFFF77));
-        assert_eq!(v.load64(TICK + 1, 8), Some(0x7777772222222222));
+        assert_eq!(v.load64(TICK + 1, 0), Some(0x77FFFFFFFFFFFF11));
+        assert_eq!(v.load64(TICK + 1, 8), Some(0x2222222222777777));
 
         v.store32(TICK + 2, 1, 0x44444444).unwrap();
-        assert_eq!(v.load64(TICK + 2, 0), Some(0x1144444444FFFF77));
-        assert_eq!(v.load64(TICK + 2, 8), Some(0x7777772222222222));
+        assert_eq!(v.load64(TICK + 2, 0), Some(0x77FFFF4444444411));
+        assert_eq!(v.load64(TICK + 2, 8), Some(0x2222222222777777));
 
         v.store32(TICK + 3, 2, 0x55555555).unwrap();
-        assert_eq!(v.load64(TICK + 3, 0), Some(0x114455555555FF77));
-        assert_eq!(v.load64(TICK + 3, 8), Some(0x7777772222222222));
+        assert_eq!(v.load64(TICK + 3, 0), Some(0x77FF555555554411));
+        assert_eq!(v.load64(TICK + 3, 8), Some(0x2222222222777777));
 
         v.store32(TICK + 4, 5, 0x66666666).unwrap();
-        assert_eq!(v.load64(TICK + 4, 0), Some(0x1144555555666666));
-        assert_eq!(v.load64(TICK + 4, 8), Some(0x6677772222222222));
+        assert_eq!(v.load64(TICK + 4, 0), Some(0x6666665555554411));
+        assert_eq!(v.load64(TICK + 4, 8), Some(0x2222222222777766));
 
         v.store32(TICK + 5, 10, 0x99999999).unwrap();
-        assert_eq!(v.load64(TICK + 5, 0), Some(0x1144555555666666));
-        assert_eq!(v.load64(TICK + 5, 8), Some(0x6677999999992222));
+        assert_eq!(v.load64(TICK + 5, 0), Some(0x6666665555554411));
+        assert_eq!(v.load64(TICK + 5, 8), Some(0x2222999999997766));
 
         v.store64(TICK + 6, 0, 0x0000000000000000).unwrap();
         v.store64(TICK + 6, 8, 0x0000000000000000).unwrap();
@@ -198,11 +442,11 @@ mod tests {
             for ind in 0..8 {
                 val |= i << (ind * 4);
             }
-            v.store32(TICK + 7 + i, i.into(), val).unwrap();
+            v.store32(TICK + 7 + i as u64, i.into(), val).unwrap();
         }
 
-        assert_eq!(v.load64(TICK + 30, 0), Some(0x0011223344556677));
-        assert_eq!(v.load64(TICK + 30, 8), Some(0x8899aabbbbbbbb00));
+        assert_eq!(v.load64(TICK + 30, 0), Some(0x7766554433221100));
+        assert_eq!(v.load64(TICK + 30, 8), Some(0x00bbbbbbbbaa9988));
 
         // u16
         v.store64(TICK + 100, 0, 0x4444444444444444).unwrap();
@@ -221,8 +465,8 @@ mod tests {
         // println!("{:016x}", v.load64(TICK + 110, 0).unwrap());
         // println!("{:016x}", v.load64(TICK + 110, 8).unwrap());
 
-        assert_eq!(v.load64(TICK + 110, 0), Some(0xffffeeeedd998877));
-        assert_eq!(v.load64(TICK + 110, 8), Some(0x6655000044444444));
+        assert_eq!(v.load64(TICK + 110, 0), Some(0x778899ddeeeeffff));
+        assert_eq!(v.load64(TICK + 110, 8), Some(0x4444444400005566));
 
         // u8
         v.store64(TICK + 200, 0, 0x2222222222222222).unwrap();
@@ -234,13 +478,13 @@ mod tests {
         v.store8(TICK + 204, 8, 0x99).unwrap();
         v.store8(TICK + 205, 9, 0x77).unwrap();
 
-        assert_eq!(v.load64(TICK + 210, 0), Some(0x3322223322222288));
-        assert_eq!(v.load64(TICK + 210, 8), Some(0x9977222222222222));
+        assert_eq!(v.load64(TICK + 210, 0), Some(0x8822222233222233));
+        assert_eq!(v.load64(TICK + 210, 8), Some(0x2222222222227799));
     }
 
     #[test]
     fn loads() {
-        const TICK: u32 = 555;
+        const TICK: Tick = Tick(555);
 
         let mut v = HistMem::new();
 
@@ -252,8 +496,8 @@ mod tests {
             println!("64 {:02x}: {:016x}", a, v.load64(TICK, a).unwrap());
         }
         assert_eq!(v.load64(TICK, 0), Some(0x1111111111111111));
-        assert_eq!(v.load64(TICK, 1), Some(0x1111111111111122));
-        assert_eq!(v.load64(TICK, 7), Some(0x1122222222222222));
+        assert_eq!(v.load64(TICK, 1), Some(0x2211111111111111));
+        assert_eq!(v.load64(TICK, 7), Some(0x2222222222222211));
         assert_eq!(v.load64(TICK, 8), Some(0x2222222222222222));
         assert_eq!(v.load64(TICK, 9), None);
 
@@ -264,9 +508,9 @@ mod tests {
         }
         assert_eq!(v.load32(TICK, 0), Some(0x11111111));
         assert_eq!(v.load32(TICK, 4), Some(0x11111111));
-        assert_eq!(v.load32(TICK, 5), Some(0x11111122));
-        assert_eq!(v.load32(TICK, 6), Some(0x11112222));
-        assert_eq!(v.load32(TICK, 7), Some(0x11222222));
+        assert_eq!(v.load32(TICK, 5), Some(0x22111111));
+        assert_eq!(v.load32(TICK, 6), Some(0x22221111));
+        assert_eq!(v.load32(TICK, 7), Some(0x22222211));
         assert_eq!(v.load32(TICK, 8), Some(0x22222222));
         assert_eq!(v.load32(TICK, 9), Some(0x22222222));
         assert_eq!(v.load32(TICK, 12), Some(0x22222222));
@@ -279,7 +523,7 @@ mod tests {
         }
         assert_eq!(v.load16(TICK, 0), Some(0x1111));
         assert_eq!(v.load16(TICK, 6), Some(0x1111));
-        assert_eq!(v.load16(TICK, 7), Some(0x1122));
+        assert_eq!(v.load16(TICK, 7), Some(0x2211));
         assert_eq!(v.load16(TICK, 8), Some(0x2222));
         assert_eq!(v.load16(TICK, 14), Some(0x2222));
         assert_eq!(v.load16(TICK, 15), None);
@@ -1,9 +1,24 @@
+//! Memory as it was at any tick of a trace. Every byte remembers what was
+//! stored to it and what loads saw there, so the value at an earlier tick
+//! can be looked up after the fact.
 //!
+//! [`HistMem`] is the one the analyzer uses. By default it's `paged`, which
+//! logs accesses per page. The others are slower but simpler, and have the
+//! same API so they can be swapped in with the `histmem-byte` or
+//! `histmem-qword` feature to check an analysis against. The tests and
+//! `benches/histmem.rs` compare all three.
 
-// we have two implementations:
-
+// a history per 8 bytes
 pub mod qword;
-// pub use qword::{HistMem, MCell};
 
+// a history per byte, what the others have to agree with
 pub mod byte;
-pub use byte::{HistMem, MCell};
+
+pub mod paged;
+
+#[cfg(feature = "histmem-byte")]
+pub use byte::HistMem;
+#[cfg(not(any(feature = "histmem-byte", feature = "histmem-qword")))]
+pub use paged::HistMem;
+#[cfg(all(feature = "histmem-qword", not(feature = "histmem-byte")))]
+pub use qword::HistMem;
@@ -0,0 +1,551 @@
+// Instead of a history per byte, each page keeps two logs of the accesses
+// that touched it: one of stores and one of what loads saw. A memset of a
+// megabyte is then 256 entries rather than a million cells. Both logs are
+// only ever appended to, in tick order, and every `CHECKPOINT` entries a log
+// takes a snapshot, so a lookup never replays more than that many entries.
+
+use crate::tick::Tick;
+use std::{collections::BTreeMap, ops::Range};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Entries between snapshots of a log
+const CHECKPOINT: usize = 2048;
+
+/// `Snapshot::ticks` of a byte the log never touched
+const NEVER: Tick = Tick::MAX;
+
+/// Accesses of up to this many bytes are kept in the entry itself
+const INLINE: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    tick: Tick,
+    offset: u16,
+    len: u16,
+    /// the bytes if there are at most `INLINE`, otherwise where they start in
+    /// `Log::data`, as a native endian u64
+    data: [u8; INLINE],
+}
+
+impl Entry {
+    fn range(&self) -> Range<usize> {
+        self.offset as usize..self.offset as usize + self.len as usize
+    }
+
+    fn overlaps(&self, range: &Range<usize>) -> bool {
+        let own = self.range();
+        own.start < range.end && range.start < own.end
+    }
+}
+
+/// What a log says about a page at some point
+#[derive(Debug, Clone)]
+struct Snapshot {
+    values: Box<[u8]>,
+    /// tick of the last entry for each byte, or `NEVER`
+    ticks: Box<[Tick]>,
+}
+
+impl Snapshot {
+    fn empty() -> Self {
+        Self {
+            values: vec![0; PAGE_SIZE as usize].into_boxed_slice(),
+            ticks: vec![NEVER; PAGE_SIZE as usize].into_boxed_slice(),
+        }
+    }
+
+    fn get(&self, offset: usize) -> Option<(Tick, u8)> {
+        let tick = self.ticks[offset];
+        (tick != NEVER).then(|| (tick, self.values[offset]))
+    }
+
+    fn apply(&mut self, entry: &Entry, bytes: &[u8]) {
+        for (offset, &byte) in entry.range().zip(bytes) {
+            self.values[offset] = byte;
+            self.ticks[offset] = entry.tick;
+        }
+    }
+}
+
+/// Accesses of one kind to a page, in tick order
+#[derive(Debug, Clone, Default)]
+struct Log {
+    entries: Vec<Entry>,
+    /// the bytes of entries too big to be inline, back to back
+    data: Vec<u8>,
+    /// `checkpoints[i]` is the state after the first `(i + 1) * CHECKPOINT`
+    /// entries
+    checkpoints: Vec<Snapshot>,
+}
+
+impl Log {
+    fn bytes<'a>(&'a self, entry: &'a Entry) -> &'a [u8] {
+        let len = entry.range().len();
+        if len <= INLINE {
+            &entry.data[..len]
+        } else {
+            &self.data[u64::from_ne_bytes(entry.data) as usize..][..len]
+        }
+    }
+
+    /// The latest snapshot before `tick`, and the entries after it up to and
+    /// including `tick`
+    fn before(&self, tick: Tick) -> (Option<&Snapshot>, &[Entry]) {
+        let end = self.entries.partition_point(|e| e.tick <= tick);
+        let taken = end / CHECKPOINT;
+        let snapshot = taken.checked_sub(1).map(|idx| &self.checkpoints[idx]);
+        (snapshot, &self.entries[taken * CHECKPOINT..end])
+    }
+
+    /// Fills in `out` with the last (tick, value) of the bytes starting at
+    /// `offset`, in one pass over the entries
+    fn load(&self, tick: Tick, offset: usize, out: &mut [Option<(Tick, u8)>]) {
+        let (snapshot, entries) = self.before(tick);
+        let range = offset..offset + out.len();
+        let mut missing = out.len();
+
+        for entry in entries.iter().rev().filter(|e| e.overlaps(&range)) {
+            let own = entry.range();
+            let bytes = self.bytes(entry);
+            for at in own.start.max(range.start)..own.end.min(range.end) {
+                let slot = &mut out[at - offset];
+                if slot.is_none() {
+                    *slot = Some((entry.tick, bytes[at - own.start]));
+                    missing -= 1;
+                }
+            }
+            if missing == 0 {
+                return;
+            }
+        }
+
+        if let Some(snapshot) = snapshot {
+            for (at, slot) in range.zip(out.iter_mut()) {
+                if slot.is_none() {
+                    *slot = snapshot.get(at);
+                }
+            }
+        }
+    }
+
+    /// Like `byte::MCell`, nothing can be added from before what the log
+    /// already has
+    fn push(&mut self, tick: Tick, offset: usize, bytes: &[u8]) -> Result<(), ()> {
+        if self.entries.last().is_some_and(|e| e.tick > tick) {
+            return Err(());
+        }
+
+        let mut entry = Entry {
+            tick,
+            offset: offset as u16,
+            len: bytes.len() as u16,
+            data: [0; INLINE],
+        };
+        if bytes.len() <= INLINE {
+            entry.data[..bytes.len()].copy_from_slice(bytes);
+        } else {
+            entry.data = (self.data.len() as u64).to_ne_bytes();
+            self.data.extend_from_slice(bytes);
+        }
+        self.entries.push(entry);
+
+        if self.entries.len() == (self.checkpoints.len() + 1) * CHECKPOINT {
+            let from = self.entries.len() - CHECKPOINT;
+            let mut state = self
+                .checkpoints
+                .last()
+                .cloned()
+                .unwrap_or_else(Snapshot::empty);
+            for entry in &self.entries[from..] {
+                state.apply(entry, self.bytes(entry));
+            }
+            self.checkpoints.push(state);
+        }
+
+        Ok(())
+    }
+
+    /// Ticks of the entries that touched `offset`
+    fn ticks(&self, offset: usize) -> impl Iterator<Item = Tick> + '_ {
+        self.entries
+            .iter()
+            .filter(move |e| e.range().contains(&offset))
+            .map(|e| e.tick)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Page {
+    stores: Log,
+    /// what loads saw, kept apart so we still know what was written
+    observed: Log,
+}
+
+impl Page {
+    /// Fills in `out` with the bytes starting at `offset`
+    fn load(&self, tick: Tick, offset: usize, out: &mut [Option<u8>]) {
+        let mut written = vec![None; out.len()];
+        let mut observed = vec![None; out.len()];
+        self.stores.load(tick, offset, &mut written);
+        self.observed.load(tick, offset, &mut observed);
+
+        // same as `byte::MCell::at_tick`, an observation of the same tick wins
+        for ((slot, written), observed) in out.iter_mut().zip(written).zip(observed) {
+            *slot = match (written, observed) {
+                (Some((wt, w)), Some((ot, _))) if wt > ot => Some(w),
+                (_, Some((_, o))) => Some(o),
+                (Some((_, w)), None) => Some(w),
+                (None, None) => None,
+            };
+        }
+    }
+
+    fn written_at(&self, tick: Tick, offset: usize) -> Option<Tick> {
+        let mut written = [None];
+        self.stores.load(tick, offset, &mut written);
+        written[0].map(|(tick, _value)| tick)
+    }
+
+    /// The whole page at `tick`
+    fn snapshot(&self, tick: Tick) -> Vec<Option<u8>> {
+        let mut state = vec![None; PAGE_SIZE as usize];
+        self.load(tick, 0, &mut state);
+        state
+    }
+
+    fn history(&self, offset: usize) -> Vec<(Tick, u8)> {
+        let mut ticks: Vec<Tick> = self
+            .stores
+            .ticks(offset)
+            .chain(self.observed.ticks(offset))
+            .collect();
+        ticks.sort_unstable();
+        ticks.dedup();
+
+        let mut history: Vec<(Tick, u8)> = Vec::new();
+        for tick in ticks {
+            let mut value = [None];
+            self.load(tick, offset, &mut value);
+            let value = value[0].expect("logic error");
+            if history.last().map(|(_t, v)| *v) != Some(value) {
+                history.push((tick, value));
+            }
+        }
+        history
+    }
+
+    /// Only logs the runs of bytes that aren't already known to have that
+    /// value, so loads of unchanged memory cost nothing
+    fn observe(&mut self, tick: Tick, offset: usize, bytes: &[u8]) -> Result<(), ()> {
+        let mut known = vec![None; bytes.len()];
+        self.load(tick, offset, &mut known);
+        let differs = |idx: &usize| known[*idx] != Some(bytes[*idx]);
+
+        let mut start = 0;
+        while let Some(from) = (start..bytes.len()).find(differs) {
+            let to = (from..bytes.len())
+                .find(|idx| !differs(idx))
+                .unwrap_or(bytes.len());
+
+            self.observed.push(tick, offset + from, &bytes[from..to])?;
+            start = to;
+        }
+        Ok(())
+    }
+}
+
+/// Splits [adr, adr + len) at page boundaries into (page, offset in page,
+/// range of the input)
+fn split(adr: u64, len: usize) -> impl Iterator<Item = (u64, usize, Range<usize>)> {
+    let mut done = 0;
+    std::iter::from_fn(move || {
+        if done == len {
+            return None;
+        }
+        let at = adr + done as u64;
+        let offset = (at % PAGE_SIZE) as usize;
+        let n = (PAGE_SIZE as usize - offset).min(len - done);
+        let chunk = (at / PAGE_SIZE, offset, done..done + n);
+        done += n;
+        Some(chunk)
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistMem {
+    // page number -> log of everything that touched it
+    pages: BTreeMap<u64, Page>,
+}
+
+impl HistMem {
+    pub fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+        }
+    }
+
+    /// Pages overlapping [range.start, range.end), with the part of the page
+    /// that is in the range
+    fn pages(&self, range: Range<u64>) -> impl Iterator<Item = (u64, &Page, Range<u64>)> {
+        // `BTreeMap::range` panics on backwards ranges
+        let pages =
+            (!range.is_empty()).then(|| range.start / PAGE_SIZE..=(range.end - 1) / PAGE_SIZE);
+        pages
+            .into_iter()
+            .flat_map(|pages| self.pages.range(pages))
+            .map(move |(&num, page)| {
+                let base = num * PAGE_SIZE;
+                let inside = range.start.max(base)..range.end.min(base + PAGE_SIZE);
+                (base, page, inside)
+            })
+    }
+
+    /// Fills in `out` with what we know of the bytes starting at `adr`
+    fn load(&self, tick: Tick, adr: u64, out: &mut [Option<u8>]) {
+        for (page, offset, range) in split(adr, out.len()) {
+            if let Some(page) = self.pages.get(&page) {
+                page.load(tick, offset, &mut out[range]);
+            }
+        }
+    }
+
+    /// `N` bytes at `adr`, if we know all of them
+    fn load_array<const N: usize>(&self, tick: Tick, adr: u64) -> Option<[u8; N]> {
+        let mut out = [None; N];
+        self.load(tick, adr, &mut out);
+
+        let mut bytes = [0; N];
+        for (byte, known) in bytes.iter_mut().zip(out) {
+            *byte = known?;
+        }
+        Some(bytes)
+    }
+
+    pub fn load8(&self, tick: Tick, adr: u64) -> Option<u8> {
+        self.load_array::<1>(tick, adr).map(|[byte]| byte)
+    }
+
+    /// When the last store to the byte before `tick` happened, loads don't
+    /// count
+    pub fn written_at(&self, tick: Tick, adr: u64) -> Option<Tick> {
+        let page = self.pages.get(&(adr / PAGE_SIZE))?;
+        page.written_at(tick, (adr % PAGE_SIZE) as usize)
+    }
+
+    /// `len` bytes starting at `adr`, `None` for the ones we know nothing of
+    pub fn load_range(&self, tick: Tick, adr: u64, len: u64) -> Vec<Option<u8>> {
+        let mut bytes = vec![None; len as usize];
+        self.load(tick, adr, &mut bytes);
+        bytes
+    }
+
+    /// Every value the byte at `adr` took on, written or observed, as
+    /// (tick, value) in tick order
+    pub fn history(&self, adr: u64) -> Vec<(Tick, u8)> {
+        match self.pages.get(&(adr / PAGE_SIZE)) {
+            Some(page) => page.history((adr % PAGE_SIZE) as usize),
+            None => Vec::new(),
+        }
+    }
+
+    /// Runs of bytes in `range` that differ between `from` and `to`
+    pub fn changed_between(&self, from: Tick, to: Tick, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        for (base, page, inside) in self.pages(range) {
+            let (before, after) = (page.snapshot(from), page.snapshot(to));
+            for adr in inside {
+                let offset = (adr - base) as usize;
+                if before[offset] == after[offset] {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run) if run.end == adr => run.end += 1,
+                    _ => runs.push(adr..adr + 1),
+                }
+            }
+        }
+        runs
+    }
+
+    /// Page aligned runs of [range.start, range.end) that we know anything
+    /// about at all, at any tick
+    pub fn touched(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        if range.is_empty() {
+            return runs;
+        }
+        let pages = range.start / PAGE_SIZE..=(range.end - 1) / PAGE_SIZE;
+        for &page in self.pages.range(pages).map(|(page, _)| page) {
+            let (start, end) = (page * PAGE_SIZE, (page + 1) * PAGE_SIZE);
+            match runs.last_mut() {
+                Some(run) if run.end == start => run.end = end,
+                _ => runs.push(start..end),
+            }
+        }
+        runs
+    }
+
+    pub fn load16(&self, tick: Tick, adr: u64) -> Option<u16> {
+        self.load_array(tick, adr).map(u16::from_le_bytes)
+    }
+
+    pub fn load32(&self, tick: Tick, adr: u64) -> Option<u32> {
+        self.load_array(tick, adr).map(u32::from_le_bytes)
+    }
+
+    pub fn load64(&self, tick: Tick, adr: u64) -> Option<u64> {
+        self.load_array(tick, adr).map(u64::from_le_bytes)
+    }
+
+    pub fn store8(&mut self, tick: Tick, adr: u64, val: u8) -> Result<(), ()> {
+        self.store_bytes(tick, adr, &[val])
+    }
+
+    pub fn store16(&mut self, tick: Tick, adr: u64, val: u16) -> Result<(), ()> {
+        self.store_bytes(tick, adr, &val.to_le_bytes())
+    }
+
+    pub fn store32(&mut self, tick: Tick, adr: u64, val: u32) -> Result<(), ()> {
+        self.store_bytes(tick, adr, &val.to_le_bytes())
+    }
+
+    pub fn store64(&mut self, tick: Tick, adr: u64, val: u64) -> Result<(), ()> {
+        self.store_bytes(tick, adr, &val.to_le_bytes())
+    }
+
+    /// Raw bytes written at `tick` all at once, e.g. by a syscall
+    pub fn store_bytes(&mut self, tick: Tick, adr: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (page, offset, range) in split(adr, bytes.len()) {
+            self.pages
+                .entry(page)
+                .or_default()
+                .stores
+                .push(tick, offset, &bytes[range])?;
+        }
+        Ok(())
+    }
+
+    /// A load read `val` at `tick`. Not a write, but `load8` returns it until
+    /// something newer comes along.
+    pub fn observe8(&mut self, tick: Tick, adr: u64, val: u8) -> Result<(), ()> {
+        self.observe_bytes(tick, adr, &[val])
+    }
+
+    /// Raw bytes we know are there at `tick`, e.g. from the file a region
+    /// was mapped from
+    pub fn observe_bytes(&mut self, tick: Tick, adr: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (page, offset, range) in split(adr, bytes.len()) {
+            self.pages
+                .entry(page)
+                .or_default()
+                .observe(tick, offset, &bytes[range])?;
+        }
+        Ok(())
+    }
+
+    pub fn observe16(&mut self, tick: Tick, adr: u64, val: u16) -> Result<(), ()> {
+        self.observe_bytes(tick, adr, &val.to_le_bytes())
+    }
+
+    pub fn observe32(&mut self, tick: Tick, adr: u64, val: u32) -> Result<(), ()> {
+        self.observe_bytes(tick, adr, &val.to_le_bytes())
+    }
+
+    pub fn observe64(&mut self, tick: Tick, adr: u64, val: u64) -> Result<(), ()> {
+        self.observe_bytes(tick, adr, &val.to_le_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HistMem, CHECKPOINT, PAGE_SIZE};
+    use crate::{mem::byte, tick::Tick};
+
+    #[test]
+    fn same_as_byte() {
+        let mut paged = HistMem::new();
+        let mut byte = byte::HistMem::new();
+
+        // enough for a few checkpoints per page, across a page boundary
+        let mut seed: u64 = 0x1234;
+        let mut random = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            seed >> 33
+        };
+        let base = PAGE_SIZE - 0x40;
+        for tick in (0..CHECKPOINT as u64 * 3).map(Tick) {
+            let adr = base + random() % 0x80;
+            let val = random();
+            match random() % 4 {
+                0 => {
+                    paged.observe64(tick, adr, val).unwrap();
+                    byte.observe64(tick, adr, val).unwrap();
+                }
+                1 => {
+                    paged.store8(tick + 1, adr, val as u8).unwrap();
+                    byte.store8(tick + 1, adr, val as u8).unwrap();
+                }
+                _ => {
+                    paged.store32(tick + 1, adr, val as u32).unwrap();
+                    byte.store32(tick + 1, adr, val as u32).unwrap();
+                }
+            }
+        }
+
+        for tick in (0..CHECKPOINT as u64 * 3 + 2).step_by(7).map(Tick) {
+            for adr in base - 1..base + 0x89 {
+                assert_eq!(paged.load8(tick, adr), byte.load8(tick, adr));
+                assert_eq!(paged.written_at(tick, adr), byte.written_at(tick, adr));
+            }
+            assert_eq!(
+                paged.load_range(tick, base - 1, 0x8a),
+                byte.load_range(tick, base - 1, 0x8a)
+            );
+            assert_eq!(
+                paged.changed_between(Tick(tick.0 / 2), tick, 0..2 * PAGE_SIZE),
+                byte.changed_between(Tick(tick.0 / 2), tick, 0..2 * PAGE_SIZE)
+            );
+        }
+        for adr in base..base + 0x88 {
+            assert_eq!(paged.history(adr), byte.history(adr));
+        }
+    }
+
+    #[test]
+    fn little_endian() {
+        let mut m = HistMem::new();
+        m.store32(Tick(1), 0x10, 0x44332211).unwrap();
+        m.observe16(Tick(1), 0x14, 0x6655).unwrap();
+
+        assert_eq!(m.load8(Tick(1), 0x10), Some(0x11));
+        assert_eq!(m.load8(Tick(1), 0x15), Some(0x66));
+        assert_eq!(m.load16(Tick(1), 0x13), Some(0x5544));
+        assert_eq!(m.load64(Tick(1), 0x10), None);
+        m.store16(Tick(2), 0x16, 0x8877).unwrap();
+        assert_eq!(m.load64(Tick(2), 0x10), Some(0x8877665544332211));
+    }
+
+    #[test]
+    fn back_in_time() {
+        let mut m = HistMem::new();
+        m.store64(Tick(5), 0x10, 0x1111111111111111).unwrap();
+
+        // the load of the step before the store can come in after it
+        m.observe8(Tick(4), 0x11, 0x44).unwrap();
+        m.observe8(Tick(5), 0x10, 0x22).unwrap();
+        assert_eq!(m.load8(Tick(4), 0x11), Some(0x44));
+        assert_eq!(m.load8(Tick(5), 0x11), Some(0x11));
+        assert_eq!(m.written_at(Tick(4), 0x11), None);
+
+        // but stores come in order
+        assert!(m.store8(Tick(4), 0x20, 0x33).is_err());
+        assert!(m.observe8(Tick(3), 0x20, 0x33).is_err());
+
+        // observations of the same tick win
+        m.store8(Tick(5), 0x10, 0x55).unwrap();
+        assert_eq!(m.load8(Tick(5), 0x10), Some(0x22));
+        assert_eq!(m.written_at(Tick(5), 0x10), Some(Tick(5)));
+        assert_eq!(m.history(0x11), vec![(Tick(4), 0x44), (Tick(5), 0x11)]);
+    }
+}
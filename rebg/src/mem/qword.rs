@@ -4,61 +4,155 @@
 // We may need to fetch or write to multiple, because load/store is not
 // necessarily aligned. Since we use u64, and the max read is u64, we at most
 // need to access 2 places.
+//
+// An update only covers the bytes the access touched, the rest of the qword
+// is looked up further back. Bytes are in memory order, the lowest address is
+// the lowest byte of the u64, like the little-endian targets we trace.
+
+use crate::tick::Tick;
+use std::{collections::BTreeMap, ops::Range};
+
+/// Only for `touched`, which answers in pages like the others
+const PAGE_SIZE: u64 = 0x1000;
+
+/// What one access did to a cell
+#[derive(Debug, Clone, Copy)]
+struct Update {
+    tick: Tick,
+    value: u64,
+    /// a bit per byte of `value` the access covered
+    mask: u8,
+}
+
+/// `mask` with each bit blown up to a whole byte
+fn bytemask(mask: u8) -> u64 {
+    (0..8)
+        .filter(|i| mask >> i & 1 == 1)
+        .fold(0, |bits, i| bits | 0xff << (i * 8))
+}
 
-use std::{cmp::min, collections::HashMap};
+/// Adds to a list of updates that is sorted by tick
+fn push(updates: &mut Vec<Update>, tick: Tick, value: u64, mask: u8) -> Result<(), ()> {
+    // is there already a tick after what we're adding?
+    if Some(tick) < updates.last().map(|x| x.tick) {
+        return Err(());
+    }
 
-#[derive(Debug)]
+    // is the previous tick equal to the current tick?
+    if let Some(last) = updates.last_mut() {
+        if last.tick == tick {
+            let bits = bytemask(mask);
+            last.value = last.value & !bits | value & bits;
+            last.mask |= mask;
+            return Ok(());
+        }
+    }
+
+    // otherwise, we can just push!
+    updates.push(Update {
+        tick,
+        value: value & bytemask(mask),
+        mask,
+    });
+    Ok(())
+}
+
+/// The last (tick, value) of byte `offset` at or before `tick`
+fn last(updates: &[Update], tick: Tick, offset: u64) -> Option<(Tick, u8)> {
+    let idx = updates.partition_point(|u| u.tick <= tick);
+
+    updates[..idx]
+        .iter()
+        .rev()
+        .find(|u| u.mask >> offset & 1 == 1)
+        .map(|u| (u.tick, (u.value >> (offset * 8)) as u8))
+}
+
+#[derive(Debug, Clone)]
 pub struct MCell {
-    values: Vec<(u32, u64)>,
+    values: Vec<Update>,
+    /// what loads saw, kept apart like in `byte::MCell`
+    observed: Vec<Update>,
 }
 
 impl MCell {
     fn new() -> Self {
-        Self { values: Vec::new() }
+        Self {
+            values: Vec::new(),
+            observed: Vec::new(),
+        }
     }
 
-    fn add_tick(&mut self, tick: u32, value: u64) -> Result<(), ()> {
-        // is there already a tick after what we're adding?
-        if Some(tick) < self.values.last().map(|x| x.0) {
-            return Err(());
-        }
+    fn add_tick(&mut self, tick: Tick, value: u64, mask: u8) -> Result<(), ()> {
+        push(&mut self.values, tick, value, mask)
+    }
 
-        // is the previous tick equal to the current tick?
-        if let Some((t, v)) = self.values.last_mut() {
-            // note,       /\ this is not a comparison
-            if *t == tick {
-                *v = value;
-                return Ok(());
-            }
+    /// A load saw `value` at `tick`, only the bytes that tell us something
+    /// new are kept
+    fn add_observed(&mut self, tick: Tick, value: u64, mask: u8) -> Result<(), ()> {
+        let new = (0..8)
+            .filter(|&i| mask >> i & 1 == 1)
+            .filter(|&i| self.at_tick(tick, i) != Some((value >> (i * 8)) as u8))
+            .fold(0, |new, i| new | 1 << i);
+        if new == 0 {
+            return Ok(());
         }
 
-        // otherwise, we can just push!
-        self.values.push((tick, value));
-        Ok(())
+        push(&mut self.observed, tick, value, new)
     }
 
-    fn at_tick(&self, tick: u32) -> Option<u64> {
-        let idx = self.values.partition_point(|(t, _v)| *t <= tick);
-
-        // we get idx 1 too high
-        // so if it's 0, that means no too early (cus index -1)
-        if idx == 0 {
-            return None;
+    /// The last known value of byte `offset`, whether it was written or
+    /// observed
+    fn at_tick(&self, tick: Tick, offset: u64) -> Option<u8> {
+        match (
+            last(&self.values, tick, offset),
+            last(&self.observed, tick, offset),
+        ) {
+            (Some((wt, w)), Some((ot, _))) if wt > ot => Some(w),
+            (_, Some((_, o))) => Some(o),
+            (Some((_, w)), None) => Some(w),
+            (None, None) => None,
         }
+    }
 
-        Some(self.values.get(idx - 1).unwrap().1)
+    /// The tick of the store that `at_tick` would return
+    fn written_at(&self, tick: Tick, offset: u64) -> Option<Tick> {
+        last(&self.values, tick, offset).map(|(t, _v)| t)
+    }
+
+    /// Every value byte `offset` took on, in tick order
+    fn history(&self, offset: u64) -> Vec<(Tick, u8)> {
+        let mut ticks: Vec<Tick> = self
+            .values
+            .iter()
+            .chain(&self.observed)
+            .filter(|u| u.mask >> offset & 1 == 1)
+            .map(|u| u.tick)
+            .collect();
+        ticks.sort_unstable();
+        ticks.dedup();
+
+        let mut history: Vec<(Tick, u8)> = Vec::new();
+        for tick in ticks {
+            let value = self.at_tick(tick, offset).expect("logic error");
+            if history.last().map(|(_t, v)| *v) != Some(value) {
+                history.push((tick, value));
+            }
+        }
+        history
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct HistMem {
-    // ptr -> value
-    cells: HashMap<u64, MCell>,
+    // aligned ptr -> value
+    cells: BTreeMap<u64, MCell>,
 }
 
 impl HistMem {
     pub fn new() -> Self {
         Self {
-            cells: HashMap::new(),
+            cells: BTreeMap::new(),
         }
     }
 
@@ -67,276 +161,215 @@ impl HistMem {
         (address >> SHIFT) << SHIFT
     }
 
-    const fn get_lower_bitmask(bytecnt: u64) -> u64 {
-        match bytecnt {
-            0 => 0,
-            1 => 0xFF00000000000000,
-            2 => 0xFFFF000000000000,
-            3 => 0xFFFFFF0000000000,
-            4 => 0xFFFFFFFF00000000,
-            5 => 0xFFFFFFFFFF000000,
-            6 => 0xFFFFFFFFFFFF0000,
-            7 => 0xFFFFFFFFFFFFFF00,
-            8 => 0xFFFFFFFFFFFFFFFF,
-            _ => Self::get_lower_bitmask(8),
+    /// Splits `bytes` at `address` into the cells they land in, as (aligned
+    /// address, value, mask)
+    fn updates(address: u64, bytes: &[u8]) -> Vec<(u64, u64, u8)> {
+        let mut updates: Vec<(u64, u64, u8)> = Vec::new();
+        for (adr, &byte) in (address..).zip(bytes) {
+            let aligned = Self::align_down(adr);
+            let offset = adr - aligned;
+            match updates.last_mut() {
+                Some((at, value, mask)) if *at == aligned => {
+                    *value |= (byte as u64) << (offset * 8);
+                    *mask |= 1 << offset;
+                }
+                _ => updates.push((aligned, (byte as u64) << (offset * 8), 1 << offset)),
+            }
         }
+        updates
     }
 
-    const fn get_upper_bitmask(bytecnt: u64) -> u64 {
-        match bytecnt {
-            0 => 0,
-            1 => 0xFF,
-            2 => 0xFFFF,
-            3 => 0xFFFFFF,
-            4 => 0xFFFFFFFF,
-            5 => 0xFFFFFFFFFF,
-            6 => 0xFFFFFFFFFFFF,
-            7 => 0xFFFFFFFFFFFFFF,
-            8 => 0xFFFFFFFFFFFFFFFF,
-            _ => Self::get_upper_bitmask(8),
-        }
+    /// Cells of [range.start, range.end) that were ever touched, in order,
+    /// with their aligned address
+    fn cells(&self, range: Range<u64>) -> impl Iterator<Item = (u64, &MCell)> {
+        // `BTreeMap::range` panics on backwards ranges
+        let cells = (!range.is_empty()).then(|| Self::align_down(range.start)..range.end);
+        cells
+            .into_iter()
+            .flat_map(|cells| self.cells.range(cells))
+            .map(|(&adr, cell)| (adr, cell))
     }
 
-    pub fn load64aligned(&self, tick: u32, adr: u64) -> Option<u64> {
-        self.cells.get(&adr)?.at_tick(tick)
+    pub fn load8(&self, tick: Tick, address: u64) -> Option<u8> {
+        let adr = Self::align_down(address);
+        self.cells.get(&adr)?.at_tick(tick, address - adr)
     }
 
-    pub fn load64(&self, tick: u32, address: u64) -> Option<u64> {
-        let adr_lower = Self::align_down(address);
-        let adr_upper = Self::align_down(address + 7);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        let lower = self.load64aligned(tick, adr_lower)?;
-        let upper = self.load64aligned(tick, adr_upper)?;
-
-        let lower = lower & Self::get_lower_bitmask(lower_len);
-        let upper = upper & Self::get_upper_bitmask(upper_len);
-
-        Some(lower | upper)
+    /// When the last store to the byte before `tick` happened, loads don't
+    /// count
+    pub fn written_at(&self, tick: Tick, address: u64) -> Option<Tick> {
+        let adr = Self::align_down(address);
+        self.cells.get(&adr)?.written_at(tick, address - adr)
     }
 
-    pub fn load32(&self, tick: u32, address: u64) -> Option<u32> {
-        let adr_lower = Self::align_down(address);
-        let adr_upper = Self::align_down(address + 3);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        let lower = self.load64aligned(tick, adr_lower)?;
-        let upper = self.load64aligned(tick, adr_upper)?;
-
-        let lower = lower & Self::get_lower_bitmask(lower_len);
-        let upper = upper & Self::get_upper_bitmask(upper_len);
-
-        let combined = lower | upper;
-        let combined = (combined >> 32) as u32;
-
-        Some(combined)
+    /// `len` bytes starting at `adr`, `None` for the ones we know nothing of
+    pub fn load_range(&self, tick: Tick, address: u64, len: u64) -> Vec<Option<u8>> {
+        (address..address + len)
+            .map(|adr| self.load8(tick, adr))
+            .collect()
     }
 
-    pub fn load16(&self, tick: u32, address: u64) -> Option<u16> {
-        let adr_lower = Self::align_down(address);
-        let adr_upper = Self::align_down(address + 1);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        let lower = self.load64aligned(tick, adr_lower)?;
-        let upper = self.load64aligned(tick, adr_upper)?;
-
-        let lower = lower & Self::get_lower_bitmask(lower_len);
-        let upper = upper & Self::get_upper_bitmask(upper_len);
-
-        let combined = lower | upper;
-        let combined = (combined >> 48) as u16;
-
-        Some(combined)
+    /// Every value the byte at `adr` took on, written or observed, as
+    /// (tick, value) in tick order
+    pub fn history(&self, address: u64) -> Vec<(Tick, u8)> {
+        let adr = Self::align_down(address);
+        self.cells
+            .get(&adr)
+            .map(|cell| cell.history(address - adr))
+            .unwrap_or_default()
     }
 
-    pub fn load8(&self, tick: u32, address: u64) -> Option<u8> {
-        let adr_lower = Self::align_down(address);
-        let offset = address - adr_lower;
-
-        let lower = self.load64aligned(tick, adr_lower)? >> (offset * 8);
-        let lower = (lower & 0xFF) as u8;
-
-        Some(lower)
+    /// Runs of bytes in `range` that differ between `from` and `to`
+    pub fn changed_between(&self, from: Tick, to: Tick, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        for (aligned, cell) in self.cells(range.clone()) {
+            for offset in 0..8 {
+                let adr = aligned + offset;
+                if !range.contains(&adr) || cell.at_tick(from, offset) == cell.at_tick(to, offset) {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run) if run.end == adr => run.end += 1,
+                    _ => runs.push(adr..adr + 1),
+                }
+            }
+        }
+        runs
     }
 
-    pub fn store64aligned(&mut self, tick: u32, address: u64, v: u64) -> Result<(), ()> {
-        self.cells.entry(address).or_insert_with(MCell::new);
-
-        let cell = self.cells.get_mut(&address).expect("logic error");
-
-        cell.add_tick(tick, v)?;
-        Ok(())
+    /// Page aligned runs of [range.start, range.end) that we know anything
+    /// about at all, at any tick
+    pub fn touched(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut runs: Vec<Range<u64>> = Vec::new();
+        if range.is_empty() {
+            return runs;
+        }
+        let first = range.start / PAGE_SIZE * PAGE_SIZE;
+        let last = (range.end - 1) / PAGE_SIZE * PAGE_SIZE + (PAGE_SIZE - 1);
+        for &adr in self.cells.range(first..=last).map(|(adr, _)| adr) {
+            let start = adr / PAGE_SIZE * PAGE_SIZE;
+            match runs.last_mut() {
+                Some(run) if run.end > start => {}
+                Some(run) if run.end == start => run.end = start + PAGE_SIZE,
+                _ => runs.push(start..start + PAGE_SIZE),
+            }
+        }
+        runs
     }
 
-    pub fn store64(&mut self, tick: u32, address: u64, value: u64) -> Result<(), ()> {
-        let adr_lower = Self::align_down(address);
-
-        if adr_lower == address {
-            self.store64aligned(tick, address, value)?;
-            return Ok(());
+    /// `N` bytes at `address`, if we know all of them
+    fn load_array<const N: usize>(&self, tick: Tick, address: u64) -> Option<[u8; N]> {
+        let mut bytes = [0; N];
+        for (adr, byte) in (address..).zip(bytes.iter_mut()) {
+            *byte = self.load8(tick, adr)?;
         }
-
-        let adr_upper = Self::align_down(address + 7);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        // instead of keeping only that which inside the bitmask, in this case
-        // we actually want to replace those parts.
-
-        let lower_existing = self.load64aligned(tick, adr_lower).ok_or(())?;
-        let upper_existing = self.load64aligned(tick, adr_upper).ok_or(())?;
-
-        let lower_existing = lower_existing & !Self::get_upper_bitmask(lower_len); // note the reversed upper
-        let lower_overwrite = value >> (upper_len * 8);
-        let lower = lower_existing | lower_overwrite;
-
-        let upper_existing = upper_existing & !Self::get_lower_bitmask(upper_len);
-        let upper_overwrite = value << (lower_len * 8);
-        let upper = upper_existing | upper_overwrite;
-
-        self.store64aligned(tick, adr_lower, lower)?;
-        self.store64aligned(tick, adr_upper, upper)?;
-
-        Ok(())
+        Some(bytes)
     }
 
-    pub fn store32(&mut self, tick: u32, address: u64, value: u32) -> Result<(), ()> {
-        let adr_lower = Self::align_down(address);
-        let adr_upper = Self::align_down(address + 3);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        // instead of keeping only that which inside the bitmask, in this case
-        // we actually want to replace those parts.
-
-        let lower_existing = self.load64aligned(tick, adr_lower).ok_or(())?;
-        let upper_existing = self.load64aligned(tick, adr_upper).ok_or(())?;
-
-        let lower_mask = {
-            let lower_mask_r = Self::get_upper_bitmask(lower_len);
-            let lower_mask_l = Self::get_lower_bitmask(upper_len + 4); // if we go too large, we just get 8
-
-            lower_mask_r & lower_mask_l
-        };
-
-        let lower = {
-            let lower_existing = lower_existing & !lower_mask; // note the reversed upper
-            let lower_overwrite = ((value as u64) << 32) >> (upper_len * 8);
-            lower_existing | lower_overwrite
-        };
+    pub fn load16(&self, tick: Tick, address: u64) -> Option<u16> {
+        self.load_array(tick, address).map(u16::from_le_bytes)
+    }
 
-        let upper_mask = Self::get_lower_bitmask(4u64.saturating_sub(lower_len));
-        let upper_existing = upper_existing & !upper_mask;
+    pub fn load32(&self, tick: Tick, address: u64) -> Option<u32> {
+        self.load_array(tick, address).map(u32::from_le_bytes)
+    }
 
-        let upper_overwrite = (value as u64) << min(lower_len * 8 + 32, 48);
-        let upper = upper_existing | upper_overwrite;
+    pub fn load64(&self, tick: Tick, address: u64) -> Option<u64> {
+        self.load_array(tick, address).map(u64::from_le_bytes)
+    }
 
-        if lower_mask != 0 {
-            self.store64aligned(tick, adr_lower, lower)?;
+    /// Raw bytes written at `tick` all at once, e.g. by a syscall
+    pub fn store_bytes(&mut self, tick: Tick, address: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (adr, value, mask) in Self::updates(address, bytes) {
+            self.cells
+                .entry(adr)
+                .or_insert_with(MCell::new)
+                .add_tick(tick, value, mask)?;
         }
-        if upper_mask != 0 {
-            self.store64aligned(tick, adr_upper, upper)?;
-        }
-
         Ok(())
     }
 
-    pub fn store16(&mut self, tick: u32, address: u64, value: u16) -> Result<(), ()> {
-        let adr_lower = Self::align_down(address);
-        let adr_upper = Self::align_down(address + 1);
-
-        let upper_len = address - adr_lower;
-        let lower_len = 8 - upper_len;
-
-        // instead of keeping only that which inside the bitmask, in this case
-        // we actually want to replace those parts.
-
-        let lower_existing = self.load64aligned(tick, adr_lower).ok_or(())?;
-        let upper_existing = self.load64aligned(tick, adr_upper).ok_or(())?;
-
-        let lower_mask = {
-            let lower_mask_r = Self::get_upper_bitmask(lower_len);
-            let lower_mask_l = Self::get_lower_bitmask(upper_len + 2); // if we go too large, we just get 8
-
-            lower_mask_r & lower_mask_l
-        };
+    pub fn store8(&mut self, tick: Tick, address: u64, value: u8) -> Result<(), ()> {
+        self.store_bytes(tick, address, &[value])
+    }
 
-        let lower = {
-            let lower_existing = lower_existing & !lower_mask; // note the reversed upper
-            let lower_overwrite = ((value as u64) << 48) >> (upper_len * 8);
-            lower_existing | lower_overwrite
-        };
+    pub fn store16(&mut self, tick: Tick, address: u64, value: u16) -> Result<(), ()> {
+        self.store_bytes(tick, address, &value.to_le_bytes())
+    }
 
-        let upper_mask = Self::get_lower_bitmask(2u64.saturating_sub(lower_len));
-        let upper_existing = upper_existing & !upper_mask;
+    pub fn store32(&mut self, tick: Tick, address: u64, value: u32) -> Result<(), ()> {
+        self.store_bytes(tick, address, &value.to_le_bytes())
+    }
 
-        let upper_overwrite = (value as u64) << min(lower_len * 8 + 48, 48);
-        let upper = upper_existing | upper_overwrite;
+    pub fn store64(&mut self, tick: Tick, address: u64, value: u64) -> Result<(), ()> {
+        self.store_bytes(tick, address, &value.to_le_bytes())
+    }
 
-        if lower_mask != 0 {
-            self.store64aligned(tick, adr_lower, lower)?;
-        }
-        if upper_mask != 0 {
-            self.store64aligned(tick, adr_upper, upper)?;
+    /// Raw bytes we know are there at `tick`, e.g. from the file a region
+    /// was mapped from
+    pub fn observe_bytes(&mut self, tick: Tick, address: u64, bytes: &[u8]) -> Result<(), ()> {
+        for (adr, value, mask) in Self::updates(address, bytes) {
+            self.cells
+                .entry(adr)
+                .or_insert_with(MCell::new)
+                .add_observed(tick, value, mask)?;
         }
-
         Ok(())
     }
 
-    pub fn store8(&mut self, tick: u32, address: u64, value: u8) -> Result<(), ()> {
-        let adr = Self::align_down(address);
-        let offset = address - adr;
-
-        let existing = self.load64aligned(tick, adr).ok_or(())?;
-
-        let mask_l = Self::get_lower_bitmask(offset + 1);
-        let mask_r = Self::get_upper_bitmask(8 - offset);
-        let mask = mask_l & mask_r;
-        let existing = existing & !mask;
+    /// A load read `value` at `tick`. Not a write, but `load8` returns it
+    /// until something newer comes along.
+    pub fn observe8(&mut self, tick: Tick, address: u64, value: u8) -> Result<(), ()> {
+        self.observe_bytes(tick, address, &[value])
+    }
 
-        let overwrite = (value as u64) << ((7 - offset) * 8);
-        let value = existing | overwrite;
+    pub fn observe16(&mut self, tick: Tick, address: u64, value: u16) -> Result<(), ()> {
+        self.observe_bytes(tick, address, &value.to_le_bytes())
+    }
 
-        if mask != 0 {
-            self.store64aligned(tick, adr, value)?;
-        }
+    pub fn observe32(&mut self, tick: Tick, address: u64, value: u32) -> Result<(), ()> {
+        self.observe_bytes(tick, address, &value.to_le_bytes())
+    }
 
-        Ok(())
+    pub fn observe64(&mut self, tick: Tick, address: u64, value: u64) -> Result<(), ()> {
+        self.observe_bytes(tick, address, &value.to_le_bytes())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::HistMem;
+    use crate::{mem::byte, tick::Tick};
 
     use super::MCell;
 
+    /// The 8 bytes at `adr` in address order, so they read left to right
+    fn in_order(m: &HistMem, tick: Tick, adr: u64) -> Option<u64> {
+        m.load64(tick, adr).map(u64::swap_bytes)
+    }
+
     #[test]
     fn ticks() {
-        let mut cell = MCell { values: vec![] };
-        cell.add_tick(4, 0x11).unwrap();
-        cell.add_tick(7, 0x22).unwrap();
-        cell.add_tick(8, 0x33).unwrap();
+        let mut cell = MCell::new();
+        cell.add_tick(Tick(4), 0x11, 0xff).unwrap();
+        cell.add_tick(Tick(7), 0x22, 0xff).unwrap();
+        cell.add_tick(Tick(8), 0x33, 0x01).unwrap();
 
         dbg!(&cell);
 
-        assert_eq!(cell.at_tick(0), None);
-        assert_eq!(cell.at_tick(3), None);
-        assert_eq!(cell.at_tick(4), Some(0x11));
-        assert_eq!(cell.at_tick(5), Some(0x11));
-        assert_eq!(cell.at_tick(6), Some(0x11));
-        assert_eq!(cell.at_tick(7), Some(0x22));
-        assert_eq!(cell.at_tick(8), Some(0x33));
-        assert_eq!(cell.at_tick(9), Some(0x33));
-        assert_eq!(cell.at_tick(9999999), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(0), 0), None);
+        assert_eq!(cell.at_tick(Tick(3), 0), None);
+        assert_eq!(cell.at_tick(Tick(4), 0), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(5), 0), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(6), 0), Some(0x11));
+        assert_eq!(cell.at_tick(Tick(7), 0), Some(0x22));
+        assert_eq!(cell.at_tick(Tick(8), 0), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(9), 0), Some(0x33));
+        assert_eq!(cell.at_tick(Tick(9999999), 0), Some(0x33));
+
+        // the last update only covered the lowest byte
+        assert_eq!(cell.at_tick(Tick(8), 1), Some(0x00));
+        assert_eq!(cell.written_at(Tick(8), 1), Some(Tick(7)));
     }
 
     #[test]
@@ -354,63 +387,63 @@ mod tests {
     #[test]
     fn overlapping_stores() {
         let mut m = HistMem::new();
-        m.store64aligned(0, 0x1234, 0x1111111111111111).unwrap();
-        m.store64aligned(0, 0x1234, 0x2222222222222222).unwrap();
+        m.store64(Tick(0), 0x1230, 0x1111111111111111).unwrap();
+        m.store64(Tick(0), 0x1230, 0x2222222222222222).unwrap();
 
-        assert_eq!(m.load64aligned(0, 0x1234), Some(0x2222222222222222))
+        assert_eq!(m.load64(Tick(0), 0x1230), Some(0x2222222222222222))
     }
 
     #[test]
     fn stores() {
-        const TICK: u32 = 555;
+        const TICK: Tick = Tick(555);
         let mut v = HistMem::new();
 
-        v.store64aligned(TICK - 1, 0, 0x1111111111111111).unwrap();
-        v.store64aligned(TICK - 1, 8, 0x2222222222222222).unwrap();
+        v.store64(Tick(TICK.0 - 1), 0, 0x1111111111111111).unwrap();
+        v.store64(Tick(TICK.0 - 1), 8, 0x2222222222222222).unwrap();
 
         // u64
         v.store64(TICK, 1, 0xFFFFFFFFFFFFFFFF).unwrap();
 
-        assert_eq!(v.load64aligned(TICK, 0), Some(0x11FFFFFFFFFFFFFF));
-        assert_eq!(v.load64aligned(TICK, 8), Some(0xFF22222222222222));
+        assert_eq!(in_order(&v, TICK, 0), Some(0x11FFFFFFFFFFFFFF));
+        assert_eq!(in_order(&v, TICK, 8), Some(0xFF22222222222222));
 
         // u32
         v.store32(TICK + 1, 7, 0x77777777).unwrap();
-        assert_eq!(v.load64aligned(TICK + 1, 0), Some(0x11FFFFFFFFFFFF77));
-        assert_eq!(v.load64aligned(TICK + 1, 8), Some(0x7777772222222222));
+        assert_eq!(in_order(&v, TICK + 1, 0), Some(0x11FFFFFFFFFFFF77));
+        assert_eq!(in_order(&v, TICK + 1, 8), Some(0x7777772222222222));
 
         v.store32(TICK + 2, 1, 0x44444444).unwrap();
-        assert_eq!(v.load64aligned(TICK + 2, 0), Some(0x1144444444FFFF77));
-        assert_eq!(v.load64aligned(TICK + 2, 8), Some(0x7777772222222222));
+        assert_eq!(in_order(&v, TICK + 2, 0), Some(0x1144444444FFFF77));
+        assert_eq!(in_order(&v, TICK + 2, 8), Some(0x7777772222222222));
 
         v.store32(TICK + 3, 2, 0x55555555).unwrap();
-        assert_eq!(v.load64aligned(TICK + 3, 0), Some(0x114455555555FF77));
-        assert_eq!(v.load64aligned(TICK + 3, 8), Some(0x7777772222222222));
+        assert_eq!(in_order(&v, TICK + 3, 0), Some(0x114455555555FF77));
+        assert_eq!(in_order(&v, TICK + 3, 8), Some(0x7777772222222222));
 
         v.store32(TICK + 4, 5, 0x66666666).unwrap();
-        assert_eq!(v.load64aligned(TICK + 4, 0), Some(0x1144555555666666));
-        assert_eq!(v.load64aligned(TICK + 4, 8), Some(0x6677772222222222));
+        assert_eq!(in_order(&v, TICK + 4, 0), Some(0x1144555555666666));
+        assert_eq!(in_order(&v, TICK + 4, 8), Some(0x6677772222222222));
 
         v.store32(TICK + 5, 10, 0x99999999).unwrap();
-        assert_eq!(v.load64aligned(TICK + 5, 0), Some(0x1144555555666666));
-        assert_eq!(v.load64aligned(TICK + 5, 8), Some(0x6677999999992222));
+        assert_eq!(in_order(&v, TICK + 5, 0), Some(0x1144555555666666));
+        assert_eq!(in_order(&v, TICK + 5, 8), Some(0x6677999999992222));
 
-        v.store64aligned(TICK + 6, 0, 0x0000000000000000).unwrap();
-        v.store64aligned(TICK + 6, 8, 0x0000000000000000).unwrap();
+        v.store64(TICK + 6, 0, 0x0000000000000000).unwrap();
+        v.store64(TICK + 6, 8, 0x0000000000000000).unwrap();
         for i in 1..12 {
             let mut val = 0;
             for ind in 0..8 {
                 val |= i << (ind * 4);
             }
-            v.store32(TICK + 7 + i, i.into(), val).unwrap();
+            v.store32(TICK + 7 + i as u64, i.into(), val).unwrap();
         }
 
-        assert_eq!(v.load64aligned(TICK + 30, 0), Some(0x0011223344556677));
-        assert_eq!(v.load64aligned(TICK + 30, 8), Some(0x8899aabbbbbbbb00));
+        assert_eq!(in_order(&v, TICK + 30, 0), Some(0x0011223344556677));
+        assert_eq!(in_order(&v, TICK + 30, 8), Some(0x8899aabbbbbbbb00));
 
         // u16
-        v.store64aligned(TICK + 100, 0, 0x4444444444444444).unwrap();
-        v.store64aligned(TICK + 100, 8, 0x4444444444444444).unwrap();
+        v.store64(TICK + 100, 0, 0x4444444444444444).unwrap();
+        v.store64(TICK + 100, 8, 0x4444444444444444).unwrap();
 
         v.store16(TICK + 101, 0, 0xffff).unwrap();
         v.store16(TICK + 102, 2, 0xeeee).unwrap();
@@ -422,15 +455,15 @@ mod tests {
         v.store16(TICK + 108, 9, 0x5555).unwrap();
         v.store16(TICK + 109, 10, 0x0000).unwrap();
 
-        // println!("{:016x}", v.load64aligned(TICK + 110, 0).unwrap());
-        // println!("{:016x}", v.load64aligned(TICK + 110, 8).unwrap());
+        // println!("{:016x}", in_order(&v, TICK + 110, 0).unwrap());
+        // println!("{:016x}", in_order(&v, TICK + 110, 8).unwrap());
 
-        assert_eq!(v.load64aligned(TICK + 110, 0), Some(0xffffeeeedd998877));
-        assert_eq!(v.load64aligned(TICK + 110, 8), Some(0x6655000044444444));
+        assert_eq!(in_order(&v, TICK + 110, 0), Some(0xffffeeeedd998877));
+        assert_eq!(in_order(&v, TICK + 110, 8), Some(0x6655000044444444));
 
         // u8
-        v.store64aligned(TICK + 200, 0, 0x2222222222222222).unwrap();
-        v.store64aligned(TICK + 200, 8, 0x2222222222222222).unwrap();
+        v.store64(TICK + 200, 0, 0x2222222222222222).unwrap();
+        v.store64(TICK + 200, 8, 0x2222222222222222).unwrap();
 
         v.store8(TICK + 201, 0, 0x33).unwrap();
         v.store8(TICK + 202, 3, 0x33).unwrap();
@@ -438,26 +471,26 @@ mod tests {
         v.store8(TICK + 204, 8, 0x99).unwrap();
         v.store8(TICK + 205, 9, 0x77).unwrap();
 
-        assert_eq!(v.load64aligned(TICK + 210, 0), Some(0x3322223322222288));
-        assert_eq!(v.load64aligned(TICK + 210, 8), Some(0x9977222222222222));
+        assert_eq!(in_order(&v, TICK + 210, 0), Some(0x3322223322222288));
+        assert_eq!(in_order(&v, TICK + 210, 8), Some(0x9977222222222222));
     }
 
     #[test]
     fn loads() {
-        const TICK: u32 = 555;
+        const TICK: Tick = Tick(555);
 
         let mut v = HistMem::new();
 
-        v.store64aligned(TICK, 0, 0x1111111111111111).unwrap();
-        v.store64aligned(TICK, 8, 0x2222222222222222).unwrap();
+        v.store64(TICK, 0, 0x1111111111111111).unwrap();
+        v.store64(TICK, 8, 0x2222222222222222).unwrap();
 
         // u64
         for a in 0..=8 {
             println!("64 {:02x}: {:016x}", a, v.load64(TICK, a).unwrap());
         }
         assert_eq!(v.load64(TICK, 0), Some(0x1111111111111111));
-        assert_eq!(v.load64(TICK, 1), Some(0x1111111111111122));
-        assert_eq!(v.load64(TICK, 7), Some(0x1122222222222222));
+        assert_eq!(v.load64(TICK, 1), Some(0x2211111111111111));
+        assert_eq!(v.load64(TICK, 7), Some(0x2222222222222211));
         assert_eq!(v.load64(TICK, 8), Some(0x2222222222222222));
         assert_eq!(v.load64(TICK, 9), None);
 
@@ -468,9 +501,9 @@ mod tests {
         }
         assert_eq!(v.load32(TICK, 0), Some(0x11111111));
         assert_eq!(v.load32(TICK, 4), Some(0x11111111));
-        assert_eq!(v.load32(TICK, 5), Some(0x11111122));
-        assert_eq!(v.load32(TICK, 6), Some(0x11112222));
-        assert_eq!(v.load32(TICK, 7), Some(0x11222222));
+        assert_eq!(v.load32(TICK, 5), Some(0x22111111));
+        assert_eq!(v.load32(TICK, 6), Some(0x22221111));
+        assert_eq!(v.load32(TICK, 7), Some(0x22222211));
         assert_eq!(v.load32(TICK, 8), Some(0x22222222));
         assert_eq!(v.load32(TICK, 9), Some(0x22222222));
         assert_eq!(v.load32(TICK, 12), Some(0x22222222));
@@ -483,7 +516,7 @@ mod tests {
         }
         assert_eq!(v.load16(TICK, 0), Some(0x1111));
         assert_eq!(v.load16(TICK, 6), Some(0x1111));
-        assert_eq!(v.load16(TICK, 7), Some(0x1122));
+        assert_eq!(v.load16(TICK, 7), Some(0x2211));
         assert_eq!(v.load16(TICK, 8), Some(0x2222));
         assert_eq!(v.load16(TICK, 14), Some(0x2222));
         assert_eq!(v.load16(TICK, 15), None);
@@ -497,4 +530,53 @@ mod tests {
         }
         assert_eq!(v.load8(TICK, 16), None);
     }
+
+    #[test]
+    fn same_as_byte() {
+        let mut qword = HistMem::new();
+        let mut byte = byte::HistMem::new();
+
+        let mut seed: u64 = 0x1234;
+        let mut random = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            seed >> 33
+        };
+        for tick in (0..2000).map(Tick) {
+            let adr = 0xff0 + random() % 0x40;
+            let val = random();
+            match random() % 4 {
+                0 => {
+                    qword.observe64(tick, adr, val).unwrap();
+                    byte.observe64(tick, adr, val).unwrap();
+                }
+                1 => {
+                    qword.store8(tick + 1, adr, val as u8).unwrap();
+                    byte.store8(tick + 1, adr, val as u8).unwrap();
+                }
+                _ => {
+                    qword.store32(tick + 1, adr, val as u32).unwrap();
+                    byte.store32(tick + 1, adr, val as u32).unwrap();
+                }
+            }
+        }
+
+        for tick in (0..2002).step_by(7).map(Tick) {
+            for adr in 0xfef..0x1039 {
+                assert_eq!(qword.load8(tick, adr), byte.load8(tick, adr));
+                assert_eq!(qword.written_at(tick, adr), byte.written_at(tick, adr));
+            }
+            assert_eq!(
+                qword.load_range(tick, 0xfef, 0x4a),
+                byte.load_range(tick, 0xfef, 0x4a)
+            );
+            assert_eq!(
+                qword.changed_between(Tick(tick.0 / 2), tick, 0xff3..0x1031),
+                byte.changed_between(Tick(tick.0 / 2), tick, 0xff3..0x1031)
+            );
+        }
+        for adr in 0xff0..0x1038 {
+            assert_eq!(qword.history(adr), byte.history(adr));
+        }
+        assert_eq!(qword.touched(0..0x3000), byte.touched(0..0x3000));
+    }
 }
@@ -0,0 +1,839 @@
+//! The gdb remote serial protocol over a finished trace, so gdb (and pwndbg,
+//! IDA, ...) can look at any tick. Registers come from the steps, memory from
+//! `HistMem`, and the library list from the symbol tables. Nothing actually
+//! runs: continuing and stepping, both ways, move a cursor through the trace
+//! and stop at breakpoints and watchpoints the same way a live target would.
+//!
+//! target remote :<port>
+//! reverse-continue, reverse-stepi, monitor tick [<tick>]
+
+use crate::{
+    analyzer::{Analysis, Process},
+    arch::Arch,
+    state::{MemoryOpKind, State, Step},
+    tick::Tick,
+};
+use bitflags::Flags;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+use tracing::info;
+
+/// What we tell gdb it may send us, in bytes of hex
+const PACKET_SIZE: usize = 0x4000;
+
+const SIGTRAP: u64 = 5;
+
+pub fn gdb<STEP, const N: usize>(analysis: Analysis<STEP, N>, arch: Arch, port: u16)
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    if analysis.trace.is_empty() {
+        info!("Empty trace, not starting the gdb server.");
+        return;
+    }
+
+    info!("Execution done, starting gdb server on port {}.", port);
+    let server = TcpListener::bind(("127.0.0.1", port)).unwrap();
+
+    // gdb only ever uses one connection, so one at a time
+    for stream in server.incoming() {
+        let result = stream.and_then(|stream| Session::new(&analysis, arch).run(stream));
+        if let Err(e) = result {
+            info!("gdb connection failed: {:?}", e);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, kind: MemoryOpKind) -> bool {
+        match self {
+            WatchKind::Write => kind == MemoryOpKind::Write,
+            WatchKind::Read => kind == MemoryOpKind::Read,
+            WatchKind::Access => true,
+        }
+    }
+
+    /// As named in stop replies
+    fn name(self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Watchpoint {
+    kind: WatchKind,
+    address: u64,
+    len: u64,
+}
+
+/// Why we stopped where we did
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stop {
+    Step,
+    Breakpoint,
+    /// (kind, address accessed)
+    Watch(WatchKind, u64),
+    /// The crash, the fatal signal is delivered at this step
+    Signal(u64),
+    /// Ran out of trace going backwards
+    Begin,
+    /// Ran out of trace going forwards
+    End,
+}
+
+struct Session<'a, STEP, const N: usize>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    analysis: &'a Analysis<STEP, N>,
+    arch: Arch,
+    /// The step about to execute
+    tick: Tick,
+    /// Picked with `Hg` for reading registers, otherwise the one at `tick`
+    thread: Option<u64>,
+    breakpoints: BTreeSet<u64>,
+    watchpoints: Vec<Watchpoint>,
+    /// Why we're at `tick`
+    stop: Stop,
+}
+
+impl<'a, STEP, const N: usize> Session<'a, STEP, N>
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    fn new(analysis: &'a Analysis<STEP, N>, arch: Arch) -> Self {
+        Self {
+            analysis,
+            arch,
+            tick: Tick(0),
+            thread: None,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            stop: Stop::Step,
+        }
+    }
+
+    fn run(mut self, stream: TcpStream) -> io::Result<()> {
+        info!("gdb connected from {}", stream.peer_addr()?);
+        let mut conn = Connection::new(stream);
+
+        while let Some(packet) = conn.read_packet()? {
+            // kill has no reply
+            if packet == "k" {
+                break;
+            }
+            conn.send(&self.handle(&packet))?;
+            match packet.as_str() {
+                "QStartNoAckMode" => conn.ack = false,
+                "D" => break,
+                _ => {}
+            }
+        }
+
+        info!("gdb disconnected");
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        match packet {
+            "?" => self.stop_reply(),
+            "QStartNoAckMode" | "qSymbol::" | "D" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => format!("QC{:x}", self.current_thread()),
+            "qfThreadInfo" => {
+                let threads: Vec<_> = self.threads().iter().map(|t| format!("{:x}", t)).collect();
+                format!("m{}", threads.join(","))
+            }
+            "qsThreadInfo" => "l".to_string(),
+            "qOffsets" => {
+                let base = self.process().table.bias;
+                format!("Text={:x};Data={:x};Bss={:x}", base, base, base)
+            }
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            "c" => self.resume(false, false),
+            "s" => self.resume(true, false),
+            "bc" => self.resume(false, true),
+            "bs" => self.resume(true, true),
+            "g" => hex::encode(self.registers()),
+            _ => {
+                if packet.starts_with("qSupported") {
+                    format!(
+                        "PacketSize={:x};QStartNoAckMode+;qXfer:features:read+;\
+                         qXfer:libraries:read+;qXfer:exec-file:read+;swbreak+;hwbreak+;\
+                         ReverseStep+;ReverseContinue+;vContSupported+",
+                        PACKET_SIZE
+                    )
+                } else if let Some(xfer) = packet.strip_prefix("qXfer:") {
+                    self.xfer(xfer).unwrap_or_else(|| "E00".to_string())
+                } else if let Some(args) = packet.strip_prefix('m') {
+                    self.read_memory(args).unwrap_or_else(|| "E14".to_string())
+                } else if let Some(actions) = packet.strip_prefix("vCont;") {
+                    // one thread moves at a time anyway, so the first action
+                    // is all that matters
+                    match actions.as_bytes().first() {
+                        Some(b's' | b'S') => self.resume(true, false),
+                        Some(b'c' | b'C') => self.resume(false, false),
+                        _ => "E01".to_string(),
+                    }
+                } else if packet.starts_with('C') {
+                    self.resume(false, false)
+                } else if packet.starts_with('S') {
+                    self.resume(true, false)
+                } else if let Some(thread) = packet.strip_prefix("Hg") {
+                    self.thread = parse_thread(thread);
+                    "OK".to_string()
+                } else if packet.starts_with('H') {
+                    "OK".to_string()
+                } else if let Some(thread) = packet.strip_prefix('T') {
+                    match parse_thread(thread) {
+                        Some(thread) if !self.threads().contains(&thread) => "E01".to_string(),
+                        _ => "OK".to_string(),
+                    }
+                } else if let Some(args) = packet.strip_prefix('Z') {
+                    self.point(args, true).unwrap_or_default()
+                } else if let Some(args) = packet.strip_prefix('z') {
+                    self.point(args, false).unwrap_or_default()
+                } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+                    self.monitor(command)
+                } else if let Some(b'M' | b'X' | b'G' | b'P') = packet.as_bytes().first() {
+                    // the past is read only
+                    "E01".to_string()
+                } else {
+                    // not supported
+                    String::new()
+                }
+            }
+        }
+    }
+
+    fn process(&self) -> &'a Process {
+        // `gdb` doesn't start on an empty trace
+        self.analysis.process(self.tick).expect("empty trace")
+    }
+
+    fn current_thread(&self) -> u64 {
+        self.analysis.trace[self.tick.index()].thread()
+    }
+
+    /// Threads of this process that have started by now
+    fn threads(&self) -> Vec<u64> {
+        let pid = self.analysis.trace[self.tick.index()].pid();
+        self.analysis
+            .threads
+            .iter()
+            .filter(|(_, ticks)| {
+                ticks
+                    .first()
+                    .is_some_and(|&t| t <= self.tick && self.analysis.trace[t.index()].pid() == pid)
+            })
+            .map(|(&thread, _)| thread)
+            .collect()
+    }
+
+    fn stop_reply(&self) -> String {
+        let (signal, reason) = match self.stop {
+            Stop::Step => (SIGTRAP, String::new()),
+            Stop::Breakpoint => (SIGTRAP, "swbreak:;".to_string()),
+            Stop::Watch(kind, address) => (SIGTRAP, format!("{}:{:x};", kind.name(), address)),
+            Stop::Signal(signo) => (gdb_signal(signo), String::new()),
+            Stop::Begin => (SIGTRAP, "replaylog:begin;".to_string()),
+            Stop::End => (SIGTRAP, "replaylog:end;".to_string()),
+        };
+        // the libraries at the new tick might not be the ones at the old one
+        format!(
+            "T{:02x}thread:{:x};{}library:;",
+            signal,
+            self.current_thread(),
+            reason
+        )
+    }
+
+    /// Moves to the next stop and tells gdb about it
+    fn resume(&mut self, step: bool, reverse: bool) -> String {
+        let (tick, stop) = match (step, reverse) {
+            (true, false) => self.step_forward(),
+            (true, true) => self.step_backward(),
+            (false, false) => self.continue_forward(),
+            (false, true) => self.continue_backward(),
+        };
+        self.tick = tick;
+        self.stop = stop;
+        self.thread = None;
+        self.stop_reply()
+    }
+
+    fn crashes_at(&self, tick: Tick) -> Option<u64> {
+        let crash = self.analysis.crash.as_ref()?;
+        crash.signal.filter(|_| crash.tick == Some(tick))
+    }
+
+    /// The first access after, or the last one before, `tick` that one of the
+    /// watchpoints catches. The access that is done by the step at `tick`
+    /// counts going forwards.
+    fn watch_hit(&self, tick: Tick, reverse: bool) -> Option<(Tick, Stop)> {
+        let process = self.analysis.process_of[tick.index()];
+        let accesses = &self.analysis.accesses;
+        let ticks = if reverse {
+            Tick(0)..tick
+        } else {
+            tick..Tick(self.analysis.trace.len() as u64)
+        };
+
+        let hits = self.watchpoints.iter().filter_map(|w| {
+            let found = accesses.accesses(process, w.address, w.len, ticks.clone());
+            let mut found = found.into_iter().filter(|a| w.kind.matches(a.kind));
+            let access = if reverse {
+                found.next_back()
+            } else {
+                found.next()
+            }?;
+            Some((
+                access.tick,
+                Stop::Watch(w.kind, access.address.max(w.address)),
+            ))
+        });
+
+        if reverse {
+            hits.max_by_key(|&(tick, _)| tick)
+        } else {
+            hits.min_by_key(|&(tick, _)| tick)
+        }
+    }
+
+    fn step_forward(&self) -> (Tick, Stop) {
+        let ticks = &self.analysis.threads[&self.current_thread()];
+        let Some(&next) = ticks.get(ticks.partition_point(|&t| t <= self.tick)) else {
+            return (self.tick, Stop::End);
+        };
+
+        if let Some(signo) = self.crashes_at(next) {
+            return (next, Stop::Signal(signo));
+        }
+        match self.watch_hit(self.tick, false) {
+            Some((tick, stop)) if tick == self.tick => (next, stop),
+            _ => (next, Stop::Step),
+        }
+    }
+
+    fn step_backward(&self) -> (Tick, Stop) {
+        let ticks = &self.analysis.threads[&self.current_thread()];
+        match ticks[..ticks.partition_point(|&t| t < self.tick)].last() {
+            Some(&prev) => (prev, Stop::Step),
+            None => (self.tick, Stop::Begin),
+        }
+    }
+
+    fn continue_forward(&self) -> (Tick, Stop) {
+        let ticks = &self.process().ticks;
+        let after = &ticks[ticks.partition_point(|&t| t <= self.tick)..];
+
+        // a watchpoint stops us right after the step that did the access
+        let watch = self.watch_hit(self.tick, false).map(|(tick, stop)| {
+            let next = after.get(after.partition_point(|&t| t <= tick));
+            (next.copied().unwrap_or(tick), stop)
+        });
+
+        for &tick in after {
+            if let Some((at, stop)) = watch.filter(|&(at, _)| at <= tick) {
+                return (at, stop);
+            }
+            if let Some(signo) = self.crashes_at(tick) {
+                return (tick, Stop::Signal(signo));
+            }
+            let pc = self.analysis.trace[tick.index()].state().pc();
+            if self.breakpoints.contains(&pc) {
+                return (tick, Stop::Breakpoint);
+            }
+        }
+
+        watch.unwrap_or((*ticks.last().unwrap(), Stop::End))
+    }
+
+    fn continue_backward(&self) -> (Tick, Stop) {
+        let ticks = &self.process().ticks;
+        let before = &ticks[..ticks.partition_point(|&t| t < self.tick)];
+
+        // going backwards we stop at the step that did the access
+        let watch = self.watch_hit(self.tick, true);
+
+        for &tick in before.iter().rev() {
+            if let Some((at, stop)) = watch.filter(|&(at, _)| at >= tick) {
+                return (at, stop);
+            }
+            let pc = self.analysis.trace[tick.index()].state().pc();
+            if self.breakpoints.contains(&pc) {
+                return (tick, Stop::Breakpoint);
+            }
+        }
+
+        watch.unwrap_or((ticks[0], Stop::Begin))
+    }
+
+    /// The registers of the thread picked by `Hg`, in gdb's default order for
+    /// the architecture, since our target description only names that
+    fn registers(&self) -> Vec<u8> {
+        let thread = self.thread.unwrap_or_else(|| self.current_thread());
+        let ticks = self
+            .analysis
+            .threads
+            .get(&thread)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // a thread that isn't running is where its next step starts
+        let tick = ticks
+            .get(ticks.partition_point(|&t| t < self.tick))
+            .or(ticks.last())
+            .copied()
+            .unwrap_or(self.tick);
+        let state = self.analysis.trace[tick.index()].state();
+
+        let regs = state.regs();
+        let flags = state.flags().bits();
+        let mut out = Vec::new();
+        match self.arch {
+            Arch::X86_64 => {
+                // rax rbx rcx rdx rsi rdi rbp rsp r8-r15
+                for idx in [0, 3, 1, 2, 6, 7, 5, 4, 8, 9, 10, 11, 12, 13, 14, 15] {
+                    out.extend_from_slice(&regs[idx].to_le_bytes());
+                }
+                out.extend_from_slice(&state.pc().to_le_bytes());
+                out.extend_from_slice(&flags.to_le_bytes());
+                // cs ss ds es fs gs
+                for selector in [0x33u32, 0x2b, 0, 0, 0, 0] {
+                    out.extend_from_slice(&selector.to_le_bytes());
+                }
+            }
+            Arch::ARM64 => {
+                // x0-x30 sp
+                for reg in regs {
+                    out.extend_from_slice(&reg.to_le_bytes());
+                }
+                out.extend_from_slice(&state.pc().to_le_bytes());
+                out.extend_from_slice(&flags.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// `addr,len`. Bytes we never saw read as zero as long as they're mapped,
+    /// like in a core file.
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, len) = args.split_once(',')?;
+        let address = u64::from_str_radix(address, 16).ok()?;
+        let len = u64::from_str_radix(len, 16).ok()?;
+        let len = len.min(PACKET_SIZE as u64 / 2).min(u64::MAX - address);
+
+        let process = self.process();
+        let bytes = process.mem.load_range(self.tick, address, len);
+        let mut out = Vec::new();
+        for (adr, byte) in (address..).zip(bytes) {
+            match byte {
+                Some(byte) => out.push(byte),
+                None if process.maps.lookup(adr, self.tick).is_some() => out.push(0),
+                None => break,
+            }
+        }
+
+        (!out.is_empty() || len == 0).then(|| hex::encode(out))
+    }
+
+    /// `Z`/`z` without the letter: `type,addr,kind`
+    fn point(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let address = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let len = u64::from_str_radix(parts.next()?, 16).ok()?;
+
+        let kind = match kind {
+            // software and hardware breakpoints are the same thing to us
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+
+        let watchpoint = Watchpoint { kind, address, len };
+        if insert {
+            self.watchpoints.push(watchpoint);
+        } else if let Some(idx) = self.watchpoints.iter().position(|w| *w == watchpoint) {
+            self.watchpoints.remove(idx);
+        }
+        Some("OK".to_string())
+    }
+
+    /// `object:read:annex:offset,length`
+    fn xfer(&self, xfer: &str) -> Option<String> {
+        let mut parts = xfer.splitn(4, ':');
+        let (object, op, annex, window) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if op != "read" {
+            return None;
+        }
+
+        let document = match (object, annex) {
+            ("features", "target.xml") => {
+                let architecture = match self.arch {
+                    Arch::X86_64 => "i386:x86-64",
+                    Arch::ARM64 => "aarch64",
+                };
+                format!(
+                    "<?xml version=\"1.0\"?><target version=\"1.0\">\
+                     <architecture>{}</architecture><osabi>GNU/Linux</osabi></target>",
+                    architecture
+                )
+            }
+            ("libraries", "") => self.libraries(),
+            ("exec-file", _) => self.process().path.clone()?,
+            _ => return None,
+        };
+
+        let (offset, length) = window.split_once(',')?;
+        let offset = usize::from_str_radix(offset, 16).ok()?;
+        let length = usize::from_str_radix(length, 16).ok()?;
+
+        let rest = document.get(offset.min(document.len())..)?;
+        if rest.len() > length {
+            Some(format!("m{}", &rest[..length]))
+        } else {
+            Some(format!("l{}", rest))
+        }
+    }
+
+    /// Everything but the program itself, which gdb knows from `qOffsets`
+    fn libraries(&self) -> String {
+        let process = self.process();
+
+        // the loader maps each segment on its own, so one library can be
+        // spread over several tables
+        let mut libraries: Vec<(&str, Vec<u64>)> = Vec::new();
+        for table in process.table.tables_at(self.tick) {
+            if std::ptr::eq(table, &process.table) {
+                continue;
+            }
+            let segments = table.segments();
+            match libraries
+                .iter_mut()
+                .find(|(path, _)| *path == table.binary_path)
+            {
+                Some((_, known)) => known.extend(segments),
+                None => libraries.push((&table.binary_path, segments)),
+            }
+        }
+
+        let mut xml = String::from("<library-list>");
+        for (path, mut segments) in libraries {
+            if segments.is_empty() {
+                continue;
+            }
+            segments.sort_unstable();
+            segments.dedup();
+
+            xml.push_str(&format!("<library name=\"{}\">", xml_escape(path)));
+            for segment in segments {
+                xml.push_str(&format!("<segment address=\"0x{:x}\"/>", segment));
+            }
+            xml.push_str("</library>");
+        }
+        xml.push_str("</library-list>");
+        xml
+    }
+
+    /// `monitor tick` says where we are, `monitor tick <n>` goes there
+    fn monitor(&mut self, command: &str) -> String {
+        let command = hex::decode(command)
+            .ok()
+            .and_then(|c| String::from_utf8(c).ok())
+            .unwrap_or_default();
+        let mut words = command.split_whitespace();
+
+        let output = match (words.next(), words.next()) {
+            (Some("tick"), None) => format!("tick {}\n", self.tick),
+            (Some("tick"), Some(tick)) => match tick.parse::<Tick>() {
+                Ok(tick) if tick.index() < self.analysis.trace.len() => {
+                    self.tick = tick;
+                    self.stop = Stop::Step;
+                    self.thread = None;
+                    format!(
+                        "tick {}, run `maint flush register-cache` to see it\n",
+                        self.tick
+                    )
+                }
+                _ => format!("no tick {}\n", tick),
+            },
+            _ => "monitor tick [<tick>]\n".to_string(),
+        };
+        hex::encode(output)
+    }
+}
+
+/// `-1` and `0` mean any thread, the pid part is ignored
+fn parse_thread(thread: &str) -> Option<u64> {
+    let thread = thread.rsplit('.').next()?;
+    match u64::from_str_radix(thread, 16) {
+        Ok(0) | Err(_) => None,
+        Ok(thread) => Some(thread),
+    }
+}
+
+/// gdb numbers signals its own way, the ones that differ from Linux
+fn gdb_signal(signo: u64) -> u64 {
+    match signo {
+        // SIGBUS
+        7 => 10,
+        // SIGUSR1
+        10 => 30,
+        // SIGUSR2
+        12 => 31,
+        // SIGSYS
+        31 => 12,
+        signo => signo,
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+/// `$data#cs`, with the bytes the protocol uses escaped
+fn frame(data: &str) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data.as_bytes() {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            escaped.extend_from_slice(&[b'}', b ^ 0x20]);
+        } else {
+            escaped.push(b);
+        }
+    }
+
+    let mut packet = vec![b'$'];
+    packet.extend_from_slice(&escaped);
+    packet.extend_from_slice(format!("#{:02x}", checksum(&escaped)).as_bytes());
+    packet
+}
+
+struct Connection<S> {
+    stream: BufReader<S>,
+    /// Until gdb asks us to stop acknowledging packets
+    ack: bool,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            ack: true,
+        }
+    }
+
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.stream.fill_buf()?.first().copied();
+        if byte.is_some() {
+            self.stream.consume(1);
+        }
+        Ok(byte)
+    }
+
+    /// The next packet, `None` once gdb hangs up. Acks and interrupts are
+    /// skipped, nothing is ever running for gdb to interrupt.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum)?;
+            let sum = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+
+            if self.ack {
+                let ok = sum == Some(checksum(&data));
+                self.stream
+                    .get_mut()
+                    .write_all(if ok { b"+" } else { b"-" })?;
+                if !ok {
+                    continue;
+                }
+            }
+
+            // only writes carry binary data, and we don't do those
+            let mut unescaped = Vec::with_capacity(data.len());
+            let mut bytes = data.into_iter();
+            while let Some(b) = bytes.next() {
+                match b {
+                    b'}' => unescaped.extend(bytes.next().map(|b| b ^ 0x20)),
+                    b => unescaped.push(b),
+                }
+            }
+            return Ok(Some(String::from_utf8_lossy(&unescaped).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.stream.get_mut().write_all(&frame(data))?;
+        self.stream.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{analyzer::testing, state::X64Step, tracer::parser::Message};
+
+    /// Both ends of a connection in one, reads from `input`
+    struct Pipe {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn packets() {
+        assert_eq!(frame("OK"), b"$OK#9a");
+        assert_eq!(frame("a#b"), b"$a}\x03b#43");
+
+        let input = b"+$qC#b4\x03$m0,1#00$vCont;c#a8".to_vec();
+        let mut conn = Connection::new(Pipe {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        });
+
+        assert_eq!(conn.read_packet().unwrap().as_deref(), Some("qC"));
+        // the bad checksum gets a nack and is skipped
+        assert_eq!(conn.read_packet().unwrap().as_deref(), Some("vCont;c"));
+        assert_eq!(conn.read_packet().unwrap(), None);
+        assert_eq!(conn.stream.get_ref().output, b"+-+");
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(parse_thread("-1"), None);
+        assert_eq!(parse_thread("0"), None);
+        assert_eq!(parse_thread("1f"), Some(0x1f));
+        assert_eq!(parse_thread("p2.1f"), Some(0x1f));
+    }
+
+    #[test]
+    fn session() {
+        const NOP: &[u8] = &[0x90];
+        // mov [rbx], rax and back
+        const STORE: &[u8] = &[0x48, 0x89, 0x03];
+        const LOAD: &[u8] = &[0x48, 0x8b, 0x03];
+
+        // every register different, so the order shows
+        let regs: [u64; 16] = std::array::from_fn(|i| (i as u64 + 1) * 0x1111);
+        let steps: [(u64, &[u8], &[Message]); 6] = [
+            (0x401000, NOP, &[]),
+            (0x401001, STORE, &[Message::Store(0x5000, 7, 8)]),
+            (0x401004, NOP, &[]),
+            (0x401005, LOAD, &[Message::Load(0x5000, 7, 8)]),
+            (0x401008, NOP, &[]),
+            (0x401009, LOAD, &[Message::Load(0x6000, 0, 8)]),
+        ];
+        let mut raw = testing::libload();
+        for (pc, code, extra) in steps {
+            raw.extend(testing::x64_step_with(pc, regs, code, extra));
+        }
+        // killed by the last step
+        raw.extend(testing::encode(&Message::Signal(11)));
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        let analysis = testing::analyze(steps, Arch::X86_64);
+
+        let mut session = Session::new(&analysis, Arch::X86_64);
+        let thread = session.current_thread();
+        let stop = |signal: u64, reason: &str| {
+            format!("T{:02x}thread:{:x};{}library:;", signal, thread, reason)
+        };
+        let mut send = |packet: &str| (session.handle(packet), session.tick);
+
+        // breakpoints both ways
+        assert_eq!(send("Z0,401004,1"), ("OK".to_string(), Tick(0)));
+        assert_eq!(send("c"), (stop(5, "swbreak:;"), Tick(2)));
+        assert_eq!(send("c"), (stop(11, ""), Tick(5)));
+        assert_eq!(send("bc"), (stop(5, "swbreak:;"), Tick(2)));
+        assert_eq!(send("z0,401004,1"), ("OK".to_string(), Tick(2)));
+        assert_eq!(send("bc"), (stop(5, "replaylog:begin;"), Tick(0)));
+
+        // forwards we stop after the access, backwards at it
+        assert_eq!(send("Z2,5000,8"), ("OK".to_string(), Tick(0)));
+        assert_eq!(send("c"), (stop(5, "watch:5000;"), Tick(2)));
+        assert_eq!(send("z2,5000,8"), ("OK".to_string(), Tick(2)));
+        assert_eq!(send("Z3,5004,4"), ("OK".to_string(), Tick(2)));
+        assert_eq!(send("c"), (stop(5, "rwatch:5004;"), Tick(4)));
+        assert_eq!(send("bc"), (stop(5, "rwatch:5004;"), Tick(3)));
+        assert_eq!(send("z3,5004,4"), ("OK".to_string(), Tick(3)));
+        assert_eq!(send("c"), (stop(11, ""), Tick(5)));
+        assert_eq!(send("c"), (stop(5, "replaylog:end;"), Tick(5)));
+
+        // rax rbx rcx rdx rsi rdi rbp rsp r8-r15, rip, eflags, selectors
+        let g = hex::decode(send("g").0).unwrap();
+        assert_eq!(g.len(), 17 * 8 + 4 + 6 * 4);
+        let words: Vec<u64> = g[..17 * 8]
+            .chunks(8)
+            .map(|w| u64::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        let order = [0, 3, 1, 2, 6, 7, 5, 4, 8, 9, 10, 11, 12, 13, 14, 15];
+        let expected: Vec<u64> = order
+            .iter()
+            .map(|&idx| regs[idx])
+            .chain([0x401009])
+            .collect();
+        assert_eq!(words, expected);
+    }
+}
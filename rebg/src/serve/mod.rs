@@ -1,17 +1,24 @@
-use crate::analyzer::Analysis;
+use crate::abi::CallingConvention;
+use crate::analyzer::{coredump, heap, ltrace, slice, taint, uninit, watch, Analysis, Invocation};
 use crate::dis::regs::Reg;
+use crate::signal;
 use crate::state::MemoryOpKind;
+use crate::tick::{self, Tick};
 use crate::{
     arch::Arch,
     state::{State, Step},
 };
 use itertools::Itertools;
 use serde_json::json;
+use std::collections::HashMap;
 use std::fmt;
 use std::net::{TcpListener, TcpStream};
 use tracing::info;
 use tungstenite::{accept, WebSocket};
 
+mod gdb;
+pub use gdb::gdb;
+
 pub fn ws<STEP, const N: usize>(analysis: Analysis<STEP, N>, arch: Arch)
 where
     STEP: Step<N> + fmt::Debug + std::marker::Sync,
@@ -36,9 +43,45 @@ where
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 enum RebgRequest {
-    Registers(u64),
+    // tick
+    Registers(Tick),
     // (from, count, tick)
-    Memory(u64, u8, u32),
+    Memory(u64, u8, Tick),
+    // (address, tick), the tick picks the process
+    MemoryHistory(u64, Tick),
+    // (from, len, tick, tick), the second tick picks the process
+    MemoryDiff(u64, u64, Tick, Tick),
+    Threads,
+    ThreadSteps(u64),
+    // (thread, tick)
+    ThreadRegisters(u64, Tick),
+    Processes,
+    // index into the process list, not a pid
+    ProcessSteps(usize),
+    Crash,
+    // by function name, calls through the PLT included
+    Invocations(String),
+    // index into the invocation list
+    InvocationReturn(usize),
+    LibraryCalls,
+    Heap,
+    Uninit,
+    // (tick, register name or hex address)
+    Slice(Tick, String),
+    // (sources, tick to get the taint state at)
+    Taint(Vec<String>, Option<Tick>),
+    // (address, len, from tick, to tick)
+    Accesses(u64, u64, Tick, Tick),
+    // (address, len, tick)
+    LastWrite(u64, u64, Tick),
+    // (fd, tick)
+    FileDescriptor(i32, Tick),
+    // index into the process list
+    FileDescriptors(usize),
+    // tick
+    MemoryMap(Tick),
+    // tick, the core file comes back hex encoded
+    Core(Tick),
 }
 
 fn handle<STEP, const N: usize>(
@@ -50,17 +93,36 @@ fn handle<STEP, const N: usize>(
 {
     let Analysis {
         trace,
-        insns,
+        insns: _,
         instrumentations,
         bt_lens,
-        table,
-        mem,
+        threads,
+        processes,
+        process_of,
+        invocations,
+        accesses,
+        crash,
     } = analysis;
 
-    // first send all addresses etc
-    let iter = trace
+    // which registers the call annotations refer to
+    let cc = CallingConvention::of(arch);
+    let abi = json!({"abi": {
+        "name": cc.name,
+        "args": cc.args.iter().map(|r| r.as_str()).collect::<Vec<_>>(),
+        "ret": cc.ret.as_str(),
+    }});
+    let abi = serde_json::to_string(&abi).unwrap();
+    ws.send(tungstenite::Message::Text(abi)).unwrap();
+
+    // annotate the call and the return of each invocation
+    let calls: HashMap<Tick, &Invocation> = invocations.iter().map(|inv| (inv.call, inv)).collect();
+    let returns: HashMap<Tick, u64> = invocations
         .iter()
-        .enumerate()
+        .filter_map(|inv| Some((inv.ret?, inv.ret_value?)))
+        .collect();
+
+    // first send all addresses etc
+    let iter = tick::enumerate(trace)
         .zip(instrumentations.iter())
         .zip(bt_lens);
 
@@ -70,27 +132,54 @@ fn handle<STEP, const N: usize>(
         let mut parts = Vec::new();
 
         for (((i, step), instru), bt_len) in chunk {
-            let symbolized = table
-                .lookup(step.state().pc())
+            let symbolized = analysis
+                .process(i)
+                .and_then(|p| p.table.lookup_at(step.state().pc(), i))
                 .map(|sy| sy.to_string())
                 .unwrap_or("".to_string());
 
-            parts.push(json!({"i": i, "a": step.state().pc(), "c": instru.disassembly, "d": bt_len, "s": symbolized}));
+            let call_args = calls.get(&i).map(|inv| &inv.args);
+            let ret_value = returns.get(&i);
+
+            parts.push(json!({"i": i, "a": step.state().pc(), "c": instru.disassembly, "d": bt_len, "s": symbolized, "t": step.thread(), "p": step.pid(), "g": instru.signal, "f": call_args, "r": ret_value}));
         }
 
         let json = serde_json::to_string(&json!({"steps": parts})).unwrap();
         ws.send(tungstenite::Message::Text(json)).unwrap();
     }
 
-    let strace: Vec<_> = trace
-        .iter()
-        .enumerate()
+    let strace: Vec<_> = tick::enumerate(trace)
         .filter_map(|(i, step)| step.strace().map(|strace| (i, strace)))
         .map(|(i, s)| json!([i, s]))
         .collect();
     let strace = serde_json::to_string(&json!({"strace": strace})).unwrap();
     ws.send(tungstenite::Message::Text(strace)).unwrap();
 
+    let syscalls: Vec<_> = tick::enumerate(trace)
+        .filter_map(|(i, step)| step.syscall().map(|syscall| json!([i, syscall])))
+        .collect();
+    let syscalls = serde_json::to_string(&json!({ "syscalls": syscalls })).unwrap();
+    ws.send(tungstenite::Message::Text(syscalls)).unwrap();
+
+    // memory the kernel wrote, the syscall at that tick is the writer
+    let syscall_writes: Vec<_> = tick::enumerate(trace)
+        .flat_map(|(i, step)| {
+            step.syscall_writes()
+                .iter()
+                .map(move |w| json!([i, w.address, w.bytes.len()]))
+        })
+        .collect();
+    let syscall_writes =
+        serde_json::to_string(&json!({ "syscall_writes": syscall_writes })).unwrap();
+    ws.send(tungstenite::Message::Text(syscall_writes)).unwrap();
+
+    let signals: Vec<_> = tick::enumerate(trace)
+        .filter_map(|(i, step)| step.signal().map(|signo| (i, signo)))
+        .map(|(i, signo)| json!([i, signo, signal::name(signo)]))
+        .collect();
+    let signals = serde_json::to_string(&json!({ "signals": signals })).unwrap();
+    ws.send(tungstenite::Message::Text(signals)).unwrap();
+
     // then send register values on request
     loop {
         let msg = ws.read().unwrap();
@@ -111,77 +200,396 @@ fn handle<STEP, const N: usize>(
         let msg: RebgRequest = serde_json::from_str(&msg).unwrap();
         match msg {
             RebgRequest::Registers(idx) => {
-                // show current values
-                let step = trace.get(idx as usize).unwrap();
-                let cur_regs = step.state().regs();
+                let serialized = serde_json::to_string(&registers(analysis, arch, idx)).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Threads => {
+                let threads: Vec<_> = threads
+                    .iter()
+                    .map(|(id, ticks)| {
+                        json!({
+                            "id": id,
+                            "first": ticks.first(),
+                            "last": ticks.last(),
+                            "count": ticks.len(),
+                        })
+                    })
+                    .collect();
 
-                // with markings based on what happen from the PREV step
-                let insn = insns.get(idx as usize);
-                let mut modifiers = vec![String::new(); cur_regs.len()];
+                let serialized = serde_json::to_string(&json!({ "threads": threads })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::ThreadSteps(thread) => {
+                let ticks = threads.get(&thread).cloned().unwrap_or_default();
 
-                if let Some(insn) = insn {
-                    for idx in insn.read.iter().flat_map(|r| r.canonical().idx()) {
-                        modifiers[idx].push('r');
-                    }
+                let serialized = serde_json::to_string(
+                    &json!({"thread_steps": {"thread": thread, "ticks": ticks}}),
+                )
+                .unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::ThreadRegisters(thread, tick) => {
+                // the last step the thread ran at or before tick
+                let idx = threads.get(&thread).and_then(|ticks| {
+                    let pos = ticks.partition_point(|&t| t <= tick);
+                    pos.checked_sub(1).map(|p| ticks[p])
+                });
 
-                    for idx in insn.write.iter().flat_map(|r| r.canonical().idx()) {
-                        modifiers[idx].push('w');
+                let serialized = match idx {
+                    Some(idx) => registers(analysis, arch, idx),
+                    None => {
+                        json!({"error": format!("thread {} has not run by tick {}", thread, tick)})
                     }
-                }
-
-                let pairs: Vec<_> = cur_regs
+                };
+                let serialized = serde_json::to_string(&serialized).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Processes => {
+                let processes: Vec<_> = processes
                     .iter()
-                    .zip(modifiers)
                     .enumerate()
-                    .map(|(idx, (value, modifier))| {
-                        let name = Reg::from_idx(arch, idx).unwrap().as_str();
-                        (name, value, modifier)
+                    .map(|(idx, p)| {
+                        json!({
+                            "idx": idx,
+                            "pid": p.pid,
+                            "parent": p.parent,
+                            "path": p.path,
+                            "first": p.ticks.first(),
+                            "last": p.ticks.last(),
+                            "count": p.ticks.len(),
+                        })
                     })
                     .collect();
 
-                let (mem_reads, mem_writes) = {
-                    let mut reads = Vec::new();
-                    let mut writes = Vec::new();
+                let serialized = serde_json::to_string(&json!({ "processes": processes })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::ProcessSteps(idx) => {
+                let ticks = processes
+                    .get(idx)
+                    .map(|p| p.ticks.clone())
+                    .unwrap_or_default();
 
-                    for op in step.memory_ops() {
-                        let deserialized = op.value.as_u64();
+                let serialized =
+                    serde_json::to_string(&json!({"process_steps": {"idx": idx, "ticks": ticks}}))
+                        .unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Invocations(name) => {
+                let matching: Vec<_> = invocations
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, inv)| inv.is_call_to(&name))
+                    .map(|(idx, inv)| json!({"idx": idx, "invocation": inv}))
+                    .collect();
 
-                        match op.kind {
-                            MemoryOpKind::Read => reads.push((op.address, deserialized)),
-                            MemoryOpKind::Write => writes.push((op.address, deserialized)),
-                        }
+                let serialized = serde_json::to_string(
+                    &json!({"invocations": {"symbol": name, "invocations": matching}}),
+                )
+                .unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::InvocationReturn(idx) => {
+                let serialized = match invocations.get(idx) {
+                    Some(inv) => {
+                        json!({"invocation_return": {"idx": idx, "tick": inv.ret, "observed": inv.observed}})
+                    }
+                    None => json!({"error": format!("no invocation {}", idx)}),
+                };
+                let serialized = serde_json::to_string(&serialized).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::FileDescriptor(fd, tick) => {
+                let reply = match analysis.process(tick) {
+                    Some(process) => {
+                        let found = process.fds.at(fd, tick);
+                        json!({"file_descriptor": {"fd": fd, "tick": tick, "file": found}})
                     }
+                    None => json!({"file_descriptor": null, "error": format!("no tick {}", tick)}),
+                };
+                let serialized = serde_json::to_string(&reply).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::FileDescriptors(idx) => {
+                let timeline = processes.get(idx).map(|p| &p.fds.timeline);
 
-                    (reads, writes)
+                let serialized = serde_json::to_string(
+                    &json!({"file_descriptors": {"idx": idx, "timeline": timeline}}),
+                )
+                .unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::MemoryMap(tick) => {
+                let reply = match analysis.process(tick) {
+                    Some(process) => {
+                        let regions = process.maps.at(tick);
+                        json!({"memory_map": {"tick": tick, "regions": regions}})
+                    }
+                    None => json!({"memory_map": null, "error": format!("no tick {}", tick)}),
                 };
+                let serialized = serde_json::to_string(&reply).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Core(tick) => {
+                let reply = if tick.index() < trace.len() {
+                    let core = coredump::coredump(analysis, arch, tick);
+                    json!({"core": {"tick": tick, "size": core.len(), "data": hex::encode(core)}})
+                } else {
+                    json!({"core": null, "error": format!("no tick {}", tick)})
+                };
+                let serialized = serde_json::to_string(&reply).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::LibraryCalls => {
+                let calls = ltrace::library_calls(analysis);
+                let serialized = serde_json::to_string(&json!({ "library_calls": calls })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Heap => {
+                let report = heap::heap(analysis);
+                let serialized = serde_json::to_string(&json!({ "heap": report })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Uninit => {
+                let reads = uninit::uninit(analysis);
+                let serialized = serde_json::to_string(&json!({ "uninit": reads })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Slice(tick, location) => {
+                let slice = slice::Location::parse(arch, &location)
+                    .filter(|_| tick.index() < trace.len())
+                    .map(|location| slice::slice(analysis, tick, location, slice::DEFAULT_LIMIT));
+                let serialized = serde_json::to_string(&json!({ "slice": slice })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Taint(sources, tick) => {
+                let sources: Result<Vec<taint::Source>, _> =
+                    sources.iter().map(|s| s.parse()).collect();
+                let serialized = match sources {
+                    Ok(sources) => {
+                        let report = taint::taint(analysis, arch, sources, tick);
+                        serde_json::to_string(&json!({ "taint": report }))
+                    }
+                    Err(e) => serde_json::to_string(&json!({ "taint": null, "error": e })),
+                }
+                .unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Accesses(address, len, from, to) => {
+                let found: Vec<_> = match process_of.get(from.index()) {
+                    Some(&process) => accesses
+                        .accesses(process, address, len, from..to)
+                        .into_iter()
+                        .map(|access| access_json(analysis, access))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let serialized = serde_json::to_string(&json!({ "accesses": found })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::LastWrite(address, len, tick) => {
+                let found = process_of
+                    .get(tick.index())
+                    .and_then(|&process| accesses.last_write(process, address, len, tick))
+                    .map(|access| access_json(analysis, access));
+                let serialized = serde_json::to_string(&json!({ "last_write": found })).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::Crash => {
+                let crash = crash.as_ref().map(|crash| {
+                    let sym = |adr: u64| {
+                        crash
+                            .tick
+                            .and_then(|tick| analysis.process(tick)?.table.lookup_at(adr, tick))
+                            .map(|sy| sy.to_string())
+                    };
 
-                let serialized =
-                    serde_json::to_string(&json!({"registers": {"idx": idx, "registers": pairs}, "mem_ops": {"r": mem_reads, "w": mem_writes}}))
-                        .unwrap();
+                    let backtrace: Vec<_> = crash
+                        .pc
+                        .into_iter()
+                        .chain(crash.backtrace.iter().rev().copied())
+                        .map(|adr| json!([adr, sym(adr)]))
+                        .collect();
 
+                    json!({
+                        "signal": crash.signal,
+                        "name": crash.signal.and_then(signal::name),
+                        "truncated": crash.truncated,
+                        "tick": crash.tick,
+                        "pc": crash.pc,
+                        "last_access": crash.last_access.map(|(tick, op)| json!({
+                            "tick": tick,
+                            "kind": match op.kind {
+                                MemoryOpKind::Read => "r",
+                                MemoryOpKind::Write => "w",
+                            },
+                            "address": op.address,
+                            "value": op.value.as_u64(),
+                        })),
+                        "backtrace": backtrace,
+                        "last_insns": crash.last_insns,
+                    })
+                });
+
+                let serialized = serde_json::to_string(&json!({ "crash": crash })).unwrap();
                 ws.send(tungstenite::Message::Text(serialized)).unwrap();
             }
             RebgRequest::Memory(from, cnt, tick) => {
+                // the address space of whoever is running at that tick
+                let Some(process) = analysis.process(tick) else {
+                    let serialized = serde_json::to_string(
+                        &json!({"memory": null, "error": format!("no tick {}", tick)}),
+                    )
+                    .unwrap();
+                    ws.send(tungstenite::Message::Text(serialized)).unwrap();
+                    continue;
+                };
+                let mem = &process.mem;
                 let mut output = Vec::new();
 
-                for offset in 0..cnt {
+                let bytes = mem.load_range(tick, from, cnt as u64 * 8);
+                for (offset, chunk) in bytes.chunks(8).enumerate() {
                     let base = from + offset as u64 * 8;
 
-                    let chunk: Vec<_> = (base..base + 8)
-                        .map(|adr| {
-                            if let Some(value) = mem.load8(tick, adr) {
-                                format!("{:02x}", value)
-                            } else {
-                                String::from("??")
-                            }
+                    let chunk: Vec<_> = chunk
+                        .iter()
+                        .map(|value| match value {
+                            Some(value) => format!("{:02x}", value),
+                            None => String::from("??"),
                         })
                         .collect();
 
                     output.push((base, chunk))
                 }
-                let serialized = serde_json::to_string(&json!({"memory": output})).unwrap();
+
+                // so the addresses can be labeled
+                let end = from + cnt as u64 * 8;
+                let regions: Vec<_> = process
+                    .maps
+                    .at(tick)
+                    .into_iter()
+                    .filter(|r| r.start < end && from < r.end)
+                    .map(|r| json!([r.start, r.end, r.to_string()]))
+                    .collect();
+
+                let serialized =
+                    serde_json::to_string(&json!({"memory": output, "regions": regions})).unwrap();
                 ws.send(tungstenite::Message::Text(serialized)).unwrap();
             }
+            RebgRequest::MemoryHistory(address, tick) => {
+                let reply = match analysis.process(tick) {
+                    Some(process) => json!({ "history": process.mem.history(address) }),
+                    None => json!({"history": null, "error": format!("no tick {}", tick)}),
+                };
+                let serialized = serde_json::to_string(&reply).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+            RebgRequest::MemoryDiff(from, len, before, after) => {
+                let reply = match analysis.process(after) {
+                    Some(process) => {
+                        let changed: Vec<_> = process
+                            .mem
+                            .changed_between(before, after, from..from + len)
+                            .into_iter()
+                            .map(|run| json!([run.start, run.end]))
+                            .collect();
+                        json!({ "changed": changed })
+                    }
+                    None => json!({"changed": null, "error": format!("no tick {}", after)}),
+                };
+                let serialized = serde_json::to_string(&reply).unwrap();
+                ws.send(tungstenite::Message::Text(serialized)).unwrap();
+            }
+        }
+    }
+}
+
+/// An access along with where it happened
+fn access_json<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    access: &watch::Access,
+) -> serde_json::Value
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let pc = analysis.trace[access.tick.index()].state().pc();
+    let location = analysis
+        .process(access.tick)
+        .and_then(|p| p.table.lookup_at(pc, access.tick))
+        .map(|sy| sy.to_string());
+    json!({
+        "tick": access.tick,
+        "kind": access.kind,
+        "address": access.address,
+        "size": access.size,
+        "value": access.value,
+        "pc": pc,
+        "location": location,
+    })
+}
+
+/// Registers at `idx`, marked with what the instruction read and wrote
+fn registers<STEP, const N: usize>(
+    analysis: &Analysis<STEP, N>,
+    arch: Arch,
+    idx: Tick,
+) -> serde_json::Value
+where
+    STEP: Step<N> + fmt::Debug,
+{
+    let Analysis { trace, insns, .. } = analysis;
+
+    // show current values
+    let step = trace.get(idx.index()).unwrap();
+    let cur_regs = step.state().regs();
+
+    // with markings based on what happen from the PREV step
+    let insn = insns.get(idx.index());
+    let mut modifiers = vec![String::new(); cur_regs.len()];
+
+    if let Some(insn) = insn {
+        for idx in insn.read.iter().flat_map(|r| r.canonical().idx()) {
+            modifiers[idx].push('r');
+        }
+
+        for idx in insn.write.iter().flat_map(|r| r.canonical().idx()) {
+            modifiers[idx].push('w');
         }
     }
+
+    let mut pairs: Vec<_> = cur_regs
+        .iter()
+        .zip(modifiers)
+        .enumerate()
+        .map(|(idx, (value, modifier))| {
+            let name = Reg::from_idx(arch, idx).unwrap().as_str();
+            (name, *value, modifier)
+        })
+        .collect();
+
+    // fs_base, tpidr_el0 etc. never show up in the read/write sets
+    pairs.extend(
+        step.state()
+            .bases()
+            .into_iter()
+            .map(|(name, value)| (name, value, String::new())),
+    );
+
+    let (mem_reads, mem_writes) = {
+        let mut reads = Vec::new();
+        let mut writes = Vec::new();
+
+        for op in step.memory_ops() {
+            let deserialized = op.value.as_u64();
+
+            match op.kind {
+                MemoryOpKind::Read => reads.push((op.address, deserialized)),
+                MemoryOpKind::Write => writes.push((op.address, deserialized)),
+            }
+        }
+
+        (reads, writes)
+    };
+
+    json!({"registers": {"idx": idx, "thread": step.thread(), "registers": pairs}, "mem_ops": {"r": mem_reads, "w": mem_writes}})
 }
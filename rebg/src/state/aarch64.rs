@@ -1,7 +1,10 @@
-use super::{Branching, GenericState, GenericStep, Instrument, MemoryOp, State, Step};
+use super::{
+    Branching, GenericState, GenericStep, Instrument, MemoryOp, State, Step, SyscallWrite,
+};
 use crate::{
     arch::Arch,
     dis::{self, groups::Group},
+    syscall::Syscall,
     tracer::parser::{Message, RegisterMessage},
 };
 use bitflags::bitflags;
@@ -11,8 +14,13 @@ pub struct Aarch64Step {
     state: Aarch64State,
     code: [u8; 4],
     address: u64,
+    thread: u64,
+    pid: u64,
+    signal: Option<u64>,
     strace: Option<Box<str>>,
+    syscall: Option<Box<Syscall>>,
     memory_ops: Box<[MemoryOp]>,
+    syscall_writes: Box<[SyscallWrite]>,
 }
 
 impl Step<32> for Aarch64Step {
@@ -35,14 +43,34 @@ impl Step<32> for Aarch64Step {
         self.address
     }
 
+    fn thread(&self) -> u64 {
+        self.thread
+    }
+
+    fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    fn signal(&self) -> Option<u64> {
+        self.signal
+    }
+
     fn strace(&self) -> Option<&str> {
         self.strace.as_deref()
     }
 
+    fn syscall(&self) -> Option<&Syscall> {
+        self.syscall.as_deref()
+    }
+
     fn memory_ops(&self) -> &[MemoryOp] {
         &self.memory_ops[..]
     }
 
+    fn syscall_writes(&self) -> &[SyscallWrite] {
+        &self.syscall_writes
+    }
+
     fn instrument(&self) -> Self::INSTRUMENT {
         Aarch64Instrument { step: self.clone() }
     }
@@ -86,6 +114,7 @@ pub struct Aarch64State {
     regs: [u64; 32],
     pc: u64,
     flags: Aarch64Flags,
+    tpidr_el0: Option<u64>,
 }
 
 impl State<32> for Aarch64State {
@@ -95,6 +124,10 @@ impl State<32> for Aarch64State {
         self.pc
     }
 
+    fn sp(&self) -> u64 {
+        self.regs[31]
+    }
+
     fn regs(&self) -> &[u64; 32] {
         &self.regs
     }
@@ -102,6 +135,13 @@ impl State<32> for Aarch64State {
     fn flags(&self) -> &Aarch64Flags {
         &self.flags
     }
+
+    fn bases(&self) -> Vec<(&'static str, u64)> {
+        self.tpidr_el0
+            .map(|v| ("tpidr_el0", v))
+            .into_iter()
+            .collect()
+    }
 }
 
 impl TryFrom<RegisterMessage> for Aarch64State {
@@ -114,6 +154,7 @@ impl TryFrom<RegisterMessage> for Aarch64State {
             regs: generic.regs,
             pc: generic.pc,
             flags: Aarch64Flags::from_bits_retain(generic.flags as u32),
+            tpidr_el0: generic.bases.first().copied(),
         })
     }
 }
@@ -128,8 +169,17 @@ impl TryFrom<&[Message]> for Aarch64Step {
             state: generic.state,
             code: generic.code.try_into().unwrap(),
             address: generic.address,
+            thread: generic.thread,
+            pid: generic.pid,
+            signal: generic.signal,
+            syscall: generic
+                .strace
+                .as_deref()
+                .and_then(|s| Syscall::parse(s, Arch::ARM64))
+                .map(Box::new),
             strace: generic.strace.map(|x| x.into()),
             memory_ops: generic.memory_ops.into_boxed_slice(),
+            syscall_writes: generic.syscall_writes.into_boxed_slice(),
         })
     }
 }
@@ -209,7 +259,27 @@ impl Instrument for Aarch64Instrument {
 #[cfg(test)]
 mod tests {
     use super::Aarch64State;
-    use crate::{state::Aarch64Flags, tracer::parser::RegisterMessage};
+    use crate::{
+        state::{Aarch64Flags, State},
+        tracer::parser::RegisterMessage,
+    };
+
+    #[test]
+    fn thread_pointer() {
+        // there's no segment to add, `mrs x0, tpidr_el0` reads it into a
+        // register and the address is computed from that
+        let input = RegisterMessage {
+            pc: 0,
+            flags: 0,
+            regs: [0x1234; 32].into(),
+            bases: [0xffff_8000_1000].into(),
+        };
+
+        let state = Aarch64State::try_from(input).unwrap();
+
+        assert_eq!(state.tpidr_el0, Some(0xffff_8000_1000));
+        assert_eq!(state.bases(), vec![("tpidr_el0", 0xffff_8000_1000)]);
+    }
 
     #[test]
     fn aarch64_state_deser() {
@@ -217,6 +287,7 @@ mod tests {
             pc: 0,
             flags: 0,
             regs: [0x1234; 32].into(),
+            bases: [].into(),
         };
 
         let result = Aarch64State::try_from(input);
@@ -229,6 +300,7 @@ mod tests {
                 regs: [0x1234; 32],
                 pc: 0,
                 flags: Aarch64Flags::empty(),
+                tpidr_el0: None,
             }
         );
     }
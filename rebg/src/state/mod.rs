@@ -12,6 +12,7 @@ pub use x64::{X64Flags, X64State, X64Step};
 use crate::{
     arch::Arch,
     dis::{self},
+    syscall::Syscall,
     tracer::parser::{Message, RegisterMessage},
 };
 
@@ -27,18 +28,33 @@ pub trait Step<const N: usize>: Clone + std::marker::Send + 'static {
     fn state(&self) -> &Self::STATE;
     // sometimes they differ, though, so also keep address
     fn address(&self) -> u64;
+    /// Thread id, 0 if the tracer doesn't tell us
+    fn thread(&self) -> u64;
+    /// Process id, 0 if the tracer doesn't tell us
+    fn pid(&self) -> u64;
+    /// Signal delivered right before this step, i.e. this is the first
+    /// instruction of the handler
+    fn signal(&self) -> Option<u64>;
     fn strace(&self) -> Option<&str>;
+    /// `strace` decoded, if we could make sense of it
+    fn syscall(&self) -> Option<&Syscall>;
     fn memory_ops(&self) -> &[MemoryOp];
+    /// What the kernel wrote to memory during the syscall of this step
+    fn syscall_writes(&self) -> &[SyscallWrite];
 
     fn instrument(&self) -> Self::INSTRUMENT;
 }
 
 /// Register values and flags
 pub trait State<const N: usize>: Clone {
-    type FLAGS: Flags + Clone + Copy + fmt::Debug;
+    type FLAGS: Flags<Bits = u32> + Clone + Copy + fmt::Debug;
     fn pc(&self) -> u64;
+    fn sp(&self) -> u64;
     fn regs(&self) -> &[u64; N];
     fn flags(&self) -> &Self::FLAGS;
+    /// Thread pointer and segment base registers (fs_base, tpidr_el0, ...),
+    /// only the ones the tracer actually sent.
+    fn bases(&self) -> Vec<(&'static str, u64)>;
 }
 
 pub trait Instrument {
@@ -53,13 +69,25 @@ pub enum Branching {
     Return,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalEvent {
+    /// We're now at the start of the handler for this signal
+    Delivered(u64),
+    /// This step does the sigreturn, the next one is back where we were
+    /// interrupted
+    Return,
+}
+
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
 pub struct Instrumentation {
     pub branch: Option<Branching>,
     pub disassembly: String,
+    pub signal: Option<SignalEvent>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MemoryOpKind {
     Read,
     Write,
@@ -82,6 +110,21 @@ impl MemoryValue {
             MemoryValue::Qword(q) => *q,
         }
     }
+
+    /// As it is laid out in memory, both targets are little endian
+    pub fn bytes(&self) -> Vec<u8> {
+        self.as_u64().to_le_bytes()[..self.size() as usize].to_vec()
+    }
+
+    /// In bytes
+    pub fn size(&self) -> u64 {
+        match &self {
+            MemoryValue::Byte(_) => 1,
+            MemoryValue::Word(_) => 2,
+            MemoryValue::Dword(_) => 4,
+            MemoryValue::Qword(_) => 8,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -91,12 +134,20 @@ pub struct MemoryOp {
     pub value: MemoryValue,
 }
 
+/// Bytes written by the kernel, e.g. the buffer of a `read`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyscallWrite {
+    pub address: u64,
+    pub bytes: Box<[u8]>,
+}
+
 // nasty shit
 // ==========
 struct GenericState<TYPE, const N: usize> {
     regs: [TYPE; N],
     pc: TYPE,
     flags: TYPE,
+    bases: Vec<TYPE>,
 }
 
 impl<TYPE, const N: usize> TryFrom<RegisterMessage> for GenericState<TYPE, N>
@@ -110,7 +161,12 @@ where
     type Error = anyhow::Error;
 
     fn try_from(input: RegisterMessage) -> anyhow::Result<Self> {
-        let RegisterMessage { pc, flags, regs } = input;
+        let RegisterMessage {
+            pc,
+            flags,
+            regs,
+            bases,
+        } = input;
 
         let pc = pc.try_into().unwrap();
         let flags = flags.try_into().unwrap();
@@ -122,7 +178,14 @@ where
             .try_into()
             .unwrap();
 
-        Ok(Self { regs, pc, flags })
+        let bases = bases.iter().map(|&v| v.try_into().unwrap()).collect();
+
+        Ok(Self {
+            regs,
+            pc,
+            flags,
+            bases,
+        })
     }
 }
 
@@ -130,8 +193,12 @@ pub struct GenericStep<STATE: TryFrom<RegisterMessage>> {
     state: STATE,
     code: Vec<u8>,
     address: u64,
+    thread: u64,
+    pid: u64,
+    signal: Option<u64>,
     strace: Option<String>,
     memory_ops: Vec<MemoryOp>,
+    syscall_writes: Vec<SyscallWrite>,
 }
 
 impl<STATE> TryFrom<&[Message]> for GenericStep<STATE>
@@ -141,26 +208,37 @@ where
     type Error = anyhow::Error;
 
     fn try_from(value: &[Message]) -> Result<Self, Self::Error> {
-        let mut s_state = None;
+        let mut s_regs = None;
+        let mut s_bases = None;
         let mut s_address = None;
         let mut s_code = None;
+        let mut s_thread = None;
+        let mut s_pid = None;
+        let mut s_signal = None;
 
         let mut strace = None;
         let mut strace_result = None;
 
         let mut memory_ops = vec![];
+        let mut syscall_writes = vec![];
 
         for m in value {
             match m {
                 Message::Address(a) => s_address = Some(*a),
                 Message::Code(c) => s_code = Some(c.to_vec()),
                 Message::Registers(regs) => {
-                    s_state = if let Some(existing) = s_state {
-                        Some(existing)
-                    } else {
-                        Some(STATE::try_from(regs.clone()).unwrap())
+                    if s_regs.is_none() {
+                        s_regs = Some(regs.clone());
+                    }
+                }
+                Message::Bases(bases) => {
+                    if s_bases.is_none() {
+                        s_bases = Some(bases.clone());
                     }
                 }
+                Message::Thread(t) => s_thread = Some(*t),
+                Message::Process(p) => s_pid = Some(*p),
+                Message::Signal(sig) => s_signal = Some(*sig),
                 Message::Flags(_) => todo!(),
                 Message::Load(adr, value, size) | Message::Store(adr, value, size) => {
                     let value = match size {
@@ -185,6 +263,10 @@ where
                 }
                 Message::Syscall(s) => strace = Some(s.to_string()),
                 Message::SyscallResult(s) => strace_result = Some(s.to_string()),
+                Message::SyscallWrite(address, bytes) => syscall_writes.push(SyscallWrite {
+                    address: *address,
+                    bytes: bytes.clone(),
+                }),
                 Message::Debug(m) => {
                     debug!("Debug message: {}", m)
                 }
@@ -199,16 +281,26 @@ where
             strace
         });
 
-        let address = s_address.unwrap();
-        let code = s_code.unwrap();
-        let state = s_state.unwrap();
+        let address = s_address.ok_or_else(|| anyhow::anyhow!("step has no address"))?;
+        let code = s_code.ok_or_else(|| anyhow::anyhow!("step has no code"))?;
+        let thread = s_thread.unwrap_or(0);
+        let pid = s_pid.unwrap_or(0);
+        let mut regs = s_regs.ok_or_else(|| anyhow::anyhow!("step has no registers"))?;
+        if let Some(bases) = s_bases {
+            regs.bases = bases;
+        }
+        let state = STATE::try_from(regs)?;
 
         Ok(Self {
             state,
             code,
             address,
+            thread,
+            pid,
+            signal: s_signal,
             strace,
             memory_ops,
+            syscall_writes,
         })
     }
 }
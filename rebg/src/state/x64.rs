@@ -5,13 +5,16 @@ use crate::{
         groups::Group,
         regs::{Reg, X64Reg},
     },
+    syscall::Syscall,
     tracer::parser::{Message, RegisterMessage},
 };
 
-use super::{Branching, GenericState, GenericStep, Instrument, MemoryOp, State, Step};
+use super::{
+    Branching, GenericState, GenericStep, Instrument, MemoryOp, State, Step, SyscallWrite,
+};
 use bitflags::bitflags;
 use capstone::{
-    arch::{self},
+    arch::{self, x86::X86OpMem},
     RegId,
 };
 
@@ -20,8 +23,13 @@ pub struct X64Step {
     state: X64State,
     code: Box<[u8]>,
     address: u64,
+    thread: u64,
+    pid: u64,
+    signal: Option<u64>,
     strace: Option<Box<str>>,
+    syscall: Option<Box<Syscall>>,
     memory_ops: Box<[MemoryOp]>,
+    syscall_writes: Box<[SyscallWrite]>,
 }
 
 impl Step<16> for X64Step {
@@ -43,14 +51,34 @@ impl Step<16> for X64Step {
         self.address
     }
 
+    fn thread(&self) -> u64 {
+        self.thread
+    }
+
+    fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    fn signal(&self) -> Option<u64> {
+        self.signal
+    }
+
     fn strace(&self) -> Option<&str> {
         self.strace.as_deref()
     }
 
+    fn syscall(&self) -> Option<&Syscall> {
+        self.syscall.as_deref()
+    }
+
     fn memory_ops(&self) -> &[super::MemoryOp] {
         &self.memory_ops
     }
 
+    fn syscall_writes(&self) -> &[SyscallWrite] {
+        &self.syscall_writes
+    }
+
     type INSTRUMENT = X64Instrument;
 
     fn instrument(&self) -> Self::INSTRUMENT {
@@ -83,6 +111,8 @@ pub struct X64State {
     regs: [u64; 16],
     pc: u64,
     flags: X64Flags,
+    fs_base: Option<u64>,
+    gs_base: Option<u64>,
 }
 
 impl State<16> for X64State {
@@ -92,6 +122,10 @@ impl State<16> for X64State {
         self.pc
     }
 
+    fn sp(&self) -> u64 {
+        self.regs[4]
+    }
+
     fn regs(&self) -> &[u64; 16] {
         &self.regs
     }
@@ -99,6 +133,13 @@ impl State<16> for X64State {
     fn flags(&self) -> &X64Flags {
         &self.flags
     }
+
+    fn bases(&self) -> Vec<(&'static str, u64)> {
+        [("fs_base", self.fs_base), ("gs_base", self.gs_base)]
+            .into_iter()
+            .filter_map(|(name, v)| Some((name, v?)))
+            .collect()
+    }
 }
 
 impl X64State {
@@ -114,6 +155,63 @@ impl X64State {
 
         Some(self.regs[reg.idx()?])
     }
+
+    /// Base of a segment register. Everything except fs and gs is flat in
+    /// long mode.
+    fn segment_base(&self, reg_id: RegId) -> Option<u64> {
+        if reg_id.0 == 0 {
+            return Some(0);
+        }
+
+        match Reg::from_num(Arch::X86_64, reg_id.0)? {
+            Reg::X64Reg(X64Reg::Fs) => self.fs_base,
+            Reg::X64Reg(X64Reg::Gs) => self.gs_base,
+            Reg::X64Reg(X64Reg::Cs | X64Reg::Ds | X64Reg::Es | X64Reg::Ss) => Some(0),
+            _ => None,
+        }
+    }
+
+    /// `seg:[base + index * scale + disp]`
+    pub fn effective_address(&self, mem: &X86OpMem) -> Option<u64> {
+        let segment = match self.segment_base(mem.segment()) {
+            Some(v) => v,
+            None => {
+                eprintln!("Unknown segment base {:?}", mem.segment());
+                return None;
+            }
+        } as i128;
+
+        let base = match self.read_reg(mem.base()) {
+            Some(v) => v,
+            None => {
+                if mem.base().0 == 0 {
+                    0
+                } else {
+                    eprintln!("Unknown base register {:?}", mem.base());
+                    return None;
+                }
+            }
+        } as i128;
+
+        let index = match self.read_reg(mem.index()) {
+            Some(v) => v,
+            None => {
+                if mem.index().0 == 0 {
+                    0
+                } else {
+                    eprintln!("Unknown index register {:?}", mem.index());
+                    return None;
+                }
+            }
+        } as i128;
+
+        let scale = mem.scale() as i128;
+
+        let disp = mem.disp() as i128;
+
+        let address = segment + base + index * scale + disp;
+        Some(address as u64)
+    }
 }
 
 impl TryFrom<RegisterMessage> for X64State {
@@ -126,6 +224,8 @@ impl TryFrom<RegisterMessage> for X64State {
             regs: generic.regs,
             pc: generic.pc,
             flags: X64Flags::from_bits_retain(generic.flags as u32),
+            fs_base: generic.bases.first().copied(),
+            gs_base: generic.bases.get(1).copied(),
         })
     }
 }
@@ -140,8 +240,17 @@ impl TryFrom<&[Message]> for X64Step {
             state: generic.state,
             code: generic.code.into_boxed_slice(),
             address: generic.address,
+            thread: generic.thread,
+            pid: generic.pid,
+            signal: generic.signal,
+            syscall: generic
+                .strace
+                .as_deref()
+                .and_then(|s| Syscall::parse(s, Arch::X86_64))
+                .map(Box::new),
             strace: generic.strace.map(|x| x.into_boxed_str()),
             memory_ops: generic.memory_ops.into_boxed_slice(),
+            syscall_writes: generic.syscall_writes.into_boxed_slice(),
         })
     }
 }
@@ -188,38 +297,7 @@ impl Instrument for X64Instrument {
                     Some(Branching::Call(imm as u64, return_address))
                 }
                 arch::x86::X86OperandType::Mem(mem) => {
-                    assert_eq!(mem.segment().0, 0);
-
-                    let base = match self.step.state().read_reg(mem.base()) {
-                        Some(v) => v,
-                        None => {
-                            if mem.base().0 == 0 {
-                                0
-                            } else {
-                                eprintln!("Unknown base register {:?}", mem.base());
-                                return None;
-                            }
-                        }
-                    } as i128;
-
-                    let index = match self.step.state().read_reg(mem.index()) {
-                        Some(v) => v,
-                        None => {
-                            if mem.index().0 == 0 {
-                                0
-                            } else {
-                                eprintln!("Unknown index register {:?}", mem.index());
-                                return None;
-                            }
-                        }
-                    } as i128;
-
-                    let scale = mem.scale() as i128;
-
-                    let disp = mem.disp() as i128;
-
-                    let target_address = base + index * scale + disp;
-                    let target_address = target_address as u64;
+                    let target_address = self.step.state().effective_address(&mem)?;
 
                     Some(Branching::Call(target_address, return_address))
                 }
@@ -236,7 +314,68 @@ impl Instrument for X64Instrument {
 #[cfg(test)]
 mod tests {
     use super::X64State;
-    use crate::{state::X64Flags, tracer::parser::RegisterMessage};
+    use crate::{
+        arch::Arch,
+        dis::Dis,
+        state::{State, X64Flags},
+        tracer::parser::RegisterMessage,
+    };
+    use capstone::arch::{
+        x86::{X86OpMem, X86Operand, X86OperandType},
+        ArchOperand,
+    };
+    use std::rc::Rc;
+
+    /// The memory operand of the instruction in `code`
+    fn mem_operand(code: &[u8]) -> X86OpMem {
+        let dis = Dis {
+            arch: Arch::X86_64,
+            cs: Rc::new(Arch::X86_64.make_capstone().unwrap()),
+        };
+        let insn = dis.disassemble_one(code, 0x401000).unwrap();
+
+        insn.operands
+            .iter()
+            .find_map(|op| match op {
+                ArchOperand::X86Operand(X86Operand {
+                    op_type: X86OperandType::Mem(mem),
+                    ..
+                }) => Some(*mem),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn segments() {
+        let mut regs = [0; 16];
+        regs[3] = 0x1000; // rbx
+        let state = X64State {
+            regs,
+            pc: 0x401000,
+            flags: X64Flags::empty(),
+            fs_base: Some(0x7fff_7000_0000),
+            gs_base: None,
+        };
+        let address = |code: &[u8]| state.effective_address(&mem_operand(code));
+
+        // mov rax, qword ptr fs:[0x28], the stack canary
+        assert_eq!(
+            address(&[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0, 0, 0]),
+            Some(0x7fff_7000_0028)
+        );
+        // mov rax, qword ptr fs:[rbx + 8]
+        assert_eq!(
+            address(&[0x64, 0x48, 0x8b, 0x43, 0x08]),
+            Some(0x7fff_7000_1008)
+        );
+        // mov rax, qword ptr [rbx + 8]
+        assert_eq!(address(&[0x48, 0x8b, 0x43, 0x08]), Some(0x1008));
+        // mov rax, qword ptr gs:[0], the tracer didn't send gs_base
+        assert_eq!(address(&[0x65, 0x48, 0x8b, 0x04, 0x25, 0, 0, 0, 0]), None);
+
+        assert_eq!(state.bases(), vec![("fs_base", 0x7fff_7000_0000)]);
+    }
 
     #[test]
     fn aarch64_state_deser() {
@@ -244,6 +383,7 @@ mod tests {
             pc: 0,
             flags: 0,
             regs: [0x4321; 16].into(),
+            bases: [0xdead, 0xbeef].into(),
         };
 
         let result = X64State::try_from(input);
@@ -256,6 +396,8 @@ mod tests {
                 regs: [0x4321; 16],
                 pc: 0,
                 flags: X64Flags::empty(),
+                fs_base: Some(0xdead),
+                gs_base: Some(0xbeef),
             }
         );
     }
@@ -1,6 +1,12 @@
-use crate::binary::Binary;
-use object::{read::elf::FileHeader, Object, ObjectSymbol};
-use std::fmt::{Display, Formatter};
+use crate::{binary::Binary, tick::Tick};
+use object::{
+    read::elf::{FileHeader, SectionHeader},
+    Architecture, Object, ObjectSymbol, ObjectSymbolTable, RelocationTarget,
+};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
 use tracing::debug;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -34,6 +40,14 @@ pub struct SymbolTable {
     /// Source of the binary this symbols applies to. If there is a separate
     /// debug info file, that should be disregarded wrt. this variable.
     pub binary_path: String,
+    /// Addresses this table was mapped at, `None` if we don't know
+    pub range: Option<(u64, u64)>,
+    /// Runtime address minus ELF virtual address, once it's mapped
+    pub bias: u64,
+    /// Tick it was mapped
+    pub loaded: Tick,
+    /// Tick it was unmapped, if it was
+    pub unloaded: Option<Tick>,
 }
 #[derive(Debug, Clone, Copy)]
 pub struct ProgramOffset {
@@ -72,9 +86,67 @@ impl SymbolTable {
                 let size = sym.size();
                 (name.to_string(), addr, size)
             })
+            .chain(Self::plt_symbols(bin))
             .collect()
     }
 
+    /// `name@plt` for the PLT stubs, which have no symbols of their own. We
+    /// decode which GOT slot each stub jumps through, and the dynamic
+    /// relocation of that slot tells us the function.
+    fn plt_symbols(bin: &Binary) -> Vec<(String, u64, u64)> {
+        let obj = bin.obj();
+        let (Some(dynsyms), Some(relocations)) =
+            (obj.dynamic_symbol_table(), obj.dynamic_relocations())
+        else {
+            return Vec::new();
+        };
+
+        // GOT slot -> function
+        let slots: HashMap<u64, &str> = relocations
+            .filter_map(|(slot, reloc)| match reloc.target() {
+                RelocationTarget::Symbol(idx) => {
+                    let name = dynsyms.symbol_by_index(idx).ok()?.name().ok()?;
+                    (!name.is_empty()).then_some((slot, name))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let endian = obj.endianness();
+        let Ok(sections) = bin.header().sections(endian, bin.raw()) else {
+            return Vec::new();
+        };
+
+        let mut symbols = Vec::new();
+        for section in sections.iter() {
+            // .plt.sec and .plt.got are the stubs that are actually called when
+            // there is one, the header of .plt doesn't decode to a slot
+            let name = sections.section_name(endian, section).unwrap_or_default();
+            if !matches!(name, b".plt" | b".plt.sec" | b".plt.got") {
+                continue;
+            }
+
+            let addr = section.sh_addr(endian);
+            let size = section.sh_entsize(endian);
+            let Ok(data) = section.data(endian, bin.raw()) else {
+                continue;
+            };
+            if size == 0 {
+                continue;
+            }
+
+            for (i, stub) in data.chunks_exact(size as usize).enumerate() {
+                let stub_addr = addr + i as u64 * size;
+                let slot = plt_slot(obj.architecture(), stub_addr, stub);
+                if let Some(name) = slot.and_then(|slot| slots.get(&slot)) {
+                    symbols.push((format!("{}@plt", name), stub_addr, size));
+                }
+            }
+        }
+
+        symbols
+    }
+
     /// Extend an existing elf with more debug symbols
     pub fn extend_with_debug(self, debug: &Binary, from: u64, to: u64) -> Self {
         // TODO support different vaddr and paddr
@@ -142,6 +214,20 @@ impl SymbolTable {
         Self { symbols, ..self }
     }
 
+    /// A table without any symbols, for binaries we can't read
+    pub fn empty(path: String) -> Self {
+        Self {
+            symbols: vec![],
+            offsets: vec![],
+            fallback: None,
+            binary_path: path,
+            range: None,
+            bias: 0,
+            loaded: Tick(0),
+            unloaded: None,
+        }
+    }
+
     pub fn from_elf(path: String, elf: &Binary) -> Self {
         let offsets = Self::get_offsets(elf);
 
@@ -150,6 +236,10 @@ impl SymbolTable {
             symbols: vec![],
             fallback: None,
             binary_path: path,
+            range: None,
+            bias: 0,
+            loaded: Tick(0),
+            unloaded: None,
         };
 
         empty.extend_with_debug(elf, u64::MIN, u64::MAX)
@@ -170,15 +260,151 @@ impl SymbolTable {
         Self { symbols, ..self }
     }
 
+    /// The whole image was mapped at `range`, valid from `tick` on
+    pub fn loaded_at(self, range: (u64, u64), tick: Tick) -> Self {
+        let lowest = self.offsets.iter().map(|o| page_down(o.addr)).min();
+
+        Self {
+            range: Some(range),
+            bias: range.0 - lowest.unwrap_or(0),
+            loaded: tick,
+            ..self
+        }
+    }
+
+    /// `size` bytes from `offset` into the file were mapped at `addr`, like
+    /// the loader does for each PT_LOAD on its own. Valid from `tick` on.
+    pub fn mapped(self, addr: u64, size: u64, offset: u64, tick: Tick) -> Self {
+        // the segment that starts there tells us how virtual addresses map.
+        // Segments can share a page of the file, the size tells them apart.
+        let starts_here = |o: &&ProgramOffset| page_down(o.offset) == offset;
+        let bias = self
+            .offsets
+            .iter()
+            .filter(starts_here)
+            .find(|o| page_down(o.addr + o.size + 0xfff) - page_down(o.addr) == size)
+            .or_else(|| self.offsets.iter().find(starts_here))
+            .map_or(addr - offset, |o| addr - page_down(o.addr));
+
+        // symbols are relative to the start of the file
+        Self {
+            range: Some((addr, addr + size)),
+            bias,
+            loaded: tick,
+            ..self.add_offset(addr - offset)
+        }
+    }
+
+    fn valid_at(&self, tick: Tick) -> bool {
+        self.loaded <= tick && self.unloaded.is_none_or(|u| tick < u)
+    }
+
+    fn find(&self, adr: u64, valid: &dyn Fn(&Self) -> bool) -> Option<SymbolReference> {
+        let here = if valid(self) {
+            // symbols end where the next one starts, that one wins
+            self.symbols
+                .iter()
+                .rev()
+                .filter(|s| s.from <= adr && adr <= s.to)
+                .max_by_key(|s| s.from)
+                .map(|s| SymbolReference {
+                    offset: adr - s.from,
+                    symbol: s,
+                })
+        } else {
+            None
+        };
+
+        here.or_else(|| self.fallback.as_ref().and_then(|f| f.find(adr, valid)))
+    }
+
+    /// Whatever is mapped at `adr` right now
     pub fn lookup(&self, adr: u64) -> Option<SymbolReference> {
-        self.symbols
+        self.find(adr, &|t| t.unloaded.is_none())
+    }
+
+    /// Whatever was mapped at `adr` at `tick`
+    pub fn lookup_at(&self, adr: u64, tick: Tick) -> Option<SymbolReference> {
+        self.find(adr, &|t| t.valid_at(tick))
+    }
+
+    /// This table and its fallbacks that were mapped at `tick`
+    pub fn tables_at(&self, tick: Tick) -> Vec<&SymbolTable> {
+        let mut tables = Vec::new();
+        let mut table = Some(self);
+        while let Some(t) = table {
+            if t.valid_at(tick) {
+                tables.push(t);
+            }
+            table = t.fallback.as_deref();
+        }
+        tables
+    }
+
+    /// Where in the file `adr` was loaded from, going by the PT_LOAD headers
+    pub fn file_offset(&self, adr: u64) -> Option<u64> {
+        let (start, end) = self.range?;
+        if adr < start || end <= adr {
+            return None;
+        }
+        let adr = adr - self.bias;
+        self.offsets
             .iter()
-            .find(|s| s.from <= adr && adr <= s.to)
-            .map(|s| SymbolReference {
-                offset: adr - s.from,
-                symbol: s,
-            })
-            .or_else(|| self.fallback.as_ref().and_then(|f| f.lookup(adr)))
+            .find(|o| o.addr <= adr && adr < o.addr + o.size)
+            .map(|o| o.offset + (adr - o.addr))
+    }
+
+    /// Where the PT_LOAD headers that are in `range` ended up
+    pub fn segments(&self) -> Vec<u64> {
+        let Some((start, end)) = self.range else {
+            return Vec::new();
+        };
+        self.offsets
+            .iter()
+            .map(|o| self.bias + o.addr)
+            .filter(|adr| (start..end).contains(adr))
+            .collect()
+    }
+
+    /// munmap of `from..to`. Tables that are only partly covered live on as
+    /// new tables for what's left.
+    pub fn unload(&mut self, from: u64, to: u64, tick: Tick) {
+        let mut remaining = Vec::new();
+        let mut table = Some(&mut *self);
+
+        while let Some(t) = table {
+            if let Some((start, end)) = t.range.filter(|(s, e)| *s < to && from < *e) {
+                if t.unloaded.is_none() {
+                    t.unloaded = Some(tick);
+
+                    for (start, end) in [(start, from), (to, end)] {
+                        if start < end {
+                            remaining.push(Self {
+                                symbols: t
+                                    .symbols
+                                    .iter()
+                                    .filter(|s| start <= s.from && s.from < end)
+                                    .cloned()
+                                    .collect(),
+                                offsets: t.offsets.clone(),
+                                fallback: None,
+                                binary_path: t.binary_path.clone(),
+                                range: Some((start, end)),
+                                bias: t.bias,
+                                loaded: tick,
+                                unloaded: None,
+                            });
+                        }
+                    }
+                }
+            }
+
+            table = t.fallback.as_deref_mut();
+        }
+
+        for t in remaining {
+            self.push_table(t);
+        }
     }
 
     /// Will traverse through self's fallbacks until it comes to the end, it will then add other as
@@ -192,9 +418,45 @@ impl SymbolTable {
     }
 }
 
+fn page_down(adr: u64) -> u64 {
+    adr & !0xfff
+}
+
+/// The GOT slot a PLT stub at `addr` jumps through
+fn plt_slot(arch: Architecture, addr: u64, stub: &[u8]) -> Option<u64> {
+    match arch {
+        // jmp *disp(%rip), possibly after endbr64 and a bnd prefix
+        Architecture::X86_64 => {
+            let at = stub.windows(2).position(|w| w == [0xff, 0x25])?;
+            let disp = stub.get(at + 2..at + 6)?;
+            let disp = i32::from_le_bytes(disp.try_into().unwrap());
+            let next = addr + at as u64 + 6;
+            Some(next.wrapping_add_signed(disp as i64))
+        }
+        // adrp x16, page; ldr x17, [x16, off]
+        Architecture::Aarch64 => {
+            let word = |i: usize| Some(u32::from_le_bytes(stub.get(i..i + 4)?.try_into().unwrap()));
+            let (adrp, ldr) = (word(0)?, word(4)?);
+            if adrp & 0x9f00_0000 != 0x9000_0000 || ldr & 0xffc0_0000 != 0xf940_0000 {
+                return None;
+            }
+
+            let imm = ((adrp >> 29) & 0b11) | (((adrp >> 5) & 0x7_ffff) << 2);
+            // sign extend the 21 bits
+            let imm = ((imm as i64) << 43) >> 43;
+            let page = (addr & !0xfff).wrapping_add_signed(imm << 12);
+            let off = ((ldr >> 10) & 0xfff) as u64 * 8;
+            Some(page + off)
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Symbol, SymbolReference, SymbolTable};
+    use super::{plt_slot, Symbol, SymbolReference, SymbolTable};
+    use crate::{analyzer::testing, binary::Binary, tick::Tick};
+    use object::Architecture;
 
     #[test]
     fn pie() {
@@ -216,6 +478,10 @@ mod tests {
             offsets: vec![],
             binary_path: "/test/file".to_string(),
             fallback: None,
+            range: None,
+            bias: 0,
+            loaded: Tick(0),
+            unloaded: None,
         };
 
         // do the pie offset
@@ -254,4 +520,101 @@ mod tests {
         assert!(pie_table.lookup(0x40_800).is_some());
         assert!(pie_table.lookup(0x40_801).is_none());
     }
+
+    #[test]
+    fn unmap() {
+        let lib = |name: &str, from| SymbolTable {
+            symbols: vec![Symbol {
+                name: name.to_string(),
+                from,
+                to: from + 0x10,
+            }],
+            ..SymbolTable::empty(format!("/{}.so", name))
+        };
+
+        let mut table = SymbolTable::empty("/main".to_string());
+        table.push_table(lib("old", 0x1000).loaded_at((0x1000, 0x2000), Tick(5)));
+        table.unload(0x1000, 0x2000, Tick(10));
+        table.push_table(lib("new", 0x1000).loaded_at((0x1000, 0x2000), Tick(15)));
+
+        let name = |tick| {
+            table
+                .lookup_at(0x1004, Tick(tick))
+                .map(|s| s.symbol.name.clone())
+        };
+        assert_eq!(name(4), None);
+        assert_eq!(name(5), Some("old".to_string()));
+        assert_eq!(name(12), None);
+        assert_eq!(name(20), Some("new".to_string()));
+        assert_eq!(table.lookup(0x1004).unwrap().symbol.name, "new");
+    }
+
+    #[test]
+    fn plt() {
+        let raw = std::fs::read(testing::bin("memory-amd64")).unwrap();
+        let bin = Binary::from_bytes(raw.into_boxed_slice()).unwrap();
+        let table = SymbolTable::from_elf("memory-amd64".into(), &bin);
+
+        let name = |adr| table.lookup(adr).map(|s| s.symbol.name.clone());
+        // .plt
+        assert_eq!(name(0x1030).as_deref(), Some("malloc@plt"));
+        assert_eq!(name(0x103b).as_deref(), Some("malloc@plt"));
+        // .plt.got, printf has its address taken so it's called through there
+        assert_eq!(name(0x1040).as_deref(), Some("printf@plt"));
+        assert_eq!(name(0x1048).as_deref(), Some("__cxa_finalize@plt"));
+    }
+
+    #[test]
+    fn segments() {
+        let raw = std::fs::read(testing::bin("memory-amd64")).unwrap();
+        let bin = Binary::from_bytes(raw.into_boxed_slice()).unwrap();
+        let elf = || SymbolTable::from_elf("memory-amd64".into(), &bin);
+
+        // how ld.so maps it, the data is 0x1000 further in memory than in the file
+        let base = 0x5555_5555_4000;
+        let mut table = SymbolTable::empty("/main".to_string());
+        table.push_table(elf().mapped(base + 0x1000, 0x1000, 0x1000, Tick(5)));
+        table.push_table(elf().mapped(base + 0x3000, 0x2000, 0x2000, Tick(5)));
+
+        let name = |table: &SymbolTable, adr| table.lookup(adr).map(|s| s.symbol.name.clone());
+        assert_eq!(name(&table, base + 0x11c0).as_deref(), Some("main"));
+        assert_eq!(name(&table, base + 0x1030).as_deref(), Some("malloc@plt"));
+        assert_eq!(name(&table, base + 0x4030).as_deref(), Some("arr"));
+
+        let text = table.fallback.as_deref().unwrap();
+        let data = text.fallback.as_deref().unwrap();
+        assert_eq!(text.file_offset(base + 0x11c0), Some(0x11c0));
+        assert_eq!(data.file_offset(base + 0x4010), Some(0x3010));
+        assert_eq!(text.segments(), vec![base + 0x1000]);
+        assert_eq!(data.segments(), vec![base + 0x3dc8]);
+
+        // what's left of the code still knows where it came from
+        table.unload(base + 0x1000, base + 0x1200, Tick(10));
+        assert_eq!(name(&table, base + 0x11c0), None);
+        let rest = table
+            .tables_at(Tick(10))
+            .into_iter()
+            .find(|t| t.range == Some((base + 0x1200, base + 0x2000)))
+            .unwrap();
+        assert_eq!(rest.file_offset(base + 0x1250), Some(0x1250));
+        assert_eq!(rest.file_offset(base + 0x11c0), None);
+    }
+
+    #[test]
+    fn plt_aarch64() {
+        // adrp x16, 0x410000; ldr x17, [x16, #8]; add x16, x16, #8; br x17
+        let stub = [
+            0x90, 0x00, 0x00, 0x90, 0x11, 0x06, 0x40, 0xf9, 0x10, 0x22, 0x00, 0x91, 0x20, 0x02,
+            0x1f, 0xd6,
+        ];
+        assert_eq!(
+            plt_slot(Architecture::Aarch64, 0x400490, &stub),
+            Some(0x410008)
+        );
+        // the stp at the start of the PLT header
+        assert_eq!(
+            plt_slot(Architecture::Aarch64, 0x400470, &[0xf0, 0x7b, 0xbf, 0xa9]),
+            None
+        );
+    }
 }
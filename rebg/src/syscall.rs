@@ -0,0 +1,413 @@
+use crate::arch::Arch;
+use std::fmt;
+
+/// A decoded syscall, as reported by the tracer
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Syscall {
+    pub number: Option<u64>,
+    pub name: String,
+    pub args: Vec<Arg>,
+    /// `None` for syscalls that don't return, like `exit` or `rt_sigreturn`
+    pub ret: Option<Return>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Arg {
+    Int(i64),
+    Hex(u64),
+    Null,
+    Str(String),
+    /// `O_RDONLY|O_CLOEXEC`, `AT_FDCWD`
+    Flags(Vec<String>),
+    /// structs, arrays and whatever else we don't understand
+    Raw(String),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Return {
+    Value(u64),
+    Error { errno: u64, message: Option<String> },
+}
+
+impl Arg {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Arg::Int(i) => Some(*i as u64),
+            Arg::Hex(h) => Some(*h),
+            Arg::Null => Some(0),
+            _ => None,
+        }
+    }
+
+    /// For fds and the like, where -1 means something
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_u64().map(|v| v as i64)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        match self {
+            Arg::Flags(flags) => flags.iter().any(|f| f == flag),
+            Arg::Raw(raw) => raw.contains(flag),
+            _ => false,
+        }
+    }
+
+    fn parse(raw: &str) -> Self {
+        let raw = raw.trim();
+
+        if raw == "NULL" {
+            return Arg::Null;
+        }
+        if let Some(hex) = raw.strip_prefix("0x") {
+            if let Ok(v) = u64::from_str_radix(hex, 16) {
+                return Arg::Hex(v);
+            }
+        }
+        if let Ok(v) = raw.parse::<i64>() {
+            return Arg::Int(v);
+        }
+        if let Some(s) = raw.strip_prefix('"') {
+            // qemu marks truncated strings with a trailing ...
+            let s = s.strip_suffix("...").unwrap_or(s);
+            if let Some(s) = s.strip_suffix('"') {
+                return Arg::Str(unescape(s));
+            }
+        }
+
+        let is_flag =
+            |f: &str| !f.is_empty() && f.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if raw.split('|').all(is_flag) {
+            return Arg::Flags(raw.split('|').map(str::to_string).collect());
+        }
+
+        Arg::Raw(raw.to_string())
+    }
+}
+
+/// Roughly how strace prints it
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Int(i) => write!(f, "{}", i),
+            Arg::Hex(h) => write!(f, "{:#x}", h),
+            Arg::Null => write!(f, "NULL"),
+            Arg::Str(s) => write!(f, "{:?}", s),
+            Arg::Flags(flags) => write!(f, "{}", flags.join("|")),
+            Arg::Raw(raw) => write!(f, "{}", raw),
+        }
+    }
+}
+
+impl Return {
+    /// The result, if it succeeded
+    pub fn value(&self) -> Option<u64> {
+        match self {
+            Return::Value(v) => Some(*v),
+            Return::Error { .. } => None,
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+
+        // -1 errno=2 (No such file or directory)
+        if let Some((_, errno)) = raw.split_once("errno=") {
+            let (errno, message) = match errno.split_once(' ') {
+                Some((errno, message)) => (errno, Some(message)),
+                None => (errno, None),
+            };
+            let message = message.map(|m| m.trim_start_matches('(').trim_end_matches(')'));
+
+            return Some(Return::Error {
+                errno: errno.parse().ok()?,
+                message: message.map(str::to_string),
+            });
+        }
+
+        let value = match Arg::parse(raw.split_whitespace().next()?) {
+            Arg::Int(i) if i < 0 => {
+                // raw kernel return, -4095..-1 are errors
+                if i >= -4095 {
+                    return Some(Return::Error {
+                        errno: (-i) as u64,
+                        message: None,
+                    });
+                }
+                i as u64
+            }
+            arg => arg.as_u64()?,
+        };
+        Some(Return::Value(value))
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// Splits on commas that are not inside strings, parens or braces. Returns
+/// the arguments and whatever came after the closing paren.
+fn split_args(s: &str) -> Option<(Vec<&str>, &str)> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        if in_str {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_str = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_str = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => {
+                let last = s[start..i].trim();
+                if !last.is_empty() || !args.is_empty() {
+                    args.push(last);
+                }
+                return Some((args, &s[i + 1..]));
+            }
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+impl Syscall {
+    /// Parses strace-style output like `openat(AT_FDCWD,"/etc/passwd",O_RDONLY) = 3`,
+    /// optionally prefixed by a pid.
+    pub fn parse(raw: &str, arch: Arch) -> Option<Self> {
+        let open = raw.find('(')?;
+        let name = raw[..open].split_whitespace().last()?;
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return None;
+        }
+
+        let (args, rest) = split_args(&raw[open + 1..])?;
+        let args = args.into_iter().map(Arg::parse).collect();
+
+        let ret = rest.trim().strip_prefix('=').and_then(Return::parse);
+
+        Some(Self {
+            number: number(arch, name),
+            name: name.to_string(),
+            args,
+            ret,
+        })
+    }
+
+    pub fn arg(&self, idx: usize) -> Option<&Arg> {
+        self.args.get(idx)
+    }
+
+    /// The return value, if it returned and succeeded
+    pub fn value(&self) -> Option<u64> {
+        self.ret.as_ref().and_then(Return::value)
+    }
+}
+
+macro_rules! numbers {
+    ($(($name:ident, $x64:expr, $aarch64:expr)),* $(,)?) => {
+        /// Syscall number by name, only covers the common ones
+        pub fn number(arch: Arch, name: &str) -> Option<u64> {
+            match name {
+                $(stringify!($name) => match arch {
+                    Arch::X86_64 => $x64,
+                    Arch::ARM64 => $aarch64,
+                },)*
+                _ => None,
+            }
+        }
+    };
+}
+
+numbers!(
+    (read, Some(0), Some(63)),
+    (write, Some(1), Some(64)),
+    (open, Some(2), None),
+    (close, Some(3), Some(57)),
+    (stat, Some(4), None),
+    (fstat, Some(5), Some(80)),
+    (lstat, Some(6), None),
+    (poll, Some(7), None),
+    (lseek, Some(8), Some(62)),
+    (mmap, Some(9), Some(222)),
+    (mprotect, Some(10), Some(226)),
+    (munmap, Some(11), Some(215)),
+    (brk, Some(12), Some(214)),
+    (rt_sigaction, Some(13), Some(134)),
+    (rt_sigprocmask, Some(14), Some(135)),
+    (rt_sigreturn, Some(15), Some(139)),
+    (ioctl, Some(16), Some(29)),
+    (pread64, Some(17), Some(67)),
+    (pwrite64, Some(18), Some(68)),
+    (readv, Some(19), Some(65)),
+    (writev, Some(20), Some(66)),
+    (access, Some(21), None),
+    (pipe, Some(22), None),
+    (sched_yield, Some(24), Some(124)),
+    (mremap, Some(25), Some(216)),
+    (madvise, Some(28), Some(233)),
+    (dup, Some(32), Some(23)),
+    (dup2, Some(33), None),
+    (nanosleep, Some(35), Some(101)),
+    (getpid, Some(39), Some(172)),
+    (sendfile, Some(40), Some(71)),
+    (socket, Some(41), Some(198)),
+    (connect, Some(42), Some(203)),
+    (accept, Some(43), Some(202)),
+    (sendto, Some(44), Some(206)),
+    (recvfrom, Some(45), Some(207)),
+    (sendmsg, Some(46), Some(211)),
+    (recvmsg, Some(47), Some(212)),
+    (shutdown, Some(48), Some(210)),
+    (bind, Some(49), Some(200)),
+    (listen, Some(50), Some(201)),
+    (clone, Some(56), Some(220)),
+    (fork, Some(57), None),
+    (vfork, Some(58), None),
+    (execve, Some(59), Some(221)),
+    (exit, Some(60), Some(93)),
+    (wait4, Some(61), Some(260)),
+    (kill, Some(62), Some(129)),
+    (uname, Some(63), Some(160)),
+    (fcntl, Some(72), Some(25)),
+    (getcwd, Some(79), Some(17)),
+    (chdir, Some(80), Some(49)),
+    (getuid, Some(102), Some(174)),
+    (getgid, Some(104), Some(176)),
+    (geteuid, Some(107), Some(175)),
+    (getegid, Some(108), Some(177)),
+    (getppid, Some(110), Some(173)),
+    (arch_prctl, Some(158), None),
+    (gettid, Some(186), Some(178)),
+    (futex, Some(202), Some(98)),
+    (getdents64, Some(217), Some(61)),
+    (set_tid_address, Some(218), Some(96)),
+    (clock_gettime, Some(228), Some(113)),
+    (exit_group, Some(231), Some(94)),
+    (tgkill, Some(234), Some(131)),
+    (openat, Some(257), Some(56)),
+    (newfstatat, Some(262), Some(79)),
+    (readlinkat, Some(267), Some(78)),
+    (faccessat, Some(269), Some(48)),
+    (set_robust_list, Some(273), Some(99)),
+    (accept4, Some(288), Some(242)),
+    (dup3, Some(292), Some(24)),
+    (pipe2, Some(293), Some(59)),
+    (prlimit64, Some(302), Some(261)),
+    (getrandom, Some(318), Some(278)),
+    (execveat, Some(322), Some(281)),
+    (rseq, Some(334), Some(293)),
+    (clone3, Some(435), Some(435)),
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{Arg, Return, Syscall};
+    use crate::arch::Arch;
+
+    #[test]
+    fn qemu_openat() {
+        let s = Syscall::parse(
+            "1234 openat(AT_FDCWD,\"/etc/a,b\",O_RDONLY|O_CLOEXEC) = 3\n",
+            Arch::X86_64,
+        )
+        .unwrap();
+
+        assert_eq!(s.number, Some(257));
+        assert_eq!(s.name, "openat");
+        assert_eq!(
+            s.args,
+            vec![
+                Arg::Flags(vec!["AT_FDCWD".into()]),
+                Arg::Str("/etc/a,b".into()),
+                Arg::Flags(vec!["O_RDONLY".into(), "O_CLOEXEC".into()]),
+            ]
+        );
+        assert_eq!(s.ret, Some(Return::Value(3)));
+    }
+
+    #[test]
+    fn errno() {
+        let s = Syscall::parse(
+            "access(\"/etc/ld.so.preload\",R_OK) = -1 errno=2 (No such file or directory)",
+            Arch::ARM64,
+        )
+        .unwrap();
+
+        assert_eq!(s.number, None);
+        assert_eq!(
+            s.ret,
+            Some(Return::Error {
+                errno: 2,
+                message: Some("No such file or directory".into())
+            })
+        );
+        assert_eq!(s.value(), None);
+    }
+
+    #[test]
+    fn qiling_and_nested() {
+        let s = Syscall::parse("read(0x3, 0x7ffe0000, 0x340) = 0x340", Arch::X86_64).unwrap();
+        assert_eq!(s.args[0], Arg::Hex(3));
+        assert_eq!(s.value(), Some(0x340));
+
+        let s = Syscall::parse(
+            "newfstatat(3,\"\",{st_mode=S_IFREG|0644,st_size=1},AT_EMPTY_PATH) = 0",
+            Arch::X86_64,
+        )
+        .unwrap();
+        assert_eq!(s.args.len(), 4);
+        assert_eq!(
+            s.args[2],
+            Arg::Raw("{st_mode=S_IFREG|0644,st_size=1}".into())
+        );
+
+        let s = Syscall::parse("rt_sigreturn(0x1)", Arch::ARM64).unwrap();
+        assert_eq!(s.number, Some(139));
+        assert_eq!(s.ret, None);
+    }
+}
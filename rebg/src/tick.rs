@@ -0,0 +1,63 @@
+//! Position in the trace. Everything that refers to a step uses this, so
+//! long traces fit everywhere. Only indexing the trace itself goes through
+//! `usize`.
+
+use std::{fmt, num::ParseIntError, ops::Add, str::FromStr};
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct Tick(pub u64);
+
+impl Tick {
+    /// After every step, for when we want the final state
+    pub const MAX: Tick = Tick(u64::MAX);
+
+    /// Stores of a step land here, so loads of the same step don't see them
+    pub fn next(self) -> Tick {
+        Tick(self.0 + 1)
+    }
+
+    /// Into the trace
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Each step of `trace` with its tick
+pub fn enumerate<T>(trace: &[T]) -> impl Iterator<Item = (Tick, &T)> {
+    (0..).map(Tick).zip(trace)
+}
+
+impl Add<u64> for Tick {
+    type Output = Tick;
+
+    fn add(self, rhs: u64) -> Tick {
+        Tick(self.0 + rhs)
+    }
+}
+
+impl FromStr for Tick {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Tick)
+    }
+}
+
+impl fmt::Display for Tick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
@@ -12,6 +12,10 @@ where
 {
     LibLoad(Vec<(String, (u64, u64))>),
     TraceStep(STEP),
+    /// The tracer saw the program get killed by this signal
+    Killed(u64),
+    /// The trace ended part-way through a message or a step
+    Truncated,
     // TODO could handle this ourselves? esp when we have iterator?
     Final(std::process::Output),
 }
@@ -1,12 +1,12 @@
 use std::{
     fmt,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
     marker::PhantomData,
     net::{TcpListener, TcpStream},
     process::Child,
 };
 
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 use crate::state::Step;
 
@@ -21,8 +21,13 @@ enum Header {
     Load = 0x33,
     Store = 0x44,
     Registers = 0x77,
+    Bases = 0x78,
+    Thread = 0x7a,
+    Process = 0x7b,
+    Signal = 0x7c,
     Syscall = 0x99,
     SyscallResult = 0x9a,
+    SyscallWrite = 0x9b,
     Debug = 0xdd,
 }
 
@@ -38,8 +43,13 @@ impl TryFrom<u8> for Header {
             0x33 => Ok(Self::Load),
             0x44 => Ok(Self::Store),
             0x77 => Ok(Self::Registers),
+            0x78 => Ok(Self::Bases),
+            0x7a => Ok(Self::Thread),
+            0x7b => Ok(Self::Process),
+            0x7c => Ok(Self::Signal),
             0x99 => Ok(Self::Syscall),
             0x9a => Ok(Self::SyscallResult),
+            0x9b => Ok(Self::SyscallWrite),
             0xdd => Ok(Self::Debug),
             _ => Err(()),
         }
@@ -47,118 +57,149 @@ impl TryFrom<u8> for Header {
 }
 
 impl Header {
-    fn deserialize<R: Read>(&self, reader: &mut R) -> Message {
+    /// Fails if the stream ends part-way through the message
+    fn deserialize<R: Read>(&self, reader: &mut R) -> io::Result<Message> {
         let mut buf_8 = [0; 8];
-        let mut next_u64 = |reader: &mut R| {
-            reader.read_exact(&mut buf_8).unwrap();
-            u64::from_le_bytes(buf_8)
+        let mut next_u64 = |reader: &mut R| -> io::Result<u64> {
+            reader.read_exact(&mut buf_8)?;
+            Ok(u64::from_le_bytes(buf_8))
         };
 
-        match self {
+        let msg = match self {
             Header::Libload => {
-                let len = next_u64(reader);
+                let len = next_u64(reader)?;
 
                 let name = {
                     let mut strbuf = vec![0; len as usize];
-                    reader.read_exact(&mut strbuf).unwrap();
+                    reader.read_exact(&mut strbuf)?;
                     String::from_utf8(strbuf).unwrap().into_boxed_str()
                 };
 
-                let from = next_u64(reader);
-                let to = next_u64(reader);
+                let from = next_u64(reader)?;
+                let to = next_u64(reader)?;
 
                 Message::LibLoad(name, from, to)
             }
             Header::Separator => Message::Separator,
-            Header::Address => Message::Address(next_u64(reader)),
+            Header::Address => Message::Address(next_u64(reader)?),
             Header::Code => {
-                let len = next_u64(reader);
+                let len = next_u64(reader)?;
 
                 let mut code = vec![0; len as usize];
-                reader.read_exact(&mut code).unwrap();
+                reader.read_exact(&mut code)?;
 
                 Message::Code(code.into_boxed_slice())
             }
             Header::Load => {
                 let size = {
                     let mut bytebuf = [0; 1];
-                    reader.read_exact(&mut bytebuf).unwrap();
+                    reader.read_exact(&mut bytebuf)?;
                     u8::from_le_bytes(bytebuf)
                 };
 
-                let adr = next_u64(reader);
-                let value = next_u64(reader);
+                let adr = next_u64(reader)?;
+                let value = next_u64(reader)?;
 
                 Message::Load(adr, value, size)
             }
             Header::Store => {
                 let size = {
                     let mut bytebuf = [0; 1];
-                    reader.read_exact(&mut bytebuf).unwrap();
+                    reader.read_exact(&mut bytebuf)?;
                     u8::from_le_bytes(bytebuf)
                 };
 
-                let adr = next_u64(reader);
-                let value = next_u64(reader);
+                let adr = next_u64(reader)?;
+                let value = next_u64(reader)?;
 
                 Message::Store(adr, value, size)
             }
             Header::Registers => {
                 let count = {
                     let mut bytebuf = [0; 1];
-                    reader.read_exact(&mut bytebuf).unwrap();
+                    reader.read_exact(&mut bytebuf)?;
                     u8::from_le_bytes(bytebuf) as usize
                 };
 
-                let flags = next_u64(reader);
-                let pc = next_u64(reader);
+                let flags = next_u64(reader)?;
+                let pc = next_u64(reader)?;
 
                 let mut regs = vec![0; count];
 
                 for reg in regs.iter_mut() {
-                    *reg = next_u64(reader);
+                    *reg = next_u64(reader)?;
                 }
 
                 Message::Registers(RegisterMessage {
                     pc,
                     flags,
                     regs: regs.into_boxed_slice(),
+                    bases: Box::new([]),
                 })
             }
+            Header::Bases => {
+                let count = {
+                    let mut bytebuf = [0; 1];
+                    reader.read_exact(&mut bytebuf)?;
+                    u8::from_le_bytes(bytebuf) as usize
+                };
+
+                let mut bases = vec![0; count];
+
+                for base in bases.iter_mut() {
+                    *base = next_u64(reader)?;
+                }
+
+                Message::Bases(bases.into_boxed_slice())
+            }
+            Header::Thread => Message::Thread(next_u64(reader)?),
+            Header::Process => Message::Process(next_u64(reader)?),
+            Header::Signal => Message::Signal(next_u64(reader)?),
             Header::Syscall => {
-                let len = next_u64(reader);
+                let len = next_u64(reader)?;
 
                 let string = {
                     let mut strbuf = vec![0; len as usize];
-                    reader.read_exact(&mut strbuf).unwrap();
+                    reader.read_exact(&mut strbuf)?;
                     String::from_utf8(strbuf).unwrap().into_boxed_str()
                 };
 
                 Message::Syscall(string)
             }
             Header::SyscallResult => {
-                let len = next_u64(reader);
+                let len = next_u64(reader)?;
 
                 let string = {
                     let mut strbuf = vec![0; len as usize];
-                    reader.read_exact(&mut strbuf).unwrap();
+                    reader.read_exact(&mut strbuf)?;
                     String::from_utf8(strbuf).unwrap().into_boxed_str()
                 };
 
                 Message::SyscallResult(string)
             }
+            Header::SyscallWrite => {
+                let adr = next_u64(reader)?;
+                let len = next_u64(reader)?;
+
+                let mut bytes = vec![0; len as usize];
+                reader.read_exact(&mut bytes)?;
+
+                Message::SyscallWrite(adr, bytes.into_boxed_slice())
+            }
             Header::Debug => {
-                let len = next_u64(reader);
+                let len = next_u64(reader)?;
 
                 let string = {
                     let mut strbuf = vec![0; len as usize];
-                    reader.read_exact(&mut strbuf).unwrap();
+                    reader.read_exact(&mut strbuf)?;
                     String::from_utf8(strbuf).unwrap().into_boxed_str()
                 };
 
                 Message::Debug(string)
             }
-        }
+        };
+
+        Ok(msg)
     }
 }
 
@@ -169,11 +210,21 @@ pub enum Message {
     Address(u64),
     Code(Box<[u8]>),
     Registers(RegisterMessage),
+    /// fs_base, gs_base on x86_64. tpidr_el0 on aarch64.
+    Bases(Box<[u64]>),
+    /// Id of the thread executing this step
+    Thread(u64),
+    /// Id of the process executing this step
+    Process(u64),
+    /// Signal number, delivered right before this step
+    Signal(u64),
     Flags(u64),
     Load(u64, u64, u8),
     Store(u64, u64, u8),
     Syscall(Box<str>),
     SyscallResult(Box<str>),
+    /// Memory the kernel filled in during the syscall of this step
+    SyscallWrite(u64, Box<[u8]>),
     Debug(Box<str>),
 }
 
@@ -182,9 +233,13 @@ pub struct RegisterMessage {
     pub pc: u64,
     pub flags: u64,
     pub regs: Box<[u64]>,
+    /// Thread pointer/segment bases, empty if the tracer didn't send them
+    pub bases: Box<[u64]>,
 }
 
-pub fn get_next_message<R: Read>(reader: &mut R) -> Option<Message> {
+/// `None` once the stream ends between messages, an error if it ends in the
+/// middle of one
+pub fn get_next_message<R: Read>(reader: &mut R) -> Option<io::Result<Message>> {
     let mut header = [0; 1];
 
     reader.read_exact(&mut header).ok()?;
@@ -203,22 +258,19 @@ pub fn get_next_message<R: Read>(reader: &mut R) -> Option<Message> {
     Some(msg)
 }
 
-pub fn get_next_step<R: Read, STEP, const N: usize>(
-    reader: &mut R,
-    proc: &mut Option<Child>,
-) -> Option<ParsedStep<STEP, N>>
+/// Reads the messages up to the next separator and makes a step of them.
+/// `None` if there were none, which is how the tracer says it's done.
+pub fn read_step<R: Read, STEP, const N: usize>(reader: &mut R) -> Option<ParsedStep<STEP, N>>
 where
-    STEP: Step<N> + Send + 'static + fmt::Debug,
+    STEP: Step<N> + fmt::Debug,
     STEP: for<'a> TryFrom<&'a [Message], Error = anyhow::Error>,
 {
-    #[allow(clippy::question_mark)]
-    if proc.is_none() {
-        return None;
-    }
-
     let mut msgs = vec![];
 
     while let Some(m) = get_next_message(reader) {
+        let Ok(m) = m else {
+            return Some(ParsedStep::Truncated);
+        };
         if matches!(m, Message::Separator) {
             break;
         }
@@ -226,16 +278,8 @@ where
         msgs.push(m);
     }
 
-    // if there are no msgs, we're done!
     if msgs.is_empty() {
-        let mut my_proc = None;
-        std::mem::swap(proc, &mut my_proc);
-        let my_proc = my_proc.unwrap();
-
-        // make sure it closed gracefully
-        let result = my_proc.wait_with_output().unwrap();
-
-        return Some(ParsedStep::Final(result));
+        return None;
     }
 
     if matches!(msgs[0], Message::LibLoad(_, _, _)) {
@@ -251,10 +295,54 @@ where
         return Some(ParsedStep::LibLoad(map));
     }
 
-    // otherwise, it's just a step :)
+    // a lone signal after the last step is what killed it
+    if let [Message::Signal(signo)] = msgs[..] {
+        return Some(ParsedStep::Killed(signo));
+    }
 
-    let s = STEP::try_from(&msgs).unwrap();
-    Some(ParsedStep::TraceStep(s))
+    // otherwise, it's just a step :) unless the stream ended before all of
+    // it was sent
+    match STEP::try_from(&msgs) {
+        Ok(s) => Some(ParsedStep::TraceStep(s)),
+        Err(e) => {
+            warn!("Last step is incomplete: {}", e);
+            Some(ParsedStep::Truncated)
+        }
+    }
+}
+
+pub fn get_next_step<R: Read, STEP, const N: usize>(
+    reader: &mut R,
+    proc: &mut Option<Child>,
+) -> Option<ParsedStep<STEP, N>>
+where
+    STEP: Step<N> + Send + 'static + fmt::Debug,
+    STEP: for<'a> TryFrom<&'a [Message], Error = anyhow::Error>,
+{
+    #[allow(clippy::question_mark)]
+    if proc.is_none() {
+        return None;
+    }
+
+    match read_step(reader) {
+        // nothing follows a cut off trace
+        Some(ParsedStep::Truncated) => {
+            *proc = None;
+            Some(ParsedStep::Truncated)
+        }
+        Some(step) => Some(step),
+        // if there are no msgs, we're done!
+        None => {
+            let mut my_proc = None;
+            std::mem::swap(proc, &mut my_proc);
+            let my_proc = my_proc.unwrap();
+
+            // make sure it closed gracefully
+            let result = my_proc.wait_with_output().unwrap();
+
+            Some(ParsedStep::Final(result))
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -296,3 +384,56 @@ impl<STEP, const N: usize> GenericParser<STEP, N> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        analyzer::testing::{self, x64_step},
+        state::X64Step,
+    };
+
+    #[test]
+    fn cut_off() {
+        let mut raw = testing::libload();
+        raw.extend(x64_step(0x401000, &[0x90], &[]));
+        let whole = raw.len();
+        raw.extend(x64_step(
+            0x401001,
+            &[0x90],
+            &[Message::Load(0x1000, 0x41, 8)],
+        ));
+        // the tracers put the separator first, the last step has none after it
+        raw.pop();
+
+        // address, code, registers
+        let regs = whole + 9 + 10 + 2 + 8 * 18;
+        for len in whole + 1..raw.len() {
+            let steps = testing::parse::<X64Step, 16, _>(&raw[..len], 0);
+            let last = match &steps[..] {
+                [ParsedStep::LibLoad(_), ParsedStep::TraceStep(_), rest @ ..] => rest,
+                _ => panic!("cut at {}: {:?}", len, steps),
+            };
+            // only the load is missing, that could be all of it
+            if len == regs {
+                assert!(matches!(
+                    last,
+                    [ParsedStep::TraceStep(_), ParsedStep::Final(_)]
+                ));
+            } else {
+                assert!(
+                    matches!(last, [ParsedStep::Truncated]),
+                    "cut at {}: {:?}",
+                    len,
+                    last
+                );
+            }
+        }
+
+        let steps = testing::parse::<X64Step, 16, _>(&raw[..], 0);
+        assert!(matches!(
+            steps[2..],
+            [ParsedStep::TraceStep(_), ParsedStep::Final(_)]
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+// Records what a program executes inside its main binary, in the format the
+// tracers send to rebg, by single stepping it with ptrace. Only x86_64, and
+// without loads or syscalls, as those would need an emulator. Stores are
+// found by comparing the stack around rsp and the binary's own mappings
+// before and after each instruction, so writes to the heap are missing. It
+// is meant for making test traces of real binaries without docker.
+//
+//     cc -o record record.c
+//     ./record out.trace ./program [args...]
+
+#define _GNU_SOURCE
+#include <limits.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <signal.h>
+#include <sys/personality.h>
+#include <sys/ptrace.h>
+#include <sys/uio.h>
+#include <sys/user.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+// longest x86 instruction, the analyzer only decodes the first one
+#define CODE_LEN 16
+
+// what is compared around rsp, with the red zone below it
+#define STACK_BELOW 128
+#define STACK_ABOVE 512
+
+static FILE *out;
+
+// memory an instruction might store to, as it was before it ran
+struct region {
+    uint64_t adr;
+    uint64_t len;
+    uint8_t *before;
+};
+
+static void u8(uint8_t v) { fwrite(&v, 1, 1, out); }
+
+static void u64(uint64_t v) { fwrite(&v, 8, 1, out); }
+
+static void bytes(const void *data, uint64_t len) {
+    u64(len);
+    fwrite(data, 1, len, out);
+}
+
+// where the program itself is mapped, going by /proc/<pid>/maps
+static int binary_range(pid_t pid, const char *path, uint64_t *low, uint64_t *high) {
+    char real[PATH_MAX], maps[64], line[PATH_MAX + 128];
+    if (!realpath(path, real))
+        return -1;
+
+    snprintf(maps, sizeof(maps), "/proc/%d/maps", pid);
+    FILE *f = fopen(maps, "r");
+    if (!f)
+        return -1;
+
+    *low = UINT64_MAX;
+    *high = 0;
+    while (fgets(line, sizeof(line), f)) {
+        uint64_t from, to;
+        char name[PATH_MAX] = "";
+        if (sscanf(line, "%lx-%lx %*s %*s %*s %*s %s", &from, &to, name) < 2)
+            continue;
+        if (strcmp(name, real) != 0)
+            continue;
+        if (from < *low)
+            *low = from;
+        if (to > *high)
+            *high = to;
+    }
+    fclose(f);
+
+    return *low < *high ? 0 : -1;
+}
+
+static int peek(pid_t pid, uint64_t adr, void *buf, uint64_t len) {
+    struct iovec local = {buf, len}, remote = {(void *)adr, len};
+    return process_vm_readv(pid, &local, 1, &remote, 1, 0) == (ssize_t)len ? 0 : -1;
+}
+
+static void remember(pid_t pid, struct region *region, uint64_t adr, uint64_t len) {
+    region->adr = adr;
+    region->len = len;
+    region->before = realloc(region->before, len);
+    if (peek(pid, adr, region->before, len) < 0)
+        region->len = 0;
+}
+
+// a store for every qword of `region` that changed since `remember`
+static void stores(pid_t pid, struct region *region) {
+    uint8_t *after = malloc(region->len);
+    if (region->len && peek(pid, region->adr, after, region->len) == 0) {
+        for (uint64_t at = 0; at + 8 <= region->len; at += 8) {
+            if (memcmp(region->before + at, after + at, 8) == 0)
+                continue;
+            uint64_t val;
+            memcpy(&val, after + at, 8);
+            u8(0x44);
+            u8(8);
+            u64(region->adr + at);
+            u64(val);
+        }
+    }
+    free(after);
+}
+
+static void step(pid_t pid, struct user_regs_struct *r) {
+    uint64_t code[CODE_LEN / 8];
+    for (int i = 0; i < CODE_LEN / 8; i++)
+        code[i] = ptrace(PTRACE_PEEKTEXT, pid, r->rip + 8 * i, 0);
+
+    u8(0x55);
+
+    u8(0xaa);
+    u64(r->rip);
+
+    u8(0xff);
+    bytes(code, CODE_LEN);
+
+    uint64_t regs[] = {
+        r->rax, r->rcx, r->rdx, r->rbx, r->rsp, r->rbp, r->rsi, r->rdi,
+        r->r8,  r->r9,  r->r10, r->r11, r->r12, r->r13, r->r14, r->r15,
+    };
+    u8(0x77);
+    u8(sizeof(regs) / 8);
+    u64(r->eflags);
+    u64(r->rip);
+    for (size_t i = 0; i < sizeof(regs) / 8; i++)
+        u64(regs[i]);
+
+    u8(0x78);
+    u8(2);
+    u64(r->fs_base);
+    u64(r->gs_base);
+
+    u8(0x7a);
+    u64(pid);
+    u8(0x7b);
+    u64(pid);
+}
+
+int main(int argc, char **argv) {
+    if (argc < 3) {
+        fprintf(stderr, "usage: %s <out> <program> [args...]\n", argv[0]);
+        return 1;
+    }
+
+    out = fopen(argv[1], "wb");
+    if (!out) {
+        perror("fopen");
+        return 1;
+    }
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        // same addresses every time
+        personality(ADDR_NO_RANDOMIZE);
+        ptrace(PTRACE_TRACEME, 0, 0, 0);
+        execv(argv[2], argv + 2);
+        perror("execv");
+        _exit(127);
+    }
+
+    int status;
+    waitpid(pid, &status, 0);
+    if (!WIFSTOPPED(status)) {
+        fprintf(stderr, "program didn't start\n");
+        return 1;
+    }
+
+    uint64_t low, high;
+    if (binary_range(pid, argv[2], &low, &high) < 0) {
+        fprintf(stderr, "can't find %s in the memory map\n", argv[2]);
+        return 1;
+    }
+
+    u8(0xee);
+    bytes(argv[2], strlen(argv[2]));
+    u64(low);
+    u64(high);
+
+    struct region regions[2] = {0};
+    int recorded = 0;
+
+    int sig = 0;
+    for (;;) {
+        ptrace(PTRACE_SINGLESTEP, pid, 0, sig);
+        waitpid(pid, &status, 0);
+        if (!WIFSTOPPED(status))
+            break;
+
+        // pass on anything that isn't us stepping
+        sig = WSTOPSIG(status) == SIGTRAP ? 0 : WSTOPSIG(status);
+
+        // the stores of the last step go with it, before the next separator
+        if (recorded)
+            for (int i = 0; i < 2; i++)
+                stores(pid, &regions[i]);
+
+        struct user_regs_struct regs;
+        ptrace(PTRACE_GETREGS, pid, 0, &regs);
+        recorded = low <= regs.rip && regs.rip < high;
+        if (recorded) {
+            step(pid, &regs);
+
+            uint64_t stack = (regs.rsp - STACK_BELOW) & ~7ul;
+            remember(pid, &regions[0], stack, STACK_BELOW + STACK_ABOVE);
+            remember(pid, &regions[1], low, high - low);
+        }
+    }
+
+    // a lone signal after the last step is what killed it
+    if (WIFSIGNALED(status)) {
+        u8(0x55);
+        u8(0x7c);
+        u64(WTERMSIG(status));
+    }
+
+    fclose(out);
+    return WIFEXITED(status) ? WEXITSTATUS(status) : 128 + WTERMSIG(status);
+}
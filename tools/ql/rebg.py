@@ -3,7 +3,7 @@
 from qiling import Qiling
 from qiling.const import QL_ARCH, QL_INTERCEPT, QL_VERBOSE
 from typing import List
-from unicorn import x86_const, arm64_const
+from unicorn import UcError, unicorn_const, x86_const, arm64_const
 from enum import Enum
 import os
 
@@ -61,11 +61,46 @@ ARM64_REGS = [
     "sp",
 ]
 
+# what the kernel fills in for uname
+UTSNAME_SIZE = 6 * 65
+
+# emulation errors and the signal the kernel would have killed us with
+FATAL_ERRORS = {
+    unicorn_const.UC_ERR_READ_UNMAPPED: 11,
+    unicorn_const.UC_ERR_WRITE_UNMAPPED: 11,
+    unicorn_const.UC_ERR_FETCH_UNMAPPED: 11,
+    unicorn_const.UC_ERR_READ_PROT: 11,
+    unicorn_const.UC_ERR_WRITE_PROT: 11,
+    unicorn_const.UC_ERR_FETCH_PROT: 11,
+    unicorn_const.UC_ERR_READ_UNALIGNED: 7,
+    unicorn_const.UC_ERR_WRITE_UNALIGNED: 7,
+    unicorn_const.UC_ERR_FETCH_UNALIGNED: 7,
+    unicorn_const.UC_ERR_INSN_INVALID: 4,
+}
+
+# the clone flags the analyzer looks at
+CLONE_FLAGS = {
+    0x100: "CLONE_VM",
+    0x200: "CLONE_FS",
+    0x400: "CLONE_FILES",
+    0x800: "CLONE_SIGHAND",
+    0x4000: "CLONE_VFORK",
+    0x10000: "CLONE_THREAD",
+}
+
 
 class Arch(Enum):
     ARM64 = QL_ARCH.ARM64
     X8664 = QL_ARCH.X8664
 
+    def bases_regs(self):
+        if self == self.ARM64:
+            return [arm64_const.UC_ARM64_REG_TPIDR_EL0]
+        elif self == self.X8664:
+            return [x86_const.UC_X86_REG_FS_BASE, x86_const.UC_X86_REG_GS_BASE]
+        else:
+            raise Exception("what u doin")
+
     def flags_reg(self):
         if self == self.ARM64:
             return arm64_const.UC_ARM64_REG_NZCV
@@ -74,6 +109,14 @@ class Arch(Enum):
         else:
             raise Exception("what u doin")
 
+    def stat_size(self):
+        if self == self.ARM64:
+            return 128
+        elif self == self.X8664:
+            return 144
+        else:
+            raise Exception("what u doin")
+
     def regs(self):
         if self == self.ARM64:
             return ARM64_REGS
@@ -91,59 +134,99 @@ class Serializer:
         sock.connect(("localhost", 1337))
 
         self.sock = sock
+        # a forked child shares the socket, so each step goes out in one write
+        # to keep the two from interleaving
+        self.buf = bytearray()
+
+    def send(self, data: bytes):
+        self.buf += data
+
+    def flush(self):
+        self.sock.sendall(self.buf)
+        self.buf = bytearray()
+
+    def forked(self):
+        """the child of a fork starts out with the parent's half-written step,
+        the parent sends that one"""
+        self.buf = bytearray()
 
     def separator(self):
-        self.sock.sendall(b"\x55")
+        self.send(b"\x55")
+        self.flush()
 
     def address(self, value: int):
-        self.sock.sendall(b"\xaa")
-        self.sock.sendall(value.to_bytes(8, "little"))
+        self.send(b"\xaa")
+        self.send(value.to_bytes(8, "little"))
 
     def code(self, bin: bytes):
-        self.sock.sendall(b"\xff")
-        self.sock.sendall(len(bin).to_bytes(8, "little"))
-        self.sock.sendall(bin)
+        self.send(b"\xff")
+        self.send(len(bin).to_bytes(8, "little"))
+        self.send(bin)
 
     def registers(self, flags, pc, regs: List[int]):
-        self.sock.sendall(b"\x77")
-        self.sock.sendall(len(regs).to_bytes(1, "little"))
-        self.sock.sendall(flags.to_bytes(8, "little"))
-        self.sock.sendall(pc.to_bytes(8, "little"))
+        self.send(b"\x77")
+        self.send(len(regs).to_bytes(1, "little"))
+        self.send(flags.to_bytes(8, "little"))
+        self.send(pc.to_bytes(8, "little"))
         for r in regs:
-            self.sock.sendall(r.to_bytes(8, "little"))
+            self.send(r.to_bytes(8, "little"))
+
+    def bases(self, bases: List[int]):
+        self.send(b"\x78")
+        self.send(len(bases).to_bytes(1, "little"))
+        for b in bases:
+            self.send(b.to_bytes(8, "little"))
+
+    def thread(self, tid: int):
+        self.send(b"\x7a")
+        self.send(tid.to_bytes(8, "little"))
+
+    def process(self, pid: int):
+        self.send(b"\x7b")
+        self.send(pid.to_bytes(8, "little"))
+
+    def signal(self, signo: int):
+        self.send(b"\x7c")
+        self.send(signo.to_bytes(8, "little"))
 
     def libload(self, name: bytes, fr: int, to: int):
-        self.sock.sendall(b"\xee")
-        self.sock.sendall(len(name).to_bytes(8, "little"))
-        self.sock.sendall(name)
-        self.sock.sendall(fr.to_bytes(8, "little"))
-        self.sock.sendall(to.to_bytes(8, "little"))
+        self.send(b"\xee")
+        self.send(len(name).to_bytes(8, "little"))
+        self.send(name)
+        self.send(fr.to_bytes(8, "little"))
+        self.send(to.to_bytes(8, "little"))
 
     def libload_bin(self, name: bytes, content: bytes, fr: int, to: int):
-        self.sock.sendall(b"\xef")
-        self.sock.sendall(len(name).to_bytes(8, "little"))
-        self.sock.sendall(name)
-        self.sock.sendall(len(content).to_bytes(8, "little"))
-        self.sock.sendall(content)
-        self.sock.sendall(fr.to_bytes(8, "little"))
-        self.sock.sendall(to.to_bytes(8, "little"))
+        self.send(b"\xef")
+        self.send(len(name).to_bytes(8, "little"))
+        self.send(name)
+        self.send(len(content).to_bytes(8, "little"))
+        self.send(content)
+        self.send(fr.to_bytes(8, "little"))
+        self.send(to.to_bytes(8, "little"))
 
     def load(self, adr, value, size):
-        self.sock.sendall(b"\x33")
-        self.sock.sendall(size.to_bytes(1, "little"))
-        self.sock.sendall(adr.to_bytes(8, "little"))
-        self.sock.sendall(value.to_bytes(8, "little", signed=True))
+        self.send(b"\x33")
+        self.send(size.to_bytes(1, "little"))
+        self.send(adr.to_bytes(8, "little"))
+        self.send(value.to_bytes(8, "little", signed=True))
 
     def store(self, adr, value, size):
-        self.sock.sendall(b"\x44")
-        self.sock.sendall(size.to_bytes(1, "little"))
-        self.sock.sendall(adr.to_bytes(8, "little"))
-        self.sock.sendall(value.to_bytes(8, "little", signed=True))
+        self.send(b"\x44")
+        self.send(size.to_bytes(1, "little"))
+        self.send(adr.to_bytes(8, "little"))
+        self.send(value.to_bytes(8, "little", signed=True))
 
     def syscall(self, data: bytes):
-        self.sock.sendall(b"\x99")
-        self.sock.sendall(len(data).to_bytes(8, "little"))
-        self.sock.sendall(data)
+        self.send(b"\x99")
+        self.send(len(data).to_bytes(8, "little"))
+        self.send(data)
+
+    def syscall_write(self, adr: int, data: bytes):
+        self.send(b"\x9b")
+        self.send(adr.to_bytes(8, "little"))
+        self.send(len(data).to_bytes(8, "little"))
+        self.send(data)
 
 
 class Rebg:
@@ -154,6 +237,11 @@ class Rebg:
         self.ql = ql
         self.arch = Arch(ql.arch.type)
 
+        # handler address -> signal, from rt_sigaction
+        self.handlers = {}
+        # the step before this one, to tell a call from a delivery
+        self.prev = None
+
         # register & setup
         binary_offsets = [
             (start, end)
@@ -192,6 +280,12 @@ class Rebg:
             ("openat", self.sys_openat),
             ("read", self.sys_read),
             ("write", self.sys_write),
+            ("clone", self.sys_clone),
+            ("fork", self.sys_fork),
+            ("vfork", self.sys_vfork),
+            ("execve", self.sys_execve),
+            ("rt_sigaction", self.sys_rt_sigaction),
+            ("rt_sigreturn", self.sys_rt_sigreturn),
         ]:
             self.ql.os.set_syscall(name, func, QL_INTERCEPT.EXIT)
 
@@ -214,6 +308,44 @@ class Rebg:
 
         self.ser.registers(flags, pc, regs)
 
+        bases = [ql.arch.regs.read(r) for r in self.arch.bases_regs()]
+        self.ser.bases(bases)
+
+        if ql.multithread:
+            self.ser.thread(ql.os.thread_management.cur_thread.id)
+
+        # qiling forks the whole emulator, so the child has its own pid
+        self.ser.process(os.getpid())
+
+        signo = self.handlers.get(address)
+        if signo is not None and not self.called(ql):
+            self.ser.signal(signo)
+
+        self.prev = (address, size)
+
+    def called(self, ql: Qiling) -> bool:
+        """whether we got here through a call from the previous step"""
+        if self.prev is None:
+            return False
+        address, size = self.prev
+
+        if self.arch == Arch.ARM64:
+            ret = ql.arch.regs.read("lr")
+        else:
+            ret = ql.stack_read(0)
+        return ret == address + size
+
+    def finish(self):
+        self.ser.flush()
+
+    def killed(self, err: UcError):
+        """a lone signal after the last step tells the analyzer how it died"""
+        signo = FATAL_ERRORS.get(err.errno)
+        if signo is None:
+            return
+        self.ser.separator()
+        self.ser.signal(signo)
+
     def mem_read(self, ql, access, adr, size, value):
         assert size in [0x1, 0x2, 0x4, 0x8]
         self.ser.load(adr, value, size)
@@ -232,10 +364,14 @@ class Rebg:
         path = ql.mem.string(path)
         syscall = f'new_fstatat(0x{fd:x}, "{path}", 0x{buf:x}, 0x{flags:x}) = 0x{ret:x}'
         self.ser.syscall(syscall.encode())
+        if ret == 0:
+            self.ser.syscall_write(buf, ql.mem.read(buf, self.arch.stat_size()))
 
     def sys_read(self, ql, fd, buf, count, ret):
         syscall = f"read(0x{fd:x}, 0x{buf:x}, 0x{count:x}) = 0x{ret:x}"
         self.ser.syscall(syscall.encode())
+        if 0 < ret <= count:
+            self.ser.syscall_write(buf, ql.mem.read(buf, ret))
 
     def sys_write(self, ql, fd, buf, count, ret):
         syscall = f"write(0x{fd:x}, 0x{buf:x}, 0x{count:x}) = 0x{ret:x}"
@@ -248,6 +384,8 @@ class Rebg:
     def sys_exit_group(self, ql, status, ret):
         syscall = f"exit_group({status}) = {ret}"
         self.ser.syscall(syscall.encode())
+        # a forked child doesn't get to `finish`
+        self.ser.flush()
 
     def sys_close(self, ql, fd, ret):
         syscall = f"close({fd}) = {ret}"
@@ -260,7 +398,49 @@ class Rebg:
     def sys_uname(self, ql, buf, ret):
         syscall = f"uname(0x{buf:x}) = 0x{ret:x}"
         self.ser.syscall(syscall.encode())
+        if ret == 0:
+            self.ser.syscall_write(buf, ql.mem.read(buf, UTSNAME_SIZE))
 
     def sys_mprotect(self, ql, addr, len, prot, ret):
         syscall = f"mprotect(0x{addr:x}, 0x{len:x}, 0x{prot:x}) = 0x{ret:x}"
         self.ser.syscall(syscall.encode())
+
+    def sys_clone(self, ql, flags, stack, parent_tid, tls, child_tid, ret):
+        names = [name for bit, name in CLONE_FLAGS.items() if flags & bit]
+        rest = flags & ~sum(CLONE_FLAGS)
+        if rest or not names:
+            names.append(f"0x{rest:x}")
+        syscall = f"clone({'|'.join(names)}, 0x{stack:x}) = {ret}"
+        self.ser.syscall(syscall.encode())
+        if ret == 0 and not flags & 0x10000:  # CLONE_THREAD
+            self.ser.forked()
+
+    def sys_fork(self, ql, ret):
+        syscall = f"fork() = {ret}"
+        self.ser.syscall(syscall.encode())
+        if ret == 0:
+            self.ser.forked()
+
+    def sys_vfork(self, ql, ret):
+        syscall = f"vfork() = {ret}"
+        self.ser.syscall(syscall.encode())
+        if ret == 0:
+            self.ser.forked()
+
+    def sys_rt_sigaction(self, ql, signum, act, oldact, sigsetsize, ret):
+        syscall = f"rt_sigaction({signum}, 0x{act:x}, 0x{oldact:x}, 0x{sigsetsize:x}) = {ret}"
+        self.ser.syscall(syscall.encode())
+        if act and ret == 0:
+            # sa_handler comes first, SIG_DFL and SIG_IGN are never run
+            handler = ql.mem.read_ptr(act)
+            if handler > 1:
+                self.handlers[handler] = signum
+
+    def sys_rt_sigreturn(self, ql, ret):
+        syscall = "rt_sigreturn(0x0)"
+        self.ser.syscall(syscall.encode())
+
+    def sys_execve(self, ql, path, argv, envp, ret):
+        path = ql.mem.string(path)
+        syscall = f'execve("{path}", 0x{argv:x}, 0x{envp:x}) = {ret}'
+        self.ser.syscall(syscall.encode())
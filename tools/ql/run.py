@@ -3,6 +3,7 @@
 from qiling import Qiling
 from qiling.extensions import pipe
 from qiling.os.mapper import QlFsMappedObject
+from unicorn import UcError
 import rebg
 
 
@@ -50,7 +51,13 @@ def run(rootfs, argv):
 
     try_patch_isa(ql)
     rb.enable()
-    ql.run()
+    try:
+        ql.run()
+    except UcError as e:
+        rb.killed(e)
+        raise
+    finally:
+        rb.finish()
 
 
 if __name__ == "__main__":
@@ -6,7 +6,9 @@ export const connectedStore = writable(false);
 export const registerStore = writable(null);
 export const memOpsStore = writable(null);
 export const memoryStore = writable(null);
+export const regionStore = writable(null);
 export const straceStore = writable(null);
+export const abiStore = writable(null);
 export const stepStore = writable(null, () => {
     const socket = new WebSocket("ws://localhost:9001");
 
@@ -32,6 +34,12 @@ export const stepStore = writable(null, () => {
         if (msgs.hasOwnProperty("memory")) {
             memoryStore.set(msgs.memory);
         }
+        if (msgs.hasOwnProperty("regions")) {
+            regionStore.set(msgs.regions);
+        }
+        if (msgs.hasOwnProperty("abi")) {
+            abiStore.set(msgs.abi);
+        }
     });
 
 